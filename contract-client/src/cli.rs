@@ -1,6 +1,8 @@
+use std::{num::NonZeroUsize, time::Duration};
+
 use clap::{Args, ValueEnum};
 
-use crate::Address;
+use crate::{transport::ReconnectConfig, Address};
 
 #[derive(Args)]
 pub struct RpcArgs {
@@ -14,16 +16,53 @@ pub struct RpcArgs {
     #[arg(
         long,
         env,
-        help = "Layer 1 blockchain RPC URL. If not provided, rpc_url is assumed to be L1"
+        help = "Layer 1 blockchain RPC URL. If not provided, rpc_url is assumed to be L1 (checked by chain ID)"
     )]
     pub l1_rpc_url: Option<String>,
     #[command(flatten)]
     contract_addrs: ContractAddrs,
     #[arg(long, env, help = "Network to connect to (mainnet or testnet)")]
     pub network: Network,
+    #[arg(
+        long,
+        env,
+        help = "Maximum number of calls in a single multicall. If not provided, all calls are sent at once"
+    )]
+    pub multicall_batch_size: Option<NonZeroUsize>,
+    #[arg(
+        long,
+        env,
+        help = "Number of attempts to re-establish a dropped websocket RPC connection",
+        default_value_t = 5
+    )]
+    pub ws_reconnect_attempts: usize,
+    #[arg(
+        long,
+        env,
+        help = "Delay before reconnecting websocket RPC connection (in seconds). Grows linearly with attempts",
+        default_value_t = 1
+    )]
+    pub ws_reconnect_backoff_sec: u64,
+    #[arg(
+        long,
+        env,
+        help = "Interval of re-reading the default allocation strategy address (in seconds). If not provided, it's read once on startup"
+    )]
+    pub strategy_refresh_interval_sec: Option<u64>,
 }
 
 impl RpcArgs {
+    pub fn ws_reconnect_config(&self) -> ReconnectConfig {
+        ReconnectConfig {
+            attempts: self.ws_reconnect_attempts,
+            backoff: Duration::from_secs(self.ws_reconnect_backoff_sec),
+        }
+    }
+
+    pub fn strategy_refresh_interval(&self) -> Option<Duration> {
+        self.strategy_refresh_interval_sec.map(Duration::from_secs)
+    }
+
     pub fn gateway_registry_addr(&self) -> Address {
         self.contract_addrs
             .gateway_registry_contract_addr
@@ -69,7 +108,14 @@ pub struct ContractAddrs {
     pub multicall_contract_addr: Option<Address>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// Chain layer whose block numbers are used for epochs by the network controller contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockLayer {
+    L1,
+    L2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
 #[clap(rename_all = "kebab_case")]
 pub enum Network {
     Tethys,
@@ -105,6 +151,22 @@ impl Network {
         }
     }
 
+    /// Both networks run on Arbitrum, where `block.number` in contracts refers to L1 blocks
+    pub fn epoch_block_layer(&self) -> BlockLayer {
+        match self {
+            Network::Tethys => BlockLayer::L1,
+            Network::Mainnet => BlockLayer::L1,
+        }
+    }
+
+    /// Chain ID of the layer 1 chain the network settles on
+    pub fn l1_chain_id(&self) -> u64 {
+        match self {
+            Network::Tethys => 11155111, // Sepolia
+            Network::Mainnet => 1,       // Ethereum
+        }
+    }
+
     pub fn multicall_default_addr(&self) -> Address {
         match self {
             Network::Tethys => "0xcA11bde05977b3631167028862bE2a173976CA11".parse().unwrap(),
@@ -1,14 +1,15 @@
 use std::{
     collections::{HashMap, HashSet},
     iter::zip,
+    num::NonZeroUsize,
     pin::Pin,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use async_trait::async_trait;
-use ethers::prelude::{BlockId, Bytes, Middleware, Multicall, Provider};
-use libp2p::futures::Stream;
+use ethers::prelude::{BlockNumber, Bytes, Middleware, Multicall, Provider, U64};
+use libp2p::futures::{stream, Stream, TryStreamExt};
 use tokio_stream::{wrappers::IntervalStream, StreamExt};
 
 use crate::{
@@ -16,11 +17,36 @@ use crate::{
     contracts::{
         AllocationsViewer, GatewayRegistry, NetworkController, Strategy, WorkerRegistration,
     },
+    metrics::{record_request, CallTimer},
     transport::Transport,
-    Address, ClientError, PeerId, RpcArgs, U256,
+    Address, BlockLayer, ClientError, Clock, Network, PeerId, RpcArgs, SystemClock, TokenAmount,
+    U256,
 };
 
 const GATEWAYS_PAGE_SIZE: U256 = U256([10000, 0, 0, 0]);
+const WORKERS_PAGE_SIZE: usize = 500;
+/// Number of most recent blocks used to measure the average block time
+const BLOCK_TIME_SAMPLE_SIZE: u64 = 100;
+
+/// Block numbers defining the current epoch, in the layer given by `Network::epoch_block_layer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochBlocks {
+    pub current_epoch_start_block: u64,
+    pub next_epoch_start_block: u64,
+    pub epoch_length: u64,
+}
+
+/// Time when the current epoch started. If the epoch start block couldn't be retrieved
+/// (e.g. it has been pruned by a non-archive node), the timestamp is extrapolated
+/// from the latest block and the average block time, and `estimated` is set.
+/// `epoch_length` (in blocks) is read fresh on every call, so that consumers caching epoch
+/// timing can detect its change and invalidate the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochStart {
+    pub timestamp: SystemTime,
+    pub estimated: bool,
+    pub epoch_length: u64,
+}
 
 #[derive(Debug, Clone)]
 pub struct Allocation {
@@ -36,6 +62,19 @@ pub struct GatewayCluster {
     pub allocated_computation_units: U256,
 }
 
+/// Status of a worker derived from its on-chain registration.
+///
+/// The `WorkerRegistration` contract doesn't expose jail status, so workers are never
+/// reported as `Jailed` at the moment – jailing is only visible in the scheduler's pongs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum WorkerStatus {
+    #[default]
+    Active,
+    Jailed,
+    /// Worker has requested deregistration and will leave the active set at `deregistered_at`
+    Deregistering,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Worker {
     pub peer_id: PeerId,
@@ -44,12 +83,24 @@ pub struct Worker {
     pub bond: U256,
     pub registered_at: u128,
     pub deregistered_at: Option<u128>,
+    pub status: WorkerStatus,
+    /// Operator-provided metadata (e.g. location, contact). The `WorkerRegistration` version
+    /// this client is built against doesn't store metadata, so it's always `None` for now.
+    pub metadata: Option<String>,
 }
 
 impl Worker {
+    pub fn bond_amount(&self) -> TokenAmount {
+        TokenAmount::from_raw(self.bond)
+    }
+
     fn new(worker: contracts::Worker, onchain_id: U256) -> Result<Self, ClientError> {
         let peer_id = PeerId::from_bytes(&worker.peer_id)?;
         let deregistered_at = (worker.deregistered_at > 0).then_some(worker.deregistered_at);
+        let status = match deregistered_at {
+            Some(_) => WorkerStatus::Deregistering,
+            None => WorkerStatus::Active,
+        };
         Ok(Self {
             peer_id,
             onchain_id,
@@ -57,6 +108,8 @@ impl Worker {
             bond: worker.bond,
             registered_at: worker.registered_at,
             deregistered_at,
+            status,
+            metadata: None,
         })
     }
 }
@@ -64,6 +117,8 @@ impl Worker {
 pub type NodeStream =
     Pin<Box<dyn Stream<Item = Result<HashSet<PeerId>, ClientError>> + Send + 'static>>;
 
+pub type WorkerStream = Pin<Box<dyn Stream<Item = Result<Worker, ClientError>> + Send + 'static>>;
+
 #[async_trait]
 pub trait Client: Send + Sync + 'static {
     /// Using regular clone is not possible for trait objects
@@ -73,59 +128,184 @@ pub trait Client: Send + Sync + 'static {
     async fn current_epoch(&self) -> Result<u32, ClientError>;
 
     /// Get the time when the current epoch started
-    async fn current_epoch_start(&self) -> Result<SystemTime, ClientError>;
+    async fn current_epoch_start(&self) -> Result<EpochStart, ClientError>;
+
+    /// Get the time elapsed since the current epoch started, according to the client's clock
+    async fn time_since_epoch_start(&self) -> Result<Duration, ClientError> {
+        let epoch_start = self.current_epoch_start().await?;
+        Ok(self.now().duration_since(epoch_start.timestamp).unwrap_or_default())
+    }
+
+    /// Current time used for calculations relative to on-chain data
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    /// Get the block numbers defining the current epoch
+    async fn epoch_blocks(&self) -> Result<EpochBlocks, ClientError>;
 
     /// Get the on-chain ID for the worker
     async fn worker_id(&self, peer_id: PeerId) -> Result<U256, ClientError>;
 
+    /// Get the last epoch in which the worker submitted a liveness proof on chain.
+    /// Returns `None` if the activity is unknown.
+    async fn worker_last_active_epoch(&self, id: U256) -> Result<Option<u32>, ClientError>;
+
     /// Get current active worker set
     async fn active_workers(&self) -> Result<Vec<Worker>, ClientError>;
 
+    /// Get a stream of current active workers. Workers are read from chain page by page,
+    /// so the whole set doesn't need to be kept in memory.
+    fn active_workers_stream(self: Box<Self>) -> WorkerStream;
+
+    /// Get the number of currently active workers without fetching them. It's read separately
+    /// from the worker list, so it may briefly disagree with `active_workers` while workers
+    /// are being registered or deregistered.
+    async fn active_worker_count(&self) -> Result<u64, ClientError>;
+
     /// Check if gateway (client) is registered on chain
     async fn is_gateway_registered(&self, peer_id: PeerId) -> Result<bool, ClientError>;
 
+    /// Check if each of the gateways is registered on chain. Results are in the input order.
+    async fn gateways_registered(&self, peer_ids: &[PeerId]) -> Result<Vec<bool>, ClientError> {
+        let mut result = Vec::with_capacity(peer_ids.len());
+        for peer_id in peer_ids {
+            result.push(self.is_gateway_registered(*peer_id).await?);
+        }
+        Ok(result)
+    }
+
     /// Get current active gateways
     async fn active_gateways(&self) -> Result<Vec<PeerId>, ClientError>;
 
+    /// Get all gateways registered by the operator
+    async fn gateways_by_operator(&self, operator: Address) -> Result<Vec<PeerId>, ClientError>;
+
+    /// Get the amount staked by the gateway's operator. Zero if the gateway is not registered.
+    async fn gateway_stake(&self, gateway_id: PeerId) -> Result<U256, ClientError>;
+
     /// Get client's allocations for the current epoch.
+    ///
+    /// Returns one entry per requested worker (all active workers if `None`). Workers the
+    /// client's strategy doesn't allocate to are returned with zero computation units.
     async fn current_allocations(
         &self,
         client_id: PeerId,
         worker_ids: Option<Vec<Worker>>,
     ) -> Result<Vec<Allocation>, ClientError>;
 
+    /// Get the address of the default allocation strategy
+    async fn default_strategy(&self) -> Result<Address, ClientError>;
+
+    /// Re-read the default strategy address from chain instead of waiting for the refresh
+    /// interval. No-op for clients that don't cache it.
+    async fn refresh_strategy(&self) -> Result<(), ClientError> {
+        Ok(())
+    }
+
     /// Get the current list of all gateway clusters with their allocated CUs
     async fn gateway_clusters(&self, worker_id: U256) -> Result<Vec<GatewayCluster>, ClientError>;
 
+    /// Get peer IDs of all active network participants (workers & gateways)
+    async fn network_nodes(&self) -> Result<HashSet<PeerId>, ClientError> {
+        let gateways = self.active_gateways().await?;
+        let workers = self.active_workers().await?;
+        let mut nodes = HashSet::from_iter(gateways);
+        nodes.extend(workers.into_iter().map(|w| w.peer_id));
+        Ok(nodes)
+    }
+
     /// Get a stream of peer IDs of all active network participants (workers & gateways)
     /// Updated on the given interval
     fn network_nodes_stream(self: Box<Self>, interval: Duration) -> NodeStream {
         Box::pin(IntervalStream::new(tokio::time::interval(interval)).then(move |_| {
             let client = self.clone_client();
-            async move {
-                let gateways = client.active_gateways().await?;
-                let workers = client.active_workers().await?;
-                let mut nodes = HashSet::from_iter(gateways);
-                nodes.extend(workers.into_iter().map(|w| w.peer_id));
-                Ok(nodes)
-            }
+            async move { client.network_nodes().await }
         }))
     }
 }
 
 pub async fn get_client(rpc_args: &RpcArgs) -> Result<Box<dyn Client>, ClientError> {
-    let l2_client = Transport::connect(&rpc_args.rpc_url).await?;
+    get_client_with_clock(rpc_args, Arc::new(SystemClock)).await
+}
+
+/// Like `get_client`, but the client reads the current time from the given clock
+pub async fn get_client_with_clock(
+    rpc_args: &RpcArgs,
+    clock: Arc<dyn Clock>,
+) -> Result<Box<dyn Client>, ClientError> {
+    let reconnect_config = rpc_args.ws_reconnect_config();
+    let l2_client = Transport::connect(&rpc_args.rpc_url, &reconnect_config).await?;
     let l1_client = match &rpc_args.l1_rpc_url {
-        Some(rpc_url) => Transport::connect(rpc_url).await?,
+        Some(rpc_url) => Transport::connect(rpc_url, &reconnect_config).await?,
         None => {
             log::warn!("Layer 1 RPC URL not provided. Assuming the main RPC URL is L1");
+            let chain_id = l2_client.get_chainid().await?.as_u64();
+            check_single_endpoint(rpc_args.network, chain_id)?;
             l2_client.clone()
         }
     };
-    let client: Box<dyn Client> = EthersClient::new(l1_client, l2_client, rpc_args).await?;
+    let client: Box<dyn Client> = EthersClient::new(l1_client, l2_client, rpc_args, clock).await?;
     Ok(client)
 }
 
+/// A single RPC endpoint is fine if epochs are counted in its own blocks, i.e. for networks
+/// with L2 epoch blocks, or if the endpoint is actually on L1. Otherwise, epoch start times
+/// would be read from the wrong chain.
+fn check_single_endpoint(network: Network, chain_id: u64) -> Result<(), ClientError> {
+    let expected = network.l1_chain_id();
+    match network.epoch_block_layer() {
+        BlockLayer::L1 if chain_id != expected => {
+            Err(ClientError::L1RpcRequired { chain_id, expected })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Give up retrying after this time
+    pub timeout: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Like `get_client`, but retries with exponential backoff if the RPC is not reachable yet
+pub async fn get_client_with_retry(
+    rpc_args: &RpcArgs,
+    retry_config: RetryConfig,
+) -> Result<Box<dyn Client>, ClientError> {
+    let deadline = tokio::time::Instant::now() + retry_config.timeout;
+    let mut backoff = retry_config.initial_backoff;
+    loop {
+        match get_client(rpc_args).await {
+            Ok(client) => return Ok(client),
+            // Invalid configuration won't be fixed by retrying
+            Err(
+                e @ (ClientError::InvalidRpcUrl(_)
+                | ClientError::InvalidProtocol
+                | ClientError::L1RpcRequired { .. }),
+            ) => return Err(e),
+            Err(e) if tokio::time::Instant::now() + backoff < deadline => {
+                log::warn!("Cannot create contract client: {e}. Retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(retry_config.max_backoff);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 #[derive(Clone)]
 struct EthersClient {
     l1_client: Arc<Provider<Transport>>,
@@ -134,8 +314,13 @@ struct EthersClient {
     network_controller: NetworkController<Provider<Transport>>,
     worker_registration: WorkerRegistration<Provider<Transport>>,
     allocations_viewer: AllocationsViewer<Provider<Transport>>,
-    default_strategy_addr: Address,
+    /// Default strategy address and the time it was read
+    default_strategy: Arc<Mutex<(Address, Instant)>>,
+    strategy_refresh_interval: Option<Duration>,
     multicall_contract_addr: Option<Address>,
+    multicall_batch_size: Option<NonZeroUsize>,
+    epoch_block_layer: BlockLayer,
+    clock: Arc<dyn Clock>,
 }
 
 impl EthersClient {
@@ -143,6 +328,7 @@ impl EthersClient {
         l1_client: Arc<Provider<Transport>>,
         l2_client: Arc<Provider<Transport>>,
         rpc_args: &RpcArgs,
+        clock: Arc<dyn Clock>,
     ) -> Result<Box<Self>, ClientError> {
         let gateway_registry =
             GatewayRegistry::get(l2_client.clone(), rpc_args.gateway_registry_addr());
@@ -160,14 +346,68 @@ impl EthersClient {
             worker_registration,
             network_controller,
             allocations_viewer,
-            default_strategy_addr,
+            default_strategy: Arc::new(Mutex::new((default_strategy_addr, Instant::now()))),
+            strategy_refresh_interval: rpc_args.strategy_refresh_interval(),
             multicall_contract_addr: Some(rpc_args.multicall_addr()),
+            multicall_batch_size: rpc_args.multicall_batch_size,
+            epoch_block_layer: rpc_args.network.epoch_block_layer(),
+            clock,
         }))
     }
 
+    /// Re-read the default strategy address from the gateway registry
+    async fn read_default_strategy(&self) -> Result<Address, ClientError> {
+        let addr = self.gateway_registry.default_strategy().call().await?;
+        *self.default_strategy.lock().expect("lock poisoned") = (addr, Instant::now());
+        Ok(addr)
+    }
+
+    /// Default strategy address, re-read if older than the refresh interval
+    async fn default_strategy_addr(&self) -> Result<Address, ClientError> {
+        let (addr, read_at) = *self.default_strategy.lock().expect("lock poisoned");
+        match self.strategy_refresh_interval {
+            Some(interval) if read_at.elapsed() >= interval => self.read_default_strategy().await,
+            _ => Ok(addr),
+        }
+    }
+
     async fn multicall(&self) -> Result<Multicall<Provider<Transport>>, ClientError> {
         Ok(contracts::multicall(self.l2_client.clone(), self.multicall_contract_addr).await?)
     }
+
+    /// Provider of the chain layer that epoch block numbers refer to
+    fn epoch_block_client(&self) -> &Provider<Transport> {
+        match self.epoch_block_layer {
+            BlockLayer::L1 => &self.l1_client,
+            BlockLayer::L2 => &self.l2_client,
+        }
+    }
+
+    async fn workers_page(&self, ids: Vec<U256>, block: U64) -> Result<Vec<Worker>, ClientError> {
+        let multicall = self.multicall().await?.block(block);
+        let calls = ids
+            .iter()
+            .map(|id| self.worker_registration.method("workers", *id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let workers: Vec<contracts::Worker> = contracts::multicall_batched(
+            multicall,
+            calls,
+            self.multicall_batch_size,
+            "active_workers",
+        )
+        .await?;
+        Ok(workers
+            .into_iter()
+            .zip(ids)
+            .filter_map(|(worker, onchain_id)| match Worker::new(worker, onchain_id) {
+                Ok(worker) => Some(worker),
+                Err(e) => {
+                    log::debug!("Error reading worker from chain: {e:?}");
+                    None
+                }
+            })
+            .collect())
+    }
 }
 
 #[async_trait]
@@ -177,6 +417,7 @@ impl Client for EthersClient {
     }
 
     async fn current_epoch(&self) -> Result<u32, ClientError> {
+        let _timer = CallTimer::new("current_epoch");
         let epoch = self
             .network_controller
             .epoch_number()
@@ -187,62 +428,161 @@ impl Client for EthersClient {
         Ok(epoch)
     }
 
-    async fn current_epoch_start(&self) -> Result<SystemTime, ClientError> {
+    async fn current_epoch_start(&self) -> Result<EpochStart, ClientError> {
+        let _timer = CallTimer::new("current_epoch_start");
+        let EpochBlocks {
+            current_epoch_start_block: block_num,
+            epoch_length,
+            ..
+        } = self.epoch_blocks().await?;
+        let client = self.epoch_block_client();
+        if let Some(block) = client.get_block(block_num).await? {
+            return Ok(EpochStart {
+                timestamp: UNIX_EPOCH + Duration::from_secs(block.timestamp.as_u64()),
+                estimated: false,
+                epoch_length,
+            });
+        }
+
+        log::warn!("Epoch start block {block_num} not found. Estimating its timestamp.");
+        let latest =
+            client.get_block(BlockNumber::Latest).await?.ok_or(ClientError::BlockNotFound)?;
+        let latest_num = latest.number.ok_or(ClientError::BlockNotFound)?.as_u64();
+        let sample_num = latest_num.saturating_sub(BLOCK_TIME_SAMPLE_SIZE);
+        let sample = client.get_block(sample_num).await?.ok_or(ClientError::BlockNotFound)?;
+        let latest_time = Duration::from_secs(latest.timestamp.as_u64());
+        let sample_time = Duration::from_secs(sample.timestamp.as_u64());
+        let avg_block_time = latest_time
+            .saturating_sub(sample_time)
+            .checked_div((latest_num - sample_num) as u32)
+            .unwrap_or_default();
+        let blocks_since_start = latest_num.saturating_sub(block_num);
+        let elapsed = avg_block_time.mul_f64(blocks_since_start as f64);
+        log::debug!("Average block time: {avg_block_time:?}, blocks since epoch start: {blocks_since_start}");
+        Ok(EpochStart {
+            timestamp: UNIX_EPOCH + latest_time.saturating_sub(elapsed),
+            estimated: true,
+            epoch_length,
+        })
+    }
+
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    async fn epoch_blocks(&self) -> Result<EpochBlocks, ClientError> {
+        let _timer = CallTimer::new("epoch_blocks");
         let next_epoch_start_block = self.network_controller.next_epoch().call().await?;
         let epoch_length_blocks = self.network_controller.epoch_length().call().await?;
-        let block_num: u64 = (next_epoch_start_block - epoch_length_blocks)
+        let current_epoch_start_block = (next_epoch_start_block - epoch_length_blocks)
             .try_into()
             .expect("Epoch number should not exceed u64 range");
-        log::debug!("Current epoch: {block_num} Epoch length: {epoch_length_blocks} Next epoch: {next_epoch_start_block}");
-        // Blocks returned by `next_epoch()` and `epoch_length()` are **L1 blocks**
-        let block = self
-            .l1_client
-            .get_block(BlockId::Number(block_num.into()))
-            .await?
-            .ok_or(ClientError::BlockNotFound)?;
-        Ok(UNIX_EPOCH + Duration::from_secs(block.timestamp.as_u64()))
+        log::debug!("Current epoch: {current_epoch_start_block} Epoch length: {epoch_length_blocks} Next epoch: {next_epoch_start_block}");
+        Ok(EpochBlocks {
+            current_epoch_start_block,
+            next_epoch_start_block: next_epoch_start_block
+                .try_into()
+                .expect("Epoch number should not exceed u64 range"),
+            epoch_length: epoch_length_blocks
+                .try_into()
+                .expect("Epoch length should not exceed u64 range"),
+        })
     }
 
     async fn worker_id(&self, peer_id: PeerId) -> Result<U256, ClientError> {
+        let _timer = CallTimer::new("worker_id");
         let peer_id = peer_id.to_bytes().into();
         let id: U256 = self.worker_registration.worker_ids(peer_id).call().await?;
         Ok(id)
     }
 
+    async fn worker_last_active_epoch(&self, _id: U256) -> Result<Option<u32>, ClientError> {
+        // The contracts don't track worker liveness proofs, so the activity is never known
+        Ok(None)
+    }
+
     async fn active_workers(&self) -> Result<Vec<Worker>, ClientError> {
-        let workers_call = self.worker_registration.method("getActiveWorkers", ())?;
-        let onchain_ids_call = self.worker_registration.method("getActiveWorkerIds", ())?;
-        let mut multicall = self.multicall().await?;
-        multicall
-            .add_call::<Vec<contracts::Worker>>(workers_call, false)
-            .add_call::<Vec<U256>>(onchain_ids_call, false);
-        let (workers, onchain_ids): (Vec<contracts::Worker>, Vec<U256>) = multicall.call().await?;
-
-        let workers = workers
-            .into_iter()
-            .zip(onchain_ids)
-            .filter_map(|(worker, onchain_id)| match Worker::new(worker, onchain_id) {
-                Ok(worker) => Some(worker),
-                Err(e) => {
-                    log::debug!("Error reading worker from chain: {e:?}");
-                    None
-                }
+        let _timer = CallTimer::new("active_workers");
+        Box::new(self.clone()).active_workers_stream().try_collect().await
+    }
+
+    async fn active_worker_count(&self) -> Result<u64, ClientError> {
+        let _timer = CallTimer::new("active_worker_count");
+        let count = self
+            .worker_registration
+            .get_active_worker_count()
+            .call()
+            .await?
+            .try_into()
+            .expect("Worker count should not exceed u64 range");
+        Ok(count)
+    }
+
+    fn active_workers_stream(self: Box<Self>) -> WorkerStream {
+        let onchain_ids = async move {
+            // Read all pages at the same block to get a consistent snapshot
+            let latest_block = self.l2_client.get_block_number().await?;
+            let onchain_ids = self
+                .worker_registration
+                .get_active_worker_ids()
+                .block(latest_block)
+                .call()
+                .await?;
+            let pages: Vec<Vec<U256>> =
+                onchain_ids.chunks(WORKERS_PAGE_SIZE).map(|ids| ids.to_vec()).collect();
+            Ok::<_, ClientError>((self, latest_block, pages))
+        };
+        let workers = stream::once(onchain_ids)
+            .map_ok(|(client, block, pages)| {
+                stream::iter(pages)
+                    .then(move |ids| {
+                        let client = client.clone();
+                        async move { client.workers_page(ids, block).await }
+                    })
+                    .map_ok(|workers| stream::iter(workers.into_iter().map(Ok)))
+                    .try_flatten()
             })
-            .collect();
-        Ok(workers)
+            .try_flatten();
+        Box::pin(workers)
     }
 
     async fn is_gateway_registered(&self, peer_id: PeerId) -> Result<bool, ClientError> {
+        let _timer = CallTimer::new("is_gateway_registered");
         let gateway_id = peer_id.to_bytes().into();
         let gateway_info: contracts::Gateway =
             self.gateway_registry.get_gateway(gateway_id).call().await?;
         Ok(gateway_info.operator != Address::zero())
     }
 
+    async fn gateways_registered(&self, peer_ids: &[PeerId]) -> Result<Vec<bool>, ClientError> {
+        let _timer = CallTimer::new("gateways_registered");
+        let multicall = self.multicall().await?;
+        let calls = peer_ids
+            .iter()
+            .map(|peer_id| {
+                let gateway_id: Bytes = peer_id.to_bytes().into();
+                self.gateway_registry.method("getGateway", gateway_id)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let gateways: Vec<contracts::Gateway> = contracts::multicall_batched(
+            multicall,
+            calls,
+            self.multicall_batch_size,
+            "gateways_registered",
+        )
+        .await?;
+        Ok(gateways
+            .into_iter()
+            .map(|gateway| gateway.operator != Address::zero())
+            .collect())
+    }
+
     async fn active_gateways(&self) -> Result<Vec<PeerId>, ClientError> {
+        let _timer = CallTimer::new("active_gateways");
         let latest_block = self.l2_client.get_block_number().await?;
         let mut active_gateways = Vec::new();
         for page in 0.. {
+            record_request("active_gateways");
             let gateway_ids = self
                 .gateway_registry
                 .get_active_gateways(page.into(), GATEWAYS_PAGE_SIZE)
@@ -259,11 +599,29 @@ impl Client for EthersClient {
         Ok(active_gateways)
     }
 
+    async fn gateways_by_operator(&self, operator: Address) -> Result<Vec<PeerId>, ClientError> {
+        let _timer = CallTimer::new("gateways_by_operator");
+        let gateway_ids = self.gateway_registry.get_my_gateways(operator).call().await?;
+        Ok(gateway_ids.iter().filter_map(|id| PeerId::from_bytes(id).ok()).collect())
+    }
+
+    async fn gateway_stake(&self, gateway_id: PeerId) -> Result<U256, ClientError> {
+        let _timer = CallTimer::new("gateway_stake");
+        let gateway_info: contracts::Gateway =
+            self.gateway_registry.get_gateway(gateway_id.to_bytes().into()).call().await?;
+        if gateway_info.operator == Address::zero() {
+            return Ok(U256::zero());
+        }
+        let stake = self.gateway_registry.get_stake(gateway_info.operator).call().await?;
+        Ok(stake.amount)
+    }
+
     async fn current_allocations(
         &self,
         client_id: PeerId,
         workers: Option<Vec<Worker>>,
     ) -> Result<Vec<Allocation>, ClientError> {
+        let _timer = CallTimer::new("current_allocations");
         let workers = match workers {
             Some(workers) => workers,
             None => self.active_workers().await?,
@@ -277,41 +635,35 @@ impl Client for EthersClient {
             self.gateway_registry.get_used_strategy(gateway_id.clone()).call().await?;
         let strategy = Strategy::get(strategy_addr, self.l2_client.clone());
 
-        // A little hack to make less requests: default strategy distributes CUs evenly,
-        // so we can just query for one worker and return the same number for all.
-        if strategy_addr == self.default_strategy_addr {
-            let first_worker_id = workers.first().expect("non empty").onchain_id;
-            let cus_per_epoch =
-                strategy.computation_units_per_epoch(gateway_id, first_worker_id).call().await?;
-            return Ok(workers
-                .into_iter()
-                .map(|w| Allocation {
-                    worker_peer_id: w.peer_id,
-                    worker_onchain_id: w.onchain_id,
-                    computation_units: cus_per_epoch,
-                })
-                .collect());
-        }
+        // Any strategy, the default one included, may leave some workers out,
+        // so each worker's allocation has to be read separately
+        let multicall = self.multicall().await?;
+        let calls: Vec<_> = workers
+            .iter()
+            .map(|w| strategy.computation_units_per_epoch(gateway_id.clone(), w.onchain_id))
+            .collect();
+        let compute_units: Vec<U256> = contracts::multicall_batched(
+            multicall,
+            calls,
+            self.multicall_batch_size,
+            "current_allocations",
+        )
+        .await?;
+        strategy_allocations(workers, compute_units)
+    }
 
-        let mut multicall = self.multicall().await?;
-        for worker in workers.iter() {
-            multicall.add_call::<U256>(
-                strategy
-                    .method("computationUnitsPerEpoch", (gateway_id.clone(), worker.onchain_id))?,
-                false,
-            );
-        }
-        let compute_units: Vec<U256> = multicall.call_array().await?;
-        Ok(zip(workers, compute_units)
-            .map(|(w, cus)| Allocation {
-                worker_peer_id: w.peer_id,
-                worker_onchain_id: w.onchain_id,
-                computation_units: cus,
-            })
-            .collect())
+    async fn default_strategy(&self) -> Result<Address, ClientError> {
+        self.default_strategy_addr().await
+    }
+
+    async fn refresh_strategy(&self) -> Result<(), ClientError> {
+        let _timer = CallTimer::new("refresh_strategy");
+        self.read_default_strategy().await?;
+        Ok(())
     }
 
     async fn gateway_clusters(&self, worker_id: U256) -> Result<Vec<GatewayCluster>, ClientError> {
+        let _timer = CallTimer::new("gateway_clusters");
         let latest_block = self.l2_client.get_block_number().await?;
 
         let mut clusters = HashMap::new();
@@ -347,3 +699,150 @@ impl Client for EthersClient {
         Ok(clusters.into_values().collect())
     }
 }
+
+/// Pair workers with the computation units read from their strategy, in the same order.
+/// Workers the strategy doesn't allocate to are kept with zero computation units.
+fn strategy_allocations(
+    workers: Vec<Worker>,
+    compute_units: Vec<U256>,
+) -> Result<Vec<Allocation>, ClientError> {
+    if compute_units.len() != workers.len() {
+        return Err(ClientError::AllocationsMismatch {
+            expected: workers.len(),
+            got: compute_units.len(),
+        });
+    }
+    Ok(zip(workers, compute_units)
+        .map(|(w, cus)| Allocation {
+            worker_peer_id: w.peer_id,
+            worker_onchain_id: w.onchain_id,
+            computation_units: cus,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockClient, MockData};
+
+    fn worker(peer_id: PeerId) -> Worker {
+        Worker {
+            peer_id,
+            onchain_id: U256::zero(),
+            address: Address::zero(),
+            bond: U256::zero(),
+            registered_at: 0,
+            deregistered_at: None,
+            status: WorkerStatus::Active,
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_network_nodes() {
+        let shared = PeerId::random();
+        let client = MockClient::new(MockData {
+            gateways: vec![PeerId::random(), shared],
+            workers: vec![worker(PeerId::random()), worker(shared)],
+            ..Default::default()
+        });
+
+        let nodes = client.network_nodes().await.unwrap();
+        let mut expected: HashSet<_> =
+            client.active_gateways().await.unwrap().into_iter().collect();
+        expected.extend(client.active_workers().await.unwrap().into_iter().map(|w| w.peer_id));
+        assert_eq!(nodes, expected);
+        assert_eq!(nodes.len(), 3);
+
+        let mut stream = client.clone_client().network_nodes_stream(Duration::from_secs(1));
+        assert_eq!(stream.next().await.unwrap().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_gateways_registered() {
+        let gateway = PeerId::random();
+        let client = MockClient::new(MockData {
+            gateways: vec![gateway],
+            ..Default::default()
+        });
+        let peer_ids = [PeerId::random(), gateway, PeerId::random(), gateway];
+        assert_eq!(
+            client.gateways_registered(&peer_ids).await.unwrap(),
+            vec![false, true, false, true]
+        );
+        assert_eq!(client.gateways_registered(&[]).await.unwrap(), Vec::<bool>::new());
+    }
+
+    #[test]
+    fn test_check_single_endpoint() {
+        assert!(check_single_endpoint(Network::Mainnet, 1).is_ok());
+        assert!(matches!(
+            check_single_endpoint(Network::Mainnet, 42161),
+            Err(ClientError::L1RpcRequired {
+                chain_id: 42161,
+                expected: 1
+            })
+        ));
+        assert!(check_single_endpoint(Network::Tethys, 11155111).is_ok());
+        assert!(check_single_endpoint(Network::Tethys, 421614).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_current_allocations_explicit_zero() {
+        let allocated = worker(PeerId::random());
+        let excluded = worker(PeerId::random());
+        let client = MockClient::new(MockData {
+            workers: vec![allocated.clone(), excluded.clone()],
+            allocations: vec![Allocation {
+                worker_peer_id: allocated.peer_id,
+                worker_onchain_id: allocated.onchain_id,
+                computation_units: 100.into(),
+            }],
+            ..Default::default()
+        });
+
+        let allocations = client.current_allocations(PeerId::random(), None).await.unwrap();
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].worker_peer_id, allocated.peer_id);
+        assert_eq!(allocations[0].computation_units, 100.into());
+        assert_eq!(allocations[1].worker_peer_id, excluded.peer_id);
+        assert_eq!(allocations[1].computation_units, U256::zero());
+
+        let allocations = client
+            .current_allocations(PeerId::random(), Some(vec![excluded.clone()]))
+            .await
+            .unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0].computation_units, U256::zero());
+    }
+
+    #[test]
+    fn test_strategy_allocations() {
+        let workers: Vec<_> = (0..4)
+            .map(|id| Worker {
+                onchain_id: id.into(),
+                ..worker(PeerId::random())
+            })
+            .collect();
+        // A strategy allocating only to workers with even on-chain IDs
+        let compute_units = vec![100.into(), U256::zero(), 100.into(), U256::zero()];
+
+        let allocations = strategy_allocations(workers.clone(), compute_units).unwrap();
+        assert_eq!(allocations.len(), workers.len());
+        for (allocation, worker) in zip(&allocations, &workers) {
+            assert_eq!(allocation.worker_peer_id, worker.peer_id);
+            assert_eq!(allocation.worker_onchain_id, worker.onchain_id);
+        }
+        let cus: Vec<_> = allocations.iter().map(|a| a.computation_units.as_u64()).collect();
+        assert_eq!(cus, vec![100, 0, 100, 0]);
+
+        assert!(matches!(
+            strategy_allocations(workers, vec![100.into()]),
+            Err(ClientError::AllocationsMismatch {
+                expected: 4,
+                got: 1
+            })
+        ));
+    }
+}
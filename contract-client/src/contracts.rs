@@ -1,11 +1,12 @@
 use ethers::{
-    contract::abigen,
+    abi::Tokenizable,
+    contract::{abigen, ContractCall},
     prelude::{Multicall, MulticallError},
     providers::Middleware,
 };
-use std::sync::Arc;
+use std::{num::NonZeroUsize, sync::Arc};
 
-use crate::Address;
+use crate::{metrics::record_multicall_batch, Address};
 
 pub use gateway_registry::Gateway;
 pub use worker_registration::Worker;
@@ -52,3 +53,29 @@ pub async fn multicall<T: Middleware>(
 ) -> Result<Multicall<T>, MulticallError<T>> {
     Multicall::new(client, addr).await
 }
+
+/// Execute `calls` using the given `multicall`, sending at most `batch_size` calls at once.
+/// If `batch_size` is `None`, all the calls are sent in a single batch.
+/// Results are returned in the same order as the calls.
+pub async fn multicall_batched<T: Middleware, D: Tokenizable>(
+    multicall: Multicall<T>,
+    calls: impl IntoIterator<Item = ContractCall<T, D>>,
+    batch_size: Option<NonZeroUsize>,
+    method: &'static str,
+) -> Result<Vec<D>, MulticallError<T>> {
+    let batch_size = batch_size.map(NonZeroUsize::get).unwrap_or(usize::MAX);
+    let mut calls = calls.into_iter().peekable();
+    let mut results = Vec::new();
+    while calls.peek().is_some() {
+        let mut batch = multicall.clone();
+        batch.clear_calls();
+        let mut num_calls = 0;
+        for call in calls.by_ref().take(batch_size) {
+            batch.add_call(call, false);
+            num_calls += 1;
+        }
+        record_multicall_batch(method, num_calls);
+        results.extend(batch.call_array::<D>().await?);
+    }
+    Ok(results)
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use libp2p::futures::{stream, Stream};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::Client;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct EpochState {
+    epoch: Option<u32>,
+    generation: u64,
+}
+
+/// Polls the current epoch in the background and notifies about epoch transitions.
+///
+/// Errors reading the epoch are logged and ignored, and so are epochs lower than the last seen
+/// one (e.g. read from a lagging RPC node), so neither signals a change.
+/// Polling stops when the watcher is dropped.
+pub struct EpochWatcher {
+    state_rx: watch::Receiver<EpochState>,
+    task: JoinHandle<()>,
+}
+
+impl EpochWatcher {
+    pub fn new(client: Box<dyn Client>, poll_interval: Duration) -> Self {
+        let (state_tx, state_rx) = watch::channel(EpochState::default());
+        let task = tokio::spawn(poll_epoch(client, poll_interval, state_tx));
+        Self { state_rx, task }
+    }
+
+    /// Last observed epoch, `None` until the epoch has been read successfully
+    pub fn current(&self) -> Option<u32> {
+        self.state_rx.borrow().epoch
+    }
+
+    /// Number of epoch transitions observed so far, counting the first successful read.
+    /// Can be used as a cache key suffix to invalidate cached data on every new epoch.
+    pub fn generation(&self) -> u64 {
+        self.state_rx.borrow().generation
+    }
+
+    /// Wait for the next epoch transition and return the new epoch.
+    /// Returns `None` if the polling task has stopped.
+    pub async fn changed(&self) -> Option<u32> {
+        let mut state_rx = self.state_rx.clone();
+        state_rx.borrow_and_update();
+        state_rx.changed().await.ok()?;
+        let epoch = state_rx.borrow().epoch;
+        epoch
+    }
+
+    /// Stream of epochs, starting with the current one (once it's known) followed by
+    /// every transition. Polling continues as long as the stream is alive.
+    pub fn into_stream(self) -> impl Stream<Item = u32> + Send + 'static {
+        let mut state_rx = self.state_rx.clone();
+        state_rx.mark_changed();
+        stream::unfold((self, state_rx), |(watcher, mut state_rx)| async move {
+            loop {
+                state_rx.changed().await.ok()?;
+                let epoch = state_rx.borrow_and_update().epoch;
+                if let Some(epoch) = epoch {
+                    return Some((epoch, (watcher, state_rx)));
+                }
+            }
+        })
+    }
+}
+
+impl Drop for EpochWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn poll_epoch(
+    client: Box<dyn Client>,
+    poll_interval: Duration,
+    state_tx: watch::Sender<EpochState>,
+) {
+    let mut interval = tokio::time::interval(poll_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let epoch = match client.current_epoch().await {
+            Ok(epoch) => epoch,
+            Err(e) => {
+                log::warn!("Error reading current epoch: {e:?}");
+                continue;
+            }
+        };
+        state_tx.send_if_modified(|state| match state.epoch {
+            Some(last) if epoch == last => false,
+            Some(last) if epoch < last => {
+                log::debug!("Ignoring epoch {epoch} lower than the last seen epoch {last}");
+                false
+            }
+            _ => {
+                state.epoch = Some(epoch);
+                state.generation += 1;
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{FaultConfig, MockClient, MockData};
+
+    use super::*;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    #[tokio::test]
+    async fn test_epoch_watcher() {
+        let client = MockClient::new(MockData {
+            epoch: 1,
+            ..Default::default()
+        });
+        let watcher = EpochWatcher::new(Box::new(client.clone()), POLL_INTERVAL);
+        assert_eq!(watcher.current(), None);
+        assert_eq!(watcher.changed().await, Some(1));
+        assert_eq!(watcher.generation(), 1);
+
+        // Neither errors nor lower epochs are transitions
+        client.set_faults(FaultConfig {
+            failing_methods: HashSet::from(["current_epoch"]),
+            ..Default::default()
+        });
+        let no_change = tokio::time::timeout(POLL_INTERVAL * 5, watcher.changed()).await;
+        assert!(no_change.is_err());
+        client.set_faults(Default::default());
+        client.update_data(|data| data.epoch = 0);
+        let no_change = tokio::time::timeout(POLL_INTERVAL * 5, watcher.changed()).await;
+        assert!(no_change.is_err());
+        assert_eq!((watcher.current(), watcher.generation()), (Some(1), 1));
+
+        client.update_data(|data| data.epoch = 2);
+        assert_eq!(watcher.changed().await, Some(2));
+        assert_eq!(watcher.generation(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_epoch_stream() {
+        use tokio_stream::StreamExt;
+
+        let client = MockClient::new(MockData {
+            epoch: 1,
+            ..Default::default()
+        });
+        let watcher = EpochWatcher::new(Box::new(client.clone()), POLL_INTERVAL);
+        watcher.changed().await;
+        let mut epochs = Box::pin(watcher.into_stream());
+        // The current epoch comes first, even though it was read before the stream was created
+        assert_eq!(epochs.next().await, Some(1));
+
+        client.update_data(|data| data.epoch = 2);
+        assert_eq!(epochs.next().await, Some(2));
+    }
+}
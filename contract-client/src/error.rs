@@ -22,6 +22,10 @@ pub enum ClientError {
     TxReceiptMissing,
     #[error("Block not found")]
     BlockNotFound,
+    #[error("Epochs are counted in L1 blocks, but the RPC endpoint is on chain {chain_id} (expected L1 chain {expected}). Provide an L1 RPC URL")]
+    L1RpcRequired { chain_id: u64, expected: u64 },
+    #[error("Got {got} allocations for {expected} workers")]
+    AllocationsMismatch { expected: usize, got: usize },
 }
 
 impl<M: Middleware> From<ContractError<M>> for ClientError {
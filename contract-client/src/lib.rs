@@ -16,13 +16,32 @@
 
 mod cli;
 mod client;
+mod clock;
 mod contracts;
+mod epoch_watcher;
 mod error;
+mod metrics;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+mod multi_network;
+mod token;
 mod transport;
 
 pub use ethers::types::{Address, U256};
 pub use libp2p::PeerId;
 
-pub use cli::{Network, RpcArgs};
-pub use client::{get_client, Allocation, Client, GatewayCluster, NodeStream, Worker};
+pub use cli::{BlockLayer, Network, RpcArgs};
+pub use client::{
+    get_client, get_client_with_clock, get_client_with_retry, Allocation, Client, EpochBlocks,
+    EpochStart, GatewayCluster, NodeStream, RetryConfig, Worker, WorkerStatus, WorkerStream,
+};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use epoch_watcher::EpochWatcher;
 pub use error::ClientError;
+#[cfg(feature = "metrics")]
+pub use metrics::register_metrics;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{FaultConfig, MockClient, MockData};
+pub use multi_network::MultiNetworkClient;
+pub use token::{TokenAmount, DEFAULT_TOKEN_SYMBOL, TOKEN_DECIMALS};
+pub use transport::ReconnectConfig;
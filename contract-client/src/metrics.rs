@@ -0,0 +1,90 @@
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+use lazy_static::lazy_static;
+#[cfg(feature = "metrics")]
+use prometheus_client::{
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+
+#[cfg(feature = "metrics")]
+type Labels = Vec<(&'static str, &'static str)>;
+
+#[cfg(feature = "metrics")]
+lazy_static! {
+    static ref CALLS: Family<Labels, Counter> = Default::default();
+    static ref CALL_DURATION: Family<Labels, Histogram, fn() -> Histogram> =
+        Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.01, 2.0, 12)));
+    static ref REQUESTS: Family<Labels, Counter> = Default::default();
+    static ref MULTICALL_CALLS: Family<Labels, Counter> = Default::default();
+}
+
+#[cfg(feature = "metrics")]
+const METHOD: &str = "method";
+
+/// Register metrics of RPC calls made by `EthersClient`, labeled with the `Client` method
+#[cfg(feature = "metrics")]
+pub fn register_metrics(registry: &mut Registry) {
+    registry.register("calls", "The number of client method calls", CALLS.clone());
+    registry.register(
+        "call_duration_seconds",
+        "Duration of client method calls",
+        CALL_DURATION.clone(),
+    );
+    registry.register(
+        "requests",
+        "The number of contract call requests sent, including multicall batches and pages",
+        REQUESTS.clone(),
+    );
+    registry.register(
+        "multicall_calls",
+        "The number of contract calls aggregated in multicall requests",
+        MULTICALL_CALLS.clone(),
+    );
+}
+
+/// Records the call and its duration when dropped. No-op without the `metrics` feature.
+pub struct CallTimer {
+    #[allow(dead_code)]
+    method: &'static str,
+    #[allow(dead_code)]
+    start: Instant,
+}
+
+impl CallTimer {
+    pub fn new(method: &'static str) -> Self {
+        Self {
+            method,
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for CallTimer {
+    fn drop(&mut self) {
+        let labels = vec![(METHOD, self.method)];
+        CALLS.get_or_create(&labels).inc();
+        CALL_DURATION.get_or_create(&labels).observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Record a single contract call request, e.g. a page of a paginated read
+#[allow(unused_variables)]
+pub fn record_request(method: &'static str) {
+    #[cfg(feature = "metrics")]
+    REQUESTS.get_or_create(&vec![(METHOD, method)]).inc();
+}
+
+/// Record a multicall request aggregating `calls` contract calls
+#[allow(unused_variables)]
+pub fn record_multicall_batch(method: &'static str, calls: usize) {
+    record_request(method);
+    #[cfg(feature = "metrics")]
+    MULTICALL_CALLS.get_or_create(&vec![(METHOD, method)]).inc_by(calls as u64);
+}
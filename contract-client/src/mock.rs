@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use libp2p::futures::{stream, TryStreamExt};
+
+use crate::{
+    Address, Allocation, Client, ClientError, Clock, EpochBlocks, EpochStart, GatewayCluster,
+    PeerId, Worker, WorkerStream, U256,
+};
+
+/// Canned on-chain data returned by `MockClient`
+#[derive(Debug, Clone)]
+pub struct MockData {
+    pub epoch: u32,
+    pub epoch_start: EpochStart,
+    pub epoch_blocks: EpochBlocks,
+    pub workers: Vec<Worker>,
+    pub gateways: Vec<PeerId>,
+    pub operator_gateways: HashMap<Address, Vec<PeerId>>,
+    /// Last active epochs by worker on-chain ID
+    pub worker_last_active_epochs: HashMap<U256, u32>,
+    /// Operator stakes by gateway ID
+    pub gateway_stakes: HashMap<PeerId, U256>,
+    pub allocations: Vec<Allocation>,
+    pub default_strategy: Address,
+    pub gateway_clusters: Vec<GatewayCluster>,
+}
+
+impl Default for MockData {
+    fn default() -> Self {
+        Self {
+            epoch: 0,
+            epoch_start: EpochStart {
+                timestamp: UNIX_EPOCH,
+                estimated: false,
+                epoch_length: 0,
+            },
+            epoch_blocks: EpochBlocks {
+                current_epoch_start_block: 0,
+                next_epoch_start_block: 0,
+                epoch_length: 0,
+            },
+            workers: vec![],
+            gateways: vec![],
+            operator_gateways: Default::default(),
+            worker_last_active_epochs: Default::default(),
+            gateway_stakes: Default::default(),
+            allocations: vec![],
+            default_strategy: Address::zero(),
+            gateway_clusters: vec![],
+        }
+    }
+}
+
+/// Failures simulated by `MockClient`
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    /// Fail every n-th call (counting calls to all methods)
+    pub fail_every: Option<NonZeroU64>,
+    /// Names of `Client` methods which always fail, e.g. `"active_workers"`
+    pub failing_methods: HashSet<&'static str>,
+    /// Delay added to every call, e.g. to trigger timeouts
+    pub latency: Option<Duration>,
+    /// Return data from the moment stale reads were enabled, ignoring later updates
+    pub stale_reads: bool,
+}
+
+#[derive(Default)]
+struct MockState {
+    data: MockData,
+    stale_data: Option<MockData>,
+    faults: FaultConfig,
+    calls: u64,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+/// `Client` implementation returning canned data, with configurable fault injection.
+/// Clones share the same state, so data and faults can be changed while the client is in use.
+#[derive(Clone, Default)]
+pub struct MockClient {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClient {
+    pub fn new(data: MockData) -> Self {
+        let state = MockState {
+            data,
+            ..Default::default()
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Use the given clock instead of the system time
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.state().clock = Some(clock);
+        self
+    }
+
+    pub fn update_data(&self, f: impl FnOnce(&mut MockData)) {
+        f(&mut self.state().data)
+    }
+
+    pub fn set_faults(&self, faults: FaultConfig) {
+        let mut state = self.state();
+        state.stale_data = match (faults.stale_reads, state.stale_data.take()) {
+            (true, Some(stale_data)) => Some(stale_data),
+            (true, None) => Some(state.data.clone()),
+            (false, _) => None,
+        };
+        state.faults = faults;
+    }
+
+    /// Number of calls made to the client so far
+    pub fn calls(&self) -> u64 {
+        self.state().calls
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("lock poisoned")
+    }
+
+    /// Apply the configured faults to a call of `method` and return the data to read from
+    async fn call(&self, method: &'static str) -> Result<MockData, ClientError> {
+        let latency = self.state().faults.latency;
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        let mut state = self.state();
+        state.calls += 1;
+        let fail_nth = state.faults.fail_every.is_some_and(|n| state.calls % n.get() == 0);
+        if fail_nth || state.faults.failing_methods.contains(method) {
+            return Err(ClientError::Contract(format!("Injected fault in {method}")));
+        }
+        Ok(state.stale_data.as_ref().unwrap_or(&state.data).clone())
+    }
+}
+
+#[async_trait]
+impl Client for MockClient {
+    fn clone_client(&self) -> Box<dyn Client> {
+        Box::new(self.clone())
+    }
+
+    async fn current_epoch(&self) -> Result<u32, ClientError> {
+        Ok(self.call("current_epoch").await?.epoch)
+    }
+
+    async fn current_epoch_start(&self) -> Result<EpochStart, ClientError> {
+        Ok(self.call("current_epoch_start").await?.epoch_start)
+    }
+
+    fn now(&self) -> SystemTime {
+        match &self.state().clock {
+            Some(clock) => clock.now(),
+            None => SystemTime::now(),
+        }
+    }
+
+    async fn epoch_blocks(&self) -> Result<EpochBlocks, ClientError> {
+        Ok(self.call("epoch_blocks").await?.epoch_blocks)
+    }
+
+    async fn worker_id(&self, peer_id: PeerId) -> Result<U256, ClientError> {
+        let workers = self.call("worker_id").await?.workers;
+        let worker = workers.into_iter().find(|w| w.peer_id == peer_id);
+        Ok(worker.map(|w| w.onchain_id).unwrap_or_default())
+    }
+
+    async fn worker_last_active_epoch(&self, id: U256) -> Result<Option<u32>, ClientError> {
+        let data = self.call("worker_last_active_epoch").await?;
+        Ok(data.worker_last_active_epochs.get(&id).copied())
+    }
+
+    async fn active_workers(&self) -> Result<Vec<Worker>, ClientError> {
+        Ok(self.call("active_workers").await?.workers)
+    }
+
+    async fn active_worker_count(&self) -> Result<u64, ClientError> {
+        Ok(self.call("active_worker_count").await?.workers.len() as u64)
+    }
+
+    fn active_workers_stream(self: Box<Self>) -> WorkerStream {
+        let workers = async move { self.call("active_workers_stream").await.map(|d| d.workers) };
+        Box::pin(
+            stream::once(workers)
+                .map_ok(|workers| stream::iter(workers.into_iter().map(Ok)))
+                .try_flatten(),
+        )
+    }
+
+    async fn is_gateway_registered(&self, peer_id: PeerId) -> Result<bool, ClientError> {
+        Ok(self.call("is_gateway_registered").await?.gateways.contains(&peer_id))
+    }
+
+    async fn active_gateways(&self) -> Result<Vec<PeerId>, ClientError> {
+        Ok(self.call("active_gateways").await?.gateways)
+    }
+
+    async fn gateways_by_operator(&self, operator: Address) -> Result<Vec<PeerId>, ClientError> {
+        let mut operator_gateways = self.call("gateways_by_operator").await?.operator_gateways;
+        Ok(operator_gateways.remove(&operator).unwrap_or_default())
+    }
+
+    async fn gateway_stake(&self, gateway_id: PeerId) -> Result<U256, ClientError> {
+        let data = self.call("gateway_stake").await?;
+        Ok(data.gateway_stakes.get(&gateway_id).copied().unwrap_or_default())
+    }
+
+    async fn current_allocations(
+        &self,
+        _client_id: PeerId,
+        worker_ids: Option<Vec<Worker>>,
+    ) -> Result<Vec<Allocation>, ClientError> {
+        let data = self.call("current_allocations").await?;
+        let workers = worker_ids.unwrap_or(data.workers);
+        Ok(workers
+            .into_iter()
+            .map(|w| {
+                data.allocations
+                    .iter()
+                    .find(|a| a.worker_peer_id == w.peer_id)
+                    .cloned()
+                    .unwrap_or(Allocation {
+                        worker_peer_id: w.peer_id,
+                        worker_onchain_id: w.onchain_id,
+                        computation_units: U256::zero(),
+                    })
+            })
+            .collect())
+    }
+
+    async fn default_strategy(&self) -> Result<Address, ClientError> {
+        Ok(self.call("default_strategy").await?.default_strategy)
+    }
+
+    async fn gateway_clusters(&self, _worker_id: U256) -> Result<Vec<GatewayCluster>, ClientError> {
+        Ok(self.call("gateway_clusters").await?.gateway_clusters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let gateway = PeerId::random();
+        let client = MockClient::new(MockData {
+            gateways: vec![gateway],
+            ..Default::default()
+        });
+        assert_eq!(client.active_gateways().await.unwrap(), vec![gateway]);
+
+        client.set_faults(FaultConfig {
+            fail_every: NonZeroU64::new(2),
+            ..Default::default()
+        });
+        assert!(client.active_gateways().await.is_err());
+        assert!(client.active_gateways().await.is_ok());
+        assert!(client.current_epoch().await.is_err());
+
+        client.set_faults(FaultConfig {
+            failing_methods: HashSet::from(["current_epoch"]),
+            stale_reads: true,
+            ..Default::default()
+        });
+        assert!(client.current_epoch().await.is_err());
+        client.update_data(|data| data.gateways.clear());
+        assert_eq!(client.active_gateways().await.unwrap(), vec![gateway]);
+
+        client.set_faults(Default::default());
+        assert_eq!(client.active_gateways().await.unwrap(), vec![]);
+        assert_eq!(client.current_epoch().await.unwrap(), 0);
+        assert_eq!(client.calls(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_time_since_epoch_start() {
+        let clock = crate::ManualClock::new(UNIX_EPOCH + Duration::from_secs(160));
+        let client = MockClient::new(MockData {
+            epoch_start: EpochStart {
+                timestamp: UNIX_EPOCH + Duration::from_secs(100),
+                estimated: false,
+                epoch_length: 0,
+            },
+            ..Default::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+        assert_eq!(client.time_since_epoch_start().await.unwrap(), Duration::from_secs(60));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(client.time_since_epoch_start().await.unwrap(), Duration::from_secs(70));
+
+        clock.set(UNIX_EPOCH);
+        assert_eq!(client.time_since_epoch_start().await.unwrap(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use libp2p::futures::future::join_all;
+
+use crate::{get_client, Client, ClientError, Network, RpcArgs, Worker};
+
+/// Client holding one inner client per network. Queries are sent to all networks concurrently,
+/// and errors are reported per network instead of failing the whole call.
+pub struct MultiNetworkClient {
+    clients: HashMap<Network, Box<dyn Client>>,
+}
+
+impl MultiNetworkClient {
+    pub fn new(clients: impl IntoIterator<Item = (Network, Box<dyn Client>)>) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+        }
+    }
+
+    /// Create clients for all the given RPC configurations, one per network
+    pub async fn connect(
+        rpc_args: impl IntoIterator<Item = &RpcArgs>,
+    ) -> Result<Self, ClientError> {
+        let mut clients = HashMap::new();
+        for args in rpc_args {
+            clients.insert(args.network, get_client(args).await?);
+        }
+        Ok(Self { clients })
+    }
+
+    pub fn client(&self, network: Network) -> Option<&dyn Client> {
+        self.clients.get(&network).map(|client| client.as_ref())
+    }
+
+    pub fn networks(&self) -> impl Iterator<Item = Network> + '_ {
+        self.clients.keys().copied()
+    }
+
+    pub async fn active_workers_all(&self) -> HashMap<Network, Result<Vec<Worker>, ClientError>> {
+        let futures = self
+            .clients
+            .iter()
+            .map(|(network, client)| async move { (*network, client.active_workers().await) });
+        join_all(futures).await.into_iter().collect()
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+use crate::U256;
+
+/// Number of decimals of the network token
+pub const TOKEN_DECIMALS: usize = 18;
+pub const DEFAULT_TOKEN_SYMBOL: &str = "SQD";
+
+/// Amount of the network token, stored as the raw on-chain value (in the smallest units)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TokenAmount(U256);
+
+impl TokenAmount {
+    pub fn from_raw(raw: U256) -> Self {
+        Self(raw)
+    }
+
+    pub fn to_raw(self) -> U256 {
+        self.0
+    }
+
+    /// Approximate amount in whole tokens. Use the raw value for exact arithmetic.
+    pub fn as_f64(&self) -> f64 {
+        let raw = self.0 .0.iter().rev().fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64);
+        raw / 10f64.powi(TOKEN_DECIMALS as i32)
+    }
+
+    /// Format the amount followed by the given token symbol
+    pub fn display_with_symbol<'a>(&'a self, symbol: &'a str) -> impl fmt::Display + 'a {
+        DisplayWithSymbol {
+            amount: self,
+            symbol,
+        }
+    }
+}
+
+impl From<U256> for TokenAmount {
+    fn from(raw: U256) -> Self {
+        Self(raw)
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display_with_symbol(DEFAULT_TOKEN_SYMBOL).fmt(f)
+    }
+}
+
+struct DisplayWithSymbol<'a> {
+    amount: &'a TokenAmount,
+    symbol: &'a str,
+}
+
+impl fmt::Display for DisplayWithSymbol<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = self.amount.0.div_mod(U256::exp10(TOKEN_DECIMALS));
+        if fraction.is_zero() {
+            return write!(f, "{whole} {}", self.symbol);
+        }
+        let fraction = format!("{fraction:0>width$}", width = TOKEN_DECIMALS);
+        write!(f, "{whole}.{} {}", fraction.trim_end_matches('0'), self.symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let amount = |raw: &str| TokenAmount::from_raw(U256::from_dec_str(raw).unwrap());
+        assert_eq!(amount("0").to_string(), "0 SQD");
+        assert_eq!(amount("100000000000000000000000").to_string(), "100000 SQD");
+        assert_eq!(amount("1500000000000000000").to_string(), "1.5 SQD");
+        assert_eq!(amount("1").to_string(), "0.000000000000000001 SQD");
+        assert_eq!(
+            amount("2050000000000000000").display_with_symbol("tSQD").to_string(),
+            "2.05 tSQD"
+        );
+    }
+
+    #[test]
+    fn test_as_f64() {
+        let amount = TokenAmount::from_raw(U256::from(1_500_000_000_000_000_000u128));
+        assert_eq!(amount.as_f64(), 1.5);
+        assert_eq!(TokenAmount::default().as_f64(), 0.0);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::ClientError;
 use ethers::{
-    prelude::{Http, JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError, Ws},
+    prelude::{
+        Http, JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError, Ws, WsClientError,
+    },
     utils::__serde_json::Error,
 };
 use libp2p::futures::TryFutureExt;
@@ -10,13 +12,90 @@ use std::{
     future::Future,
     pin::Pin,
     sync::Arc,
+    time::Duration,
 };
+use tokio::sync::RwLock;
 use url::Url;
 
 #[derive(Debug, Clone)]
 pub enum Transport {
     Http(Http),
-    Ws(Ws),
+    Ws(Arc<ReconnectingWs>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    pub attempts: usize,
+    /// Delay before the first reconnection attempt, multiplied by the attempt number for next ones
+    pub backoff: Duration,
+}
+
+/// Websocket connection which is re-established after it has been dropped.
+/// Requests failed due to the lost connection are retried on the new one. Subscriptions are
+/// not supported (`Transport` doesn't implement `PubsubClient`), so there are none to restore.
+#[derive(Debug)]
+pub struct ReconnectingWs {
+    url: String,
+    config: ReconnectConfig,
+    // Connection generation is used to avoid reconnecting multiple times after a single failure
+    ws: RwLock<(u64, Ws)>,
+}
+
+impl ReconnectingWs {
+    async fn connect(url: &str, config: ReconnectConfig) -> Result<Self, WsClientError> {
+        let ws = Ws::connect_with_reconnects(url, config.attempts).await?;
+        Ok(Self {
+            url: url.to_owned(),
+            config,
+            ws: RwLock::new((0, ws)),
+        })
+    }
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, WsClientError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            let (generation, ws) = self.ws.read().await.clone();
+            match ws.request(method, &params).await {
+                Err(e) if is_connection_lost(&e) && attempt < self.config.attempts => {
+                    attempt += 1;
+                    log::warn!(
+                        "Websocket connection to {} lost: {e}. Reconnecting (attempt {attempt}/{})",
+                        self.url,
+                        self.config.attempts
+                    );
+                    tokio::time::sleep(self.config.backoff * attempt as u32).await;
+                    if let Err(e) = self.reconnect(generation).await {
+                        log::warn!("Reconnecting to {} failed: {e}", self.url);
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn reconnect(&self, failed_generation: u64) -> Result<(), WsClientError> {
+        let mut ws = self.ws.write().await;
+        if ws.0 != failed_generation {
+            return Ok(()); // Already reconnected by another request
+        }
+        let new_ws = Ws::connect_with_reconnects(self.url.as_str(), self.config.attempts).await?;
+        *ws = (failed_generation + 1, new_ws);
+        Ok(())
+    }
+}
+
+fn is_connection_lost(e: &WsClientError) -> bool {
+    matches!(
+        e,
+        WsClientError::UnexpectedClose
+            | WsClientError::DeadChannel
+            | WsClientError::TooManyReconnects
+            | WsClientError::InternalError(_)
+    )
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -60,11 +139,16 @@ impl From<TransportError> for ProviderError {
 }
 
 impl Transport {
-    pub async fn connect(rpc_url: &str) -> Result<Arc<Provider<Self>>, ClientError> {
+    pub async fn connect(
+        rpc_url: &str,
+        reconnect_config: &ReconnectConfig,
+    ) -> Result<Arc<Provider<Self>>, ClientError> {
         let transport = if rpc_url.starts_with("http") {
             Transport::Http(Http::new(Url::parse(rpc_url)?))
         } else if rpc_url.starts_with("ws") {
-            Transport::Ws(Ws::connect(rpc_url).await?)
+            Transport::Ws(Arc::new(
+                ReconnectingWs::connect(rpc_url, reconnect_config.clone()).await?,
+            ))
         } else {
             return Err(ClientError::InvalidProtocol);
         };
@@ -0,0 +1,22 @@
+//! Throughput of the hash functions supported in `SizeAndHash` on a large query result.
+//! Run with `cargo bench -p subsquid-messages --bench hash_throughput`.
+
+use std::time::Instant;
+
+use subsquid_messages::{HashAlgo, SizeAndHash};
+
+const DATA_SIZE: usize = 50 << 20;
+const ITERATIONS: u32 = 5;
+
+fn main() {
+    let data = vec![0x5au8; DATA_SIZE];
+    for algo in [HashAlgo::Sha3_256, HashAlgo::Blake3] {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(SizeAndHash::compute_with(&data, algo));
+        }
+        let elapsed = start.elapsed() / ITERATIONS;
+        let throughput = DATA_SIZE as f64 / elapsed.as_secs_f64() / (1 << 20) as f64;
+        println!("{algo:?}: {elapsed:?} per 50 MiB ({throughput:.0} MiB/s)");
+    }
+}
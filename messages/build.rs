@@ -2,16 +2,22 @@
 fn main() -> std::io::Result<()> {
     println!("cargo:rerun-if-changed=proto/messages.proto");
     prost_build::Config::new()
-        .type_attribute(".", "#[derive(Eq, serde::Serialize, serde::Deserialize)]")
+        .type_attribute(".", "#[derive(Eq)]")
+        .type_attribute(".", "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]")
         .type_attribute("messages.Range", "#[derive(Copy, Ord, PartialOrd)]")
         .skip_debug(["messages.OkResult"])
-        .field_attribute("messages.SizeAndHash.sha3_256", "#[serde(with = \"hex\")]")
-        .field_attribute("messages.QueryExecuted.query_hash","#[serde(with = \"hex\")]")
-        .field_attribute("messages.QuerySubmitted.query_hash","#[serde(with = \"hex\")]")
-        .field_attribute("messages.Pong.ping_hash", "#[serde(with = \"hex\")]")
-        .field_attribute("messages.Ping.signature","#[serde(with = \"hex\")]")
-        .field_attribute("messages.Query.signature", "#[serde(with = \"hex\")]")
-        .field_attribute("messages.QueryExecuted.signature", "#[serde(with = \"hex\")]")
+        // All bytes fields are hex-encoded. Peer IDs are base58 strings in the schema
+        // (e.g. `worker_id`, `client_id`), so they are serialized as is.
+        .field_attribute("messages.SizeAndHash.sha3_256", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.SizeAndHash.blake3", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.QueryExecuted.query_hash","#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.QuerySubmitted.query_hash","#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.Pong.ping_hash", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.Ping.signature","#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.Query.signature", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.QueryExecuted.signature", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.OkResult.data", "#[cfg_attr(feature = \"serde\", serde(with = \"hex\"))]")
+        .field_attribute("messages.OkResult.exec_plan", "#[cfg_attr(feature = \"serde\", serde(default, with = \"crate::hex_opt\"))]")
         .protoc_arg("--experimental_allow_proto3_optional")
         .compile_protos(&["proto/messages.proto"], &["proto/"])?;
     Ok(())
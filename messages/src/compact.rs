@@ -0,0 +1,205 @@
+//! Compact binary encoding of `WorkerState` and `RangeSet`, e.g. for DHT records.
+//!
+//! Ranges are delta-encoded: each range is stored as the gap since the end of the previous one
+//! and its length, both as varints. For mostly contiguous coverage of high block numbers this
+//! is much smaller than protobuf, which stores absolute bounds with per-field tags.
+//! Datasets are sorted, so equal states have equal encodings.
+
+use prost::{
+    bytes::{Buf, BufMut},
+    encoding::{decode_varint, encode_varint},
+};
+
+use crate::{Range, RangeSet, WorkerState};
+
+/// Version tag written as the first byte of every encoding
+pub const COMPACT_VERSION: u8 = 1;
+
+/// Maximum length of a varint-encoded `u64`
+const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CompactDecodeError {
+    #[error("Unsupported compact encoding version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unexpected end of data")]
+    Truncated,
+    #[error("Invalid data: {0}")]
+    Invalid(&'static str),
+}
+
+impl RangeSet {
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut buf = vec![COMPACT_VERSION];
+        encode_ranges(self, &mut buf);
+        buf
+    }
+
+    pub fn from_compact(mut data: &[u8]) -> Result<Self, CompactDecodeError> {
+        read_version(&mut data)?;
+        let ranges = decode_ranges(&mut data)?;
+        ensure_consumed(data)?;
+        Ok(ranges)
+    }
+}
+
+impl WorkerState {
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut datasets: Vec<_> = self.datasets.iter().collect();
+        datasets.sort_by_key(|(dataset, _)| *dataset);
+        let mut buf = vec![COMPACT_VERSION];
+        encode_varint(datasets.len() as u64, &mut buf);
+        for (dataset, ranges) in datasets {
+            encode_varint(dataset.len() as u64, &mut buf);
+            buf.put_slice(dataset.as_bytes());
+            encode_ranges(ranges, &mut buf);
+        }
+        buf
+    }
+
+    pub fn from_compact(mut data: &[u8]) -> Result<Self, CompactDecodeError> {
+        read_version(&mut data)?;
+        let count = read_varint(&mut data)?;
+        let mut state = WorkerState::default();
+        for _ in 0..count {
+            let len = usize::try_from(read_varint(&mut data)?)
+                .map_err(|_| CompactDecodeError::Invalid("dataset name length"))?;
+            if data.remaining() < len {
+                return Err(CompactDecodeError::Truncated);
+            }
+            let dataset = String::from_utf8(data[..len].to_vec())
+                .map_err(|_| CompactDecodeError::Invalid("dataset name is not UTF-8"))?;
+            data.advance(len);
+            let ranges = decode_ranges(&mut data)?;
+            state.datasets.insert(dataset, ranges);
+        }
+        ensure_consumed(data)?;
+        Ok(state)
+    }
+}
+
+fn encode_ranges(ranges: &RangeSet, buf: &mut Vec<u8>) {
+    // Normalize, so that ranges are sorted and disjoint
+    let ranges = RangeSet::from(ranges.ranges.iter().copied());
+    encode_varint(ranges.ranges.len() as u64, buf);
+    let mut next_begin = 0u64;
+    for range in ranges.ranges {
+        encode_varint(range.begin as u64 - next_begin, buf);
+        encode_varint((range.end - range.begin) as u64, buf);
+        next_begin = range.end as u64 + 1;
+    }
+}
+
+fn decode_ranges(data: &mut &[u8]) -> Result<RangeSet, CompactDecodeError> {
+    let count = read_varint(data)?;
+    let mut ranges = Vec::new();
+    let mut next_begin = 0u64;
+    for _ in 0..count {
+        let gap = read_varint(data)?;
+        let len = read_varint(data)?;
+        let begin = next_begin.checked_add(gap).and_then(|begin| u32::try_from(begin).ok());
+        let end = begin.and_then(|begin| (begin as u64).checked_add(len));
+        let (Some(begin), Some(Ok(end))) = (begin, end.map(u32::try_from)) else {
+            return Err(CompactDecodeError::Invalid("range out of bounds"));
+        };
+        ranges.push(Range::new(begin, end));
+        next_begin = end as u64 + 1;
+    }
+    Ok(RangeSet { ranges })
+}
+
+fn read_version(data: &mut &[u8]) -> Result<(), CompactDecodeError> {
+    if !data.has_remaining() {
+        return Err(CompactDecodeError::Truncated);
+    }
+    match data.get_u8() {
+        COMPACT_VERSION => Ok(()),
+        version => Err(CompactDecodeError::UnsupportedVersion(version)),
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, CompactDecodeError> {
+    // A varint cut off by the end of data consists only of bytes with the continuation bit set
+    if data.len() < MAX_VARINT_LEN && data.iter().all(|b| b & 0x80 != 0) {
+        return Err(CompactDecodeError::Truncated);
+    }
+    decode_varint(data).map_err(|_| CompactDecodeError::Invalid("malformed varint"))
+}
+
+fn ensure_consumed(data: &[u8]) -> Result<(), CompactDecodeError> {
+    if data.is_empty() {
+        Ok(())
+    } else {
+        Err(CompactDecodeError::Invalid("trailing data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::ProstMsg;
+
+    use super::*;
+
+    /// Datasets with a few gaps in otherwise contiguous coverage of recent blocks
+    fn realistic_state() -> WorkerState {
+        let datasets = (0..20)
+            .map(|i| {
+                let start = 18_000_000 + i * 100_000;
+                let ranges = (0..30).map(|j| {
+                    let begin = start + j * 10_000;
+                    Range::new(begin, begin + 9_000 + i * 10)
+                });
+                (format!("s3://ethereum-mainnet-{i}"), RangeSet::from(ranges))
+            })
+            .collect::<HashMap<_, _>>();
+        WorkerState::from(datasets)
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let state = realistic_state();
+        assert_eq!(WorkerState::from_compact(&state.to_compact()), Ok(state.clone()));
+
+        let ranges = RangeSet::from([Range::new(0, 0), Range::new(5, 10), Range::new(11, 20)]);
+        let decoded = RangeSet::from_compact(&ranges.to_compact()).unwrap();
+        assert_eq!(decoded.ranges, vec![Range::new(0, 0), Range::new(5, 20)]);
+        let max = RangeSet::from([Range::new(u32::MAX - 1, u32::MAX)]);
+        assert_eq!(RangeSet::from_compact(&max.to_compact()), Ok(max));
+        assert_eq!(
+            WorkerState::from_compact(&WorkerState::default().to_compact()),
+            Ok(WorkerState::default())
+        );
+    }
+
+    #[test]
+    fn test_compact_size() {
+        let state = realistic_state();
+        let compact = state.to_compact().len();
+        let protobuf = state.encoded_len();
+        assert!(compact * 2 < protobuf, "compact: {compact}, protobuf: {protobuf}");
+    }
+
+    #[test]
+    fn test_compact_invalid() {
+        let encoded = realistic_state().to_compact();
+        assert_eq!(
+            WorkerState::from_compact(&encoded[..encoded.len() - 1]),
+            Err(CompactDecodeError::Truncated)
+        );
+        let mut unsupported = encoded.clone();
+        unsupported[0] = 2;
+        assert_eq!(
+            WorkerState::from_compact(&unsupported),
+            Err(CompactDecodeError::UnsupportedVersion(2))
+        );
+        assert_eq!(WorkerState::from_compact(&[]), Err(CompactDecodeError::Truncated));
+        let mut trailing = encoded;
+        trailing.push(0);
+        assert_eq!(
+            WorkerState::from_compact(&trailing),
+            Err(CompactDecodeError::Invalid("trailing data"))
+        );
+    }
+}
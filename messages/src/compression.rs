@@ -0,0 +1,88 @@
+use std::{fmt, str::FromStr};
+
+use crate::Query;
+
+/// Codecs which can be used to compress query result data.
+///
+/// Gateways list the codecs they accept in `Query::accepted_compression` and workers
+/// choose one of them with `Query::negotiate_compression`. If the list is empty
+/// (e.g. the gateway is older), the result is sent uncompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown compression codec: {0}")]
+pub struct UnknownCompression(pub String);
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = UnknownCompression;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(UnknownCompression(s.to_owned())),
+        }
+    }
+}
+
+impl Query {
+    /// Codecs accepted by the client, in the order of preference. Unknown codecs are skipped.
+    pub fn accepted_codecs(&self) -> impl Iterator<Item = Compression> + '_ {
+        self.accepted_compression.iter().filter_map(|c| c.parse().ok())
+    }
+
+    /// Choose the most preferred codec accepted by the client and supported by the worker.
+    /// Returns `None` if the result should be sent uncompressed.
+    pub fn negotiate_compression(&self, supported: &[Compression]) -> Option<Compression> {
+        self.accepted_codecs().find(|c| supported.contains(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(accepted: &[&str]) -> Query {
+        Query {
+            accepted_compression: accepted.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_negotiate_compression() {
+        let both = [Compression::Gzip, Compression::Zstd];
+        assert_eq!(query(&[]).negotiate_compression(&both), None);
+        assert_eq!(query(&["zstd", "gzip"]).negotiate_compression(&both), Some(Compression::Zstd));
+        assert_eq!(
+            query(&["brotli", "gzip"]).negotiate_compression(&both),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(query(&["zstd"]).negotiate_compression(&[Compression::Gzip]), None);
+        assert_eq!(query(&["zstd"]).negotiate_compression(&[]), None);
+    }
+}
@@ -1,11 +1,20 @@
+use std::cmp::min;
+
+use sha3::{Digest, Keccak256};
+
 use crate::Range;
 
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+/// Number of digits in zero-padded block numbers of the chunk path
+pub const DEFAULT_BLOCK_WIDTH: usize = 10;
+
+#[derive(Clone)]
 pub struct DataChunk {
     top: u32,
     first_block: u32,
     last_block: u32,
     last_hash: String,
+    // Widths of the top, first and last block numbers in the path
+    block_widths: [usize; 3],
 }
 
 impl DataChunk {
@@ -17,9 +26,16 @@ impl DataChunk {
             first_block,
             last_block,
             last_hash,
+            block_widths: [DEFAULT_BLOCK_WIDTH; 3],
         }
     }
 
+    /// Set the number of digits used to format block numbers in the chunk path
+    pub fn with_block_width(mut self, block_width: usize) -> Self {
+        self.block_widths = [block_width; 3];
+        self
+    }
+
     #[inline]
     pub fn top(&self) -> u32 {
         self.top
@@ -34,13 +50,64 @@ impl DataChunk {
     pub fn last_block(&self) -> u32 {
         self.last_block
     }
+
+    /// Numbers of digits of the top, first and last block numbers in the chunk path
+    #[inline]
+    pub fn block_widths(&self) -> [usize; 3] {
+        self.block_widths
+    }
+
+    #[inline]
+    pub fn last_hash(&self) -> &str {
+        &self.last_hash
+    }
+
+    /// Compute the hex-encoded Keccak-256 hash identifying chunk `data` in the chunk path.
+    /// Not to be confused with the SHA3-256 hash of query results in `SizeAndHash`.
+    pub fn compute_path_hash(data: impl AsRef<[u8]>) -> String {
+        hex::encode(Keccak256::digest(data))
+    }
+
+    /// Check if the hash in the chunk path is a prefix of the Keccak-256 hash of `data`
+    pub fn verify_hash(&self, data: impl AsRef<[u8]>) -> bool {
+        !self.last_hash.is_empty()
+            && Self::compute_path_hash(data).starts_with(&self.last_hash.to_ascii_lowercase())
+    }
+}
+
+// Block width only affects formatting, so it's not taken into account in comparisons
+impl DataChunk {
+    fn cmp_key(&self) -> (u32, u32, u32, &str) {
+        (self.top, self.first_block, self.last_block, &self.last_hash)
+    }
+}
+
+impl PartialEq for DataChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp_key() == other.cmp_key()
+    }
+}
+
+impl Eq for DataChunk {}
+
+impl PartialOrd for DataChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataChunk {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.cmp_key().cmp(&other.cmp_key())
+    }
 }
 
 impl std::fmt::Display for DataChunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [top_width, first_width, last_width] = self.block_widths;
         write!(
             f,
-            "{:010}/{:010}-{:010}-{}",
+            "{:0top_width$}/{:0first_width$}-{:0last_width$}-{}",
             self.top, self.first_block, self.last_block, self.last_hash
         )
     }
@@ -52,28 +119,236 @@ impl std::fmt::Debug for DataChunk {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseChunkError {
+    #[error("Malformed chunk path, expected '<top>/<first_block>-<last_block>-<hash>': {0}")]
+    MalformedPath(String),
+    #[error("Invalid block number: '{0}'")]
+    InvalidBlockNumber(String),
+    #[error("Invalid block range: top {top}, first block {first_block}, last block {last_block}")]
+    InvalidRange {
+        top: u32,
+        first_block: u32,
+        last_block: u32,
+    },
+}
+
+/// Parse a zero-padded block number of any width. Returns the number and its width.
+fn parse_block_number(s: &str) -> Result<(u32, usize), ParseChunkError> {
+    let invalid = || ParseChunkError::InvalidBlockNumber(s.to_owned());
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let number = s.parse().map_err(|_| invalid())?;
+    Ok((number, s.len()))
+}
+
 impl std::str::FromStr for DataChunk {
-    type Err = ();
+    type Err = ParseChunkError;
 
-    #[allow(clippy::get_first)]
+    /// Parse chunk path. Block numbers can have any width, and the width of each one
+    /// is preserved when the chunk is formatted back. The hash may be empty.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let top_range_split = s.split('/').collect::<Vec<_>>();
-        let top: u32 = top_range_split.get(0).ok_or(())?.parse().or(Err(()))?;
-        let range_str = top_range_split.get(1).ok_or(())?;
-        let range_split = range_str.split('-').collect::<Vec<_>>();
-        let beg: u32 = range_split.get(0).ok_or(())?.parse().or(Err(()))?;
-        let end: u32 = range_split.get(1).ok_or(())?.parse().or(Err(()))?;
-        let hash = range_split.get(2).ok_or(())?;
-        if top <= beg && beg <= end {
-            Ok(DataChunk::new(top, beg, end, hash.to_string()))
-        } else {
-            Err(())
+        let malformed = || ParseChunkError::MalformedPath(s.to_owned());
+        let (top, range) = s.split_once('/').ok_or_else(malformed)?;
+        let mut range_split = range.splitn(3, '-');
+        let (Some(first_block), Some(last_block), Some(hash)) =
+            (range_split.next(), range_split.next(), range_split.next())
+        else {
+            return Err(malformed());
+        };
+        let (top, top_width) = parse_block_number(top)?;
+        let (first_block, first_width) = parse_block_number(first_block)?;
+        let (last_block, last_width) = parse_block_number(last_block)?;
+        if top > first_block || first_block > last_block {
+            return Err(ParseChunkError::InvalidRange {
+                top,
+                first_block,
+                last_block,
+            });
         }
+        let mut chunk = DataChunk::new(top, first_block, last_block, hash.to_owned());
+        chunk.block_widths = [top_width, first_width, last_width];
+        Ok(chunk)
     }
 }
 
+/// Split `range` into consecutive block ranges of `chunk_size` blocks, starting at the beginning
+/// of the range. The last one ends at the end of the range, so it may be shorter.
+/// Yields nothing for an empty range or zero chunk size.
+pub fn tile_range(range: Range, chunk_size: u32) -> impl Iterator<Item = Range> {
+    let (begin, end) = (range.begin as u64, range.end as u64);
+    let chunk_size = chunk_size as u64;
+    let num_chunks = if chunk_size == 0 || begin > end {
+        0
+    } else {
+        (end - begin) / chunk_size + 1
+    };
+    (0..num_chunks).map(move |i| {
+        let first_block = begin + i * chunk_size;
+        let last_block = min(first_block + chunk_size - 1, end);
+        Range::new(first_block as u32, last_block as u32)
+    })
+}
+
 impl From<DataChunk> for Range {
     fn from(chunk: DataChunk) -> Self {
         Range::new(chunk.first_block, chunk.last_block)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{tile_range, DataChunk, ParseChunkError};
+    use crate::{Range, SizeAndHash};
+
+    #[test]
+    fn test_parse_default_width() {
+        let path = "0000000000/0000808640-0000816499-b0486318";
+        let chunk: DataChunk = path.parse().unwrap();
+        assert_eq!(chunk.top(), 0);
+        assert_eq!(chunk.first_block(), 808640);
+        assert_eq!(chunk.last_block(), 816499);
+        assert_eq!(chunk.block_widths(), [10; 3]);
+        assert_eq!(chunk.to_string(), path);
+    }
+
+    #[test]
+    fn test_parse_custom_width() {
+        let path = "000000800000/000000808640-000000816499-b0486318";
+        let chunk: DataChunk = path.parse().unwrap();
+        assert_eq!(chunk.top(), 800000);
+        assert_eq!(chunk.first_block(), 808640);
+        assert_eq!(chunk.last_block(), 816499);
+        assert_eq!(chunk.block_widths(), [12; 3]);
+        assert_eq!(chunk.to_string(), path);
+    }
+
+    #[test]
+    fn test_width_not_compared() {
+        let default: DataChunk = "0000000000/0000000100-0000000199-abcdef".parse().unwrap();
+        let wide: DataChunk = "000000000000/000000000100-000000000199-abcdef".parse().unwrap();
+        assert_eq!(default, wide);
+        assert_eq!(default.cmp(&wide), std::cmp::Ordering::Equal);
+        let next: DataChunk = "0000000000/0000000200-0000000299-abcdef".parse().unwrap();
+        assert!(wide < next);
+    }
+
+    #[test]
+    fn test_parse_mixed_width() {
+        let path = "0/000000808640-0000816499-b0486318";
+        let chunk: DataChunk = path.parse().unwrap();
+        assert_eq!(chunk.first_block(), 808640);
+        assert_eq!(chunk.block_widths(), [1, 12, 10]);
+        assert_eq!(chunk.to_string(), path);
+        assert!("0000000000/+000808640-0000816499-b0486318".parse::<DataChunk>().is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_hash() {
+        let path = "0000000000/0000000000-0000000999-";
+        let chunk: DataChunk = path.parse().unwrap();
+        assert_eq!(chunk.last_hash(), "");
+        assert!(!chunk.verify_hash("abc"));
+        assert_eq!(chunk.to_string(), path);
+    }
+
+    #[test]
+    fn test_format_zero_padded() {
+        let chunk = DataChunk::new(0, 0, 999, "abcdef".to_owned());
+        assert_eq!(chunk.to_string(), "0000000000/0000000000-0000000999-abcdef");
+        assert_eq!(chunk.to_string().parse::<DataChunk>(), Ok(chunk));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let parse = |s: &str| s.parse::<DataChunk>();
+        assert_eq!(
+            parse("0000000000-0000000999-abcdef"),
+            Err(ParseChunkError::MalformedPath("0000000000-0000000999-abcdef".to_owned()))
+        );
+        assert_eq!(
+            parse("0000000000/0000000999"),
+            Err(ParseChunkError::MalformedPath("0000000000/0000000999".to_owned()))
+        );
+        assert_eq!(
+            parse("0000000000/00000x0000-0000000999-abcdef"),
+            Err(ParseChunkError::InvalidBlockNumber("00000x0000".to_owned()))
+        );
+        assert_eq!(
+            parse("0000000000/0000000000-9999999999-abcdef"),
+            Err(ParseChunkError::InvalidBlockNumber("9999999999".to_owned()))
+        );
+        assert_eq!(
+            parse("0000000000/0000000000-0000000999"),
+            Err(ParseChunkError::MalformedPath("0000000000/0000000000-0000000999".to_owned()))
+        );
+        assert_eq!(
+            parse("0000000000/0000001000-0000000999-abcdef"),
+            Err(ParseChunkError::InvalidRange {
+                top: 0,
+                first_block: 1000,
+                last_block: 999
+            })
+        );
+    }
+
+    #[test]
+    fn test_tile_range() {
+        let cases = [
+            (Range::new(1000, 5000), 1000, 5),
+            (Range::new(1000, 4999), 1000, 4),
+            (Range::new(10, 20), 1000, 1),
+            (Range::new(7, 7), 3, 1),
+            (Range::new(u32::MAX - 10, u32::MAX), 4, 3),
+        ];
+        for (range, chunk_size, num_chunks) in cases {
+            let chunks: Vec<_> = tile_range(range, chunk_size).collect();
+            assert_eq!(chunks.len(), num_chunks, "{range:?}");
+            assert_eq!(chunks[0].begin, range.begin);
+            assert_eq!(chunks[chunks.len() - 1].end, range.end);
+            for pair in chunks.windows(2) {
+                assert_eq!(pair[0].end + 1, pair[1].begin);
+                assert_eq!(pair[0].end - pair[0].begin + 1, chunk_size);
+            }
+        }
+        let last = tile_range(Range::new(1000, 5000), 1000).last().unwrap();
+        assert_eq!(last, Range::new(5000, 5000));
+
+        assert_eq!(tile_range(Range { begin: 5, end: 4 }, 10).count(), 0);
+        assert_eq!(tile_range(Range::new(0, 10), 0).count(), 0);
+    }
+
+    #[test]
+    fn test_path_hash() {
+        assert_eq!(
+            DataChunk::compute_path_hash(""),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(
+            DataChunk::compute_path_hash("abc"),
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+        );
+
+        let chunk: DataChunk = "0000000000/0000000000-0000000100-4e03657a".parse().unwrap();
+        assert!(chunk.verify_hash("abc"));
+        assert!(!chunk.verify_hash("abd"));
+        // SHA3-256 of the data must not verify
+        let chunk: DataChunk = "0000000000/0000000000-0000000100-3a985da7".parse().unwrap();
+        assert!(!chunk.verify_hash("abc"));
+    }
+
+    #[test]
+    fn test_result_hash() {
+        let result = SizeAndHash::compute("abc");
+        assert_eq!(result.size, Some(3));
+        assert_eq!(
+            hex::encode(&result.sha3_256),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+        assert!(result.verify("abc"));
+        assert!(!result.verify("abd"));
+        assert!(result.verify_size("abd"));
+        assert!(!result.verify_size("abcd"));
+    }
+}
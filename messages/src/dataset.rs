@@ -0,0 +1,120 @@
+use std::{borrow::Borrow, fmt::Display, ops::Deref, str::FromStr};
+
+pub const MAX_DATASET_ID_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidDatasetId {
+    #[error("Dataset ID is empty")]
+    Empty,
+    #[error("Dataset ID too long ({0} > {MAX_DATASET_ID_LEN})")]
+    TooLong(usize),
+    #[error("Invalid character in dataset ID: {0:?}")]
+    InvalidChar(char),
+}
+
+/// Validated dataset identifier, e.g. `s3://moonbeam-evm-1`.
+///
+/// On the wire, dataset IDs are plain strings, so `DatasetId` is (de)serialized as one.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct DatasetId(String);
+
+impl DatasetId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn validate(id: &str) -> Result<(), InvalidDatasetId> {
+        if id.is_empty() {
+            return Err(InvalidDatasetId::Empty);
+        }
+        if id.len() > MAX_DATASET_ID_LEN {
+            return Err(InvalidDatasetId::TooLong(id.len()));
+        }
+        match id.chars().find(|c| !(c.is_ascii_alphanumeric() || "-_.~:/+=".contains(*c))) {
+            Some(c) => Err(InvalidDatasetId::InvalidChar(c)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<String> for DatasetId {
+    type Error = InvalidDatasetId;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::validate(&id)?;
+        Ok(Self(id))
+    }
+}
+
+impl TryFrom<&str> for DatasetId {
+    type Error = InvalidDatasetId;
+
+    fn try_from(id: &str) -> Result<Self, Self::Error> {
+        id.to_owned().try_into()
+    }
+}
+
+impl FromStr for DatasetId {
+    type Err = InvalidDatasetId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.try_into()
+    }
+}
+
+impl From<DatasetId> for String {
+    fn from(id: DatasetId) -> Self {
+        id.0
+    }
+}
+
+impl Deref for DatasetId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<str> for DatasetId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for DatasetId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for DatasetId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dataset_id_validation() {
+        let id: DatasetId = "s3://moonbeam-evm-1".parse().unwrap();
+        assert_eq!(id.as_str(), "s3://moonbeam-evm-1");
+        assert_eq!(DatasetId::try_from(""), Err(InvalidDatasetId::Empty));
+        assert_eq!(
+            DatasetId::try_from("s3://moonbeam evm"),
+            Err(InvalidDatasetId::InvalidChar(' '))
+        );
+        assert_eq!(
+            DatasetId::try_from("a".repeat(MAX_DATASET_ID_LEN + 1)),
+            Err(InvalidDatasetId::TooLong(MAX_DATASET_ID_LEN + 1))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use crate::SizeAndHash;
+
+/// Hash functions supported in `SizeAndHash`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum HashAlgo {
+    #[default]
+    Sha3_256,
+    /// Much faster on large query results, but not understood by older verifiers
+    Blake3,
+}
+
+impl SizeAndHash {
+    /// Algorithm the hash was computed with, as recorded by the populated hash field
+    pub fn hash_algo(&self) -> HashAlgo {
+        if self.sha3_256.is_empty() && !self.blake3.is_empty() {
+            HashAlgo::Blake3
+        } else {
+            HashAlgo::Sha3_256
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_with() {
+        let sha3 = SizeAndHash::compute("abc");
+        assert_eq!(sha3, SizeAndHash::compute_with("abc", HashAlgo::Sha3_256));
+        assert_eq!(sha3.hash_algo(), HashAlgo::Sha3_256);
+        assert!(sha3.blake3.is_empty());
+
+        let blake3 = SizeAndHash::compute_with("abc", HashAlgo::Blake3);
+        assert_eq!(blake3.size, Some(3));
+        assert_eq!(
+            hex::encode(&blake3.blake3),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(blake3.hash_algo(), HashAlgo::Blake3);
+        assert!(blake3.verify("abc"));
+        assert!(!blake3.verify("abd"));
+        assert!(sha3.verify("abc"));
+    }
+
+    #[test]
+    fn test_verify() {
+        for algo in [HashAlgo::Sha3_256, HashAlgo::Blake3] {
+            let data = b"query result data";
+            let hash = SizeAndHash::compute_with(data, algo);
+            assert!(hash.verify(data));
+            assert!(!hash.verify(&data[..data.len() - 1]));
+            assert!(!hash.verify([&data[..], b"!"].concat()));
+            assert!(!hash.verify(b"query result DATA"));
+
+            let no_size = SizeAndHash {
+                size: None,
+                ..hash.clone()
+            };
+            assert!(!no_size.verify(data));
+            let wrong_size = SizeAndHash {
+                size: Some(0),
+                ..hash
+            };
+            assert!(!wrong_size.verify(data));
+        }
+    }
+}
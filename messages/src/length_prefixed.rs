@@ -0,0 +1,153 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use crate::ProstMsg;
+
+/// Maximum length of a varint-encoded `u64`
+const MAX_VARINT_LEN: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Truncated message: expected {expected} bytes, got {read}")]
+    Truncated { expected: usize, read: usize },
+    #[error("Invalid message: {0}")]
+    Decode(#[from] prost::DecodeError),
+}
+
+/// Write a single message prefixed with its varint-encoded length
+pub fn write_length_prefixed<M: ProstMsg, W: Write>(mut writer: W, msg: &M) -> io::Result<()> {
+    writer.write_all(&msg.encode_length_delimited_to_vec())
+}
+
+/// Iterate over back-to-back length-prefixed messages, as written by [`write_length_prefixed`].
+///
+/// Iteration stops after the first error, because the stream can't be resynchronized.
+pub fn read_length_prefixed<M: ProstMsg + Default, R: Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<M, ReadError>> {
+    LengthPrefixedReader {
+        reader,
+        done: false,
+        _msg: PhantomData,
+    }
+}
+
+struct LengthPrefixedReader<M, R> {
+    reader: R,
+    done: bool,
+    _msg: PhantomData<M>,
+}
+
+impl<M: ProstMsg + Default, R: Read> LengthPrefixedReader<M, R> {
+    /// Returns `None` on a clean end of stream
+    fn read_len(&mut self) -> Result<Option<usize>, ReadError> {
+        let mut prefix = Vec::with_capacity(MAX_VARINT_LEN);
+        let mut byte = [0u8];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) if prefix.is_empty() => return Ok(None),
+                Ok(0) => {
+                    return Err(ReadError::Truncated {
+                        expected: prefix.len() + 1,
+                        read: prefix.len(),
+                    })
+                }
+                Ok(_) => prefix.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+            if byte[0] & 0x80 == 0 || prefix.len() == MAX_VARINT_LEN {
+                return Ok(Some(prost::decode_length_delimiter(prefix.as_slice())?));
+            }
+        }
+    }
+
+    fn read_msg(&mut self) -> Result<Option<M>, ReadError> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        // The prefix is untrusted, so let the buffer grow with the bytes actually read
+        let mut buf = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() < len {
+            return Err(ReadError::Truncated {
+                expected: len,
+                read: buf.len(),
+            });
+        }
+        Ok(Some(M::decode(buf.as_slice())?))
+    }
+}
+
+impl<M: ProstMsg + Default, R: Read> Iterator for LengthPrefixedReader<M, R> {
+    type Item = Result<M, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_msg().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryExecuted;
+
+    fn query_executed(seq_no: u64) -> QueryExecuted {
+        QueryExecuted {
+            client_id: "client".to_string(),
+            worker_id: "worker".to_string(),
+            seq_no: Some(seq_no),
+            exec_time_ms: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let msgs: Vec<_> = (0..5).map(query_executed).collect();
+        let mut buf = Vec::new();
+        for msg in &msgs {
+            write_length_prefixed(&mut buf, msg).unwrap();
+        }
+
+        let decoded: Vec<QueryExecuted> =
+            read_length_prefixed(buf.as_slice()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded, msgs);
+        assert_eq!(read_length_prefixed::<QueryExecuted, _>(&[][..]).count(), 0);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut buf = Vec::new();
+        write_length_prefixed(&mut buf, &query_executed(1)).unwrap();
+        write_length_prefixed(&mut buf, &query_executed(2)).unwrap();
+        buf.truncate(buf.len() - 3);
+
+        let mut iter = read_length_prefixed::<QueryExecuted, _>(buf.as_slice());
+        assert_eq!(iter.next().unwrap().unwrap(), query_executed(1));
+        assert!(matches!(iter.next(), Some(Err(ReadError::Truncated { .. }))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_huge_prefix() {
+        let mut buf = Vec::new();
+        prost::encode_length_delimiter(usize::MAX >> 1, &mut buf).unwrap();
+        buf.extend_from_slice(b"short body");
+
+        let mut iter = read_length_prefixed::<QueryExecuted, _>(buf.as_slice());
+        assert!(matches!(iter.next(), Some(Err(ReadError::Truncated { read: 10, .. }))));
+        assert!(iter.next().is_none());
+    }
+}
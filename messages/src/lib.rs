@@ -16,20 +16,60 @@
 
 use std::{
     collections::HashMap,
-    fmt::{Debug, Formatter},
+    fmt::{Debug, Display, Formatter},
     ops::{Deref, DerefMut},
 };
 
 pub use prost::Message as ProstMsg;
 use sha3::{Digest, Sha3_256};
+use subtle::ConstantTimeEq;
 
+pub use compact::CompactDecodeError;
+pub use compression::Compression;
+pub use dataset::{DatasetId, InvalidDatasetId};
+pub use hash_algo::HashAlgo;
+pub use length_prefixed::{read_length_prefixed, write_length_prefixed};
+pub use result_format::ResultFormat;
+
+pub mod compact;
+pub mod compression;
 pub mod data_chunk;
+pub mod dataset;
+pub mod hash_algo;
+pub mod length_prefixed;
+pub mod query;
 pub mod range;
+pub mod result_format;
 #[cfg(feature = "signatures")]
 pub mod signatures;
+pub mod worker_state;
 
 include!(concat!(env!("OUT_DIR"), "/messages.rs"));
 
+/// Hex (de)serialization of optional bytes fields
+#[cfg(feature = "serde")]
+mod hex_opt {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        data: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match data {
+            Some(data) => serializer.serialize_some(&hex::encode(data)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|data| hex::decode(data).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
 impl Deref for WorkerState {
     type Target = HashMap<String, RangeSet>;
 
@@ -44,12 +84,37 @@ impl DerefMut for WorkerState {
     }
 }
 
+/// Datasets are keyed by plain strings for wire compatibility. States converted from
+/// assignments and pings have their keys validated. These methods provide access by
+/// validated `DatasetId`s.
+impl WorkerState {
+    pub fn dataset(&self, id: &DatasetId) -> Option<&RangeSet> {
+        self.datasets.get(id.as_str())
+    }
+
+    pub fn insert_dataset(&mut self, id: DatasetId, ranges: RangeSet) -> Option<RangeSet> {
+        self.datasets.insert(id.into(), ranges)
+    }
+
+    /// Validate and return IDs of all datasets in the state
+    pub fn dataset_ids(&self) -> Result<Vec<DatasetId>, InvalidDatasetId> {
+        self.datasets.keys().map(|id| id.as_str().try_into()).collect()
+    }
+}
+
 impl From<HashMap<String, RangeSet>> for WorkerState {
     fn from(datasets: HashMap<String, RangeSet>) -> Self {
         Self { datasets }
     }
 }
 
+impl From<HashMap<DatasetId, RangeSet>> for WorkerState {
+    fn from(datasets: HashMap<DatasetId, RangeSet>) -> Self {
+        let datasets = datasets.into_iter().map(|(id, ranges)| (id.into(), ranges)).collect();
+        Self { datasets }
+    }
+}
+
 impl From<&query_result::Result> for query_finished::Result {
     fn from(result: &query_result::Result) -> Self {
         match result {
@@ -58,21 +123,65 @@ impl From<&query_result::Result> for query_finished::Result {
             query_result::Result::ServerError(err) => Self::ServerError(err.clone()),
             query_result::Result::NoAllocation(()) => Self::NoAllocation(()),
             query_result::Result::Timeout(()) => Self::Timeout(()),
+            query_result::Result::NotReady(()) => Self::NotReady(()),
         }
     }
 }
 
 impl SizeAndHash {
+    /// Compute the size and SHA3-256 hash of `data`
     pub fn compute(data: impl AsRef<[u8]>) -> Self {
-        let size = data.as_ref().len() as u32;
-        let mut hasher = Sha3_256::new();
-        hasher.update(data);
-        let hash = hasher.finalize();
-        Self {
-            size: Some(size),
-            sha3_256: hash.to_vec(),
+        Self::compute_with(data, HashAlgo::Sha3_256)
+    }
+
+    pub fn compute_with(data: impl AsRef<[u8]>, algo: HashAlgo) -> Self {
+        let data = data.as_ref();
+        let size = Some(data.len() as u32);
+        match algo {
+            HashAlgo::Sha3_256 => Self {
+                size,
+                sha3_256: Sha3_256::digest(data).to_vec(),
+                ..Default::default()
+            },
+            HashAlgo::Blake3 => Self {
+                size,
+                blake3: blake3::hash(data).as_bytes().to_vec(),
+                ..Default::default()
+            },
         }
     }
+
+    /// Check if the size and hash match `data`, using the algorithm the hash was computed with.
+    /// Returns `false` if the size is missing. Hashes are compared in constant time.
+    pub fn verify(&self, data: impl AsRef<[u8]>) -> bool {
+        let data = data.as_ref();
+        if !self.verify_size(data) {
+            return false;
+        }
+        let algo = self.hash_algo();
+        let computed = Self::compute_with(data, algo);
+        let (hash, computed) = match algo {
+            HashAlgo::Sha3_256 => (&self.sha3_256, computed.sha3_256),
+            HashAlgo::Blake3 => (&self.blake3, computed.blake3),
+        };
+        hash.as_slice().ct_eq(&computed).into()
+    }
+
+    /// Check only if the size matches `data`. A cheap prefilter before the full `verify`.
+    pub fn verify_size(&self, data: impl AsRef<[u8]>) -> bool {
+        u32::try_from(data.as_ref().len()).is_ok_and(|size| self.size == Some(size))
+    }
+}
+
+/// Compute a fast, non-cryptographic fingerprint of the query, e.g. for deduplication.
+///
+/// Fingerprints are meant for local, in-memory indexing only. They are not collision resistant
+/// and must never be used for trust decisions – use `SizeAndHash` or signatures for integrity.
+/// Values are process-local: the same query always gets the same fingerprint within a process,
+/// but it may differ between platforms, CPU features and library versions, so fingerprints
+/// must not be persisted or compared across processes.
+pub fn query_fingerprint(query: &[u8]) -> u64 {
+    ahash::RandomState::with_seeds(0, 0, 0, 0).hash_one(query)
 }
 
 #[cfg(feature = "semver")]
@@ -89,9 +198,10 @@ impl Debug for OkResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "OkResult {{ data: <{} bytes>, exec_plan: <{} bytes> }}",
+            "OkResult {{ data: <{} bytes>, exec_plan: <{} bytes>, compression: {:?} }}",
             self.data.len(),
-            self.exec_plan.as_ref().map(|b| b.len()).unwrap_or_default()
+            self.exec_plan.as_ref().map(|b| b.len()).unwrap_or_default(),
+            self.compression
         )
     }
 }
@@ -126,6 +236,37 @@ impl From<Vec<QueryExecuted>> for QueryLogs {
     }
 }
 
+impl QueryLogs {
+    /// Sum up the number of executed queries and served bytes per worker ID.
+    pub fn summarize(&self) -> HashMap<String, WorkerStats> {
+        let mut stats: HashMap<String, WorkerStats> = HashMap::new();
+        for query in self.queries_executed.iter() {
+            stats.entry(query.worker_id.clone()).or_default().add_query(query);
+        }
+        stats
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkerStats {
+    pub queries: u64,
+    pub served_bytes: u64,
+}
+
+impl WorkerStats {
+    pub fn add_query(&mut self, query: &QueryExecuted) {
+        self.queries += 1;
+        if let Some(query_executed::Result::Ok(InputAndOutput {
+            output: Some(output),
+            ..
+        })) = &query.result
+        {
+            self.served_bytes += output.size.unwrap_or_default() as u64;
+        }
+    }
+}
+
 impl QueryResult {
     pub fn new(query_id: String, result: query_result::Result) -> Self {
         Self {
@@ -134,3 +275,70 @@ impl QueryResult {
         }
     }
 }
+
+impl query_result::Result {
+    pub fn bad_request(err: impl Display) -> Self {
+        Self::BadRequest(err.to_string())
+    }
+
+    pub fn server_error(err: impl Display) -> Self {
+        Self::ServerError(err.to_string())
+    }
+
+    pub fn timeout() -> Self {
+        Self::Timeout(())
+    }
+
+    pub fn no_allocation() -> Self {
+        Self::NoAllocation(())
+    }
+
+    pub fn not_ready() -> Self {
+        Self::NotReady(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_fingerprint_deterministic() {
+        let query = br#"{"fromBlock": 0, "toBlock": 1000}"#;
+        assert_eq!(query_fingerprint(query), query_fingerprint(&query.to_vec()));
+        assert_eq!(query_fingerprint(b""), query_fingerprint(b""));
+        assert_ne!(query_fingerprint(query), query_fingerprint(br#"{"fromBlock": 1}"#));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let result = QueryResult {
+            query_id: "query-1".to_owned(),
+            result: Some(query_result::Result::Ok(OkResult {
+                data: vec![0xde, 0xad],
+                exec_plan: Some(vec![0xbe, 0xef]),
+                compression: Some("gzip".to_owned()),
+                format: None,
+            })),
+        };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["result"]["Ok"]["data"], "dead");
+        assert_eq!(json["result"]["Ok"]["exec_plan"], "beef");
+        assert_eq!(serde_json::from_value::<QueryResult>(json).unwrap(), result);
+
+        let no_plan = QueryResult {
+            result: Some(query_result::Result::Ok(OkResult::default())),
+            ..result
+        };
+        let json = serde_json::to_string(&no_plan).unwrap();
+        assert_eq!(serde_json::from_str::<QueryResult>(&json).unwrap(), no_plan);
+
+        let state = WorkerState::from(HashMap::from([(
+            "s3://moonbeam-evm-1".to_owned(),
+            RangeSet::from([Range::new(0, 999), Range::new(2000, 2999)]),
+        )]));
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<WorkerState>(&json).unwrap(), state);
+    }
+}
@@ -0,0 +1,177 @@
+#[cfg(feature = "signatures")]
+use libp2p::identity::Keypair;
+
+#[cfg(feature = "signatures")]
+use crate::signatures::SignedMessage;
+use crate::{compression::Compression, Query, Range, RangeSet, ResultFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueryBuildError {
+    #[error("Missing field: {0}")]
+    MissingField(&'static str),
+    #[error("Invalid block range: {0:?}")]
+    InvalidRange(Range),
+    #[error("Block range {0:?} not available in dataset")]
+    RangeNotAvailable(Range),
+}
+
+/// Builder for the query requests sent by gateways to workers.
+///
+/// The block range is only used for validation – it should match the range requested
+/// in the query body.
+#[derive(Debug, Clone, Default)]
+pub struct QueryBuilder {
+    query_id: Option<String>,
+    dataset: Option<String>,
+    query: Option<String>,
+    block_range: Option<Range>,
+    dataset_ranges: Option<RangeSet>,
+    profiling: bool,
+    client_state_json: Option<String>,
+    accepted_compression: Vec<Compression>,
+    result_format: Option<ResultFormat>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn query_id(mut self, query_id: impl Into<String>) -> Self {
+        self.query_id = Some(query_id.into());
+        self
+    }
+
+    pub fn dataset(mut self, dataset: impl Into<String>) -> Self {
+        self.dataset = Some(dataset.into());
+        self
+    }
+
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.query = Some(query.into());
+        self
+    }
+
+    pub fn block_range(mut self, range: Range) -> Self {
+        self.block_range = Some(range);
+        self
+    }
+
+    /// Blocks available in the dataset. If set, the block range has to be included in them.
+    pub fn dataset_ranges(mut self, ranges: RangeSet) -> Self {
+        self.dataset_ranges = Some(ranges);
+        self
+    }
+
+    pub fn profiling(mut self, profiling: bool) -> Self {
+        self.profiling = profiling;
+        self
+    }
+
+    pub fn client_state_json(mut self, client_state_json: impl Into<String>) -> Self {
+        self.client_state_json = Some(client_state_json.into());
+        self
+    }
+
+    /// Compression codecs accepted for the result, in the order of preference
+    pub fn accepted_compression(mut self, codecs: impl IntoIterator<Item = Compression>) -> Self {
+        self.accepted_compression = codecs.into_iter().collect();
+        self
+    }
+
+    /// Requested encoding of the result. Workers which don't support it return raw data.
+    /// The gateway transport drops it for workers too old to decode the field.
+    pub fn result_format(mut self, format: ResultFormat) -> Self {
+        self.result_format = Some(format);
+        self
+    }
+
+    /// Validate the fields and build an unsigned query
+    pub fn build(self) -> Result<Query, QueryBuildError> {
+        let query_id = self.query_id.ok_or(QueryBuildError::MissingField("query_id"))?;
+        let dataset = self.dataset.ok_or(QueryBuildError::MissingField("dataset"))?;
+        let query = self.query.ok_or(QueryBuildError::MissingField("query"))?;
+        let range = self.block_range.ok_or(QueryBuildError::MissingField("block_range"))?;
+        if range.begin > range.end {
+            return Err(QueryBuildError::InvalidRange(range));
+        }
+        if let Some(ranges) = self.dataset_ranges {
+            if !ranges.includes(range) {
+                return Err(QueryBuildError::RangeNotAvailable(range));
+            }
+        }
+        Ok(Query {
+            query_id: Some(query_id),
+            dataset: Some(dataset),
+            query: Some(query),
+            profiling: Some(self.profiling),
+            client_state_json: self.client_state_json,
+            signature: vec![],
+            accepted_compression: self.accepted_compression.iter().map(|c| c.to_string()).collect(),
+            result_format: self.result_format.map(|f| f.to_string()),
+        })
+    }
+
+    /// Validate the fields and build a query signed with the client's keypair
+    #[cfg(feature = "signatures")]
+    pub fn build_signed(self, keypair: &Keypair) -> Result<Query, QueryBuildError> {
+        let mut query = self.build()?;
+        query.sign(keypair);
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ProstMsg;
+
+    use super::*;
+
+    fn builder() -> QueryBuilder {
+        QueryBuilder::new()
+            .query_id("query-1")
+            .dataset("s3://ethereum-mainnet")
+            .query(r#"{"fromBlock": 100, "toBlock": 200}"#)
+            .block_range(Range::new(100, 200))
+    }
+
+    #[test]
+    fn test_build_round_trip() {
+        let query = builder().profiling(true).build().unwrap();
+        let decoded = Query::decode(query.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, query);
+        assert_eq!(decoded.query_id.as_deref(), Some("query-1"));
+        assert_eq!(decoded.dataset.as_deref(), Some("s3://ethereum-mainnet"));
+        assert_eq!(decoded.profiling, Some(true));
+        assert_eq!(decoded.client_state_json, None);
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let err = QueryBuilder::new().dataset("ds").query("{}").build().unwrap_err();
+        assert_eq!(err, QueryBuildError::MissingField("query_id"));
+        let err = builder().block_range(Range { begin: 2, end: 1 }).build().unwrap_err();
+        assert_eq!(err, QueryBuildError::InvalidRange(Range { begin: 2, end: 1 }));
+        let err = QueryBuilder::new().query_id("q").dataset("ds").query("{}").build().unwrap_err();
+        assert_eq!(err, QueryBuildError::MissingField("block_range"));
+    }
+
+    #[test]
+    fn test_range_not_available() {
+        let available = RangeSet::from(vec![Range::new(0, 150)]);
+        let err = builder().dataset_ranges(available).build().unwrap_err();
+        assert_eq!(err, QueryBuildError::RangeNotAvailable(Range::new(100, 200)));
+
+        let available = RangeSet::from(vec![Range::new(0, 1000)]);
+        assert!(builder().dataset_ranges(available).build().is_ok());
+    }
+
+    #[cfg(feature = "signatures")]
+    #[test]
+    fn test_build_signed() {
+        let keypair = Keypair::generate_ed25519();
+        let query = builder().build_signed(&keypair).unwrap();
+        let mut decoded = Query::decode(query.encode_to_vec().as_slice()).unwrap();
+        assert!(decoded.verify_signature(&keypair.public().to_peer_id()));
+    }
+}
@@ -1,4 +1,11 @@
-use std::cmp::{max, Ordering};
+//! `RangeSet` keeps its ranges sorted, non-overlapping and non-adjacent (normalized).
+//! Sets built with `From` or `extend` are always normalized, lookups rely on it.
+//! Sets constructed directly or decoded from messages should be normalized with `normalize`.
+
+use std::{
+    borrow::Cow,
+    cmp::{max, min, Ordering},
+};
 
 pub use crate::{Range, RangeSet};
 
@@ -18,6 +25,33 @@ impl RangeSet {
         self.containing_range(point).is_some()
     }
 
+    /// Check if the block is included in the set with a binary search
+    pub fn contains(&self, block: u64) -> bool {
+        u32::try_from(block).is_ok_and(|block| self.has(block))
+    }
+
+    /// Sort and merge the ranges, dropping empty ones
+    pub fn normalize(&mut self) {
+        // Ranges decoded from messages may be empty
+        self.ranges.retain(|r| r.begin <= r.end);
+        if self.ranges.is_empty() {
+            return;
+        }
+        self.ranges.sort();
+        let mut pi = 0;
+        for i in 1..self.ranges.len() {
+            let c = self.ranges[i];
+            let p = self.ranges[pi];
+            if c.begin > p.end.saturating_add(1) {
+                pi += 1;
+                self.ranges[pi] = c;
+            } else {
+                self.ranges[pi] = Range::new(p.begin, max(c.end, p.end));
+            }
+        }
+        self.ranges.truncate(pi + 1);
+    }
+
     pub fn find_containing_range(&self, point: u32) -> Option<Range> {
         self.containing_range(point).map(|i| self.ranges[i])
     }
@@ -43,6 +77,156 @@ impl RangeSet {
             false
         }
     }
+
+    /// Number of points included in the set, same as `num_blocks`
+    pub fn size(&self) -> u64 {
+        self.num_blocks()
+    }
+
+    /// Number of blocks included in the set, counting blocks of overlapping ranges once,
+    /// even if the set isn't normalized
+    pub fn num_blocks(&self) -> u64 {
+        self.normalized()
+            .ranges
+            .iter()
+            .fold(0u64, |n, r| n.saturating_add((r.end - r.begin) as u64 + 1))
+    }
+
+    /// Check if the set includes no blocks
+    pub fn is_empty(&self) -> bool {
+        self.ranges.iter().all(|r| r.begin > r.end)
+    }
+
+    /// This set if it's normalized, a normalized copy otherwise
+    fn normalized(&self) -> Cow<'_, RangeSet> {
+        if self.is_normalized() {
+            Cow::Borrowed(self)
+        } else {
+            let mut normalized = self.clone();
+            normalized.normalize();
+            Cow::Owned(normalized)
+        }
+    }
+
+    fn is_normalized(&self) -> bool {
+        self.ranges.iter().all(|r| r.begin <= r.end)
+            && self.ranges.windows(2).all(|w| w[0].end.saturating_add(1) < w[1].begin)
+    }
+
+    /// Number of points included in both sets
+    pub fn intersection_size(&self, other: &RangeSet) -> u64 {
+        let mut size = 0;
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (self.ranges[i], other.ranges[j]);
+            let begin = max(a.begin, b.begin);
+            let end = min(a.end, b.end);
+            if begin <= end {
+                size += (end - begin) as u64 + 1;
+            }
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        size
+    }
+
+    /// Points included in either set
+    pub fn union(&self, other: &RangeSet) -> RangeSet {
+        RangeSet::from(self.ranges.iter().chain(&other.ranges).copied())
+    }
+
+    /// Points included in both sets
+    pub fn intersection(&self, other: &RangeSet) -> RangeSet {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (self.ranges[i], other.ranges[j]);
+            let begin = max(a.begin, b.begin);
+            let end = min(a.end, b.end);
+            if begin <= end {
+                ranges.push(Range::new(begin, end));
+            }
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        RangeSet { ranges }
+    }
+
+    /// Points included in this set but not in `other`
+    pub fn difference(&self, other: &RangeSet) -> RangeSet {
+        let mut ranges = Vec::new();
+        let mut j = 0;
+        for &range in &self.ranges {
+            let mut begin = range.begin;
+            // Skip ranges of `other` entirely before the remaining part
+            while j < other.ranges.len() && other.ranges[j].end < begin {
+                j += 1;
+            }
+            let mut k = j;
+            let mut covered = false;
+            while k < other.ranges.len() && other.ranges[k].begin <= range.end {
+                let b = other.ranges[k];
+                if b.begin > begin {
+                    ranges.push(Range::new(begin, b.begin - 1));
+                }
+                if b.end >= range.end {
+                    covered = true;
+                    break;
+                }
+                begin = b.end + 1;
+                k += 1;
+            }
+            if !covered {
+                ranges.push(Range::new(begin, range.end));
+            }
+        }
+        RangeSet { ranges }
+    }
+
+    /// Union of all the sets, computed with a single sort and merge of all their ranges
+    pub fn union_all(sets: impl IntoIterator<Item = RangeSet>) -> RangeSet {
+        RangeSet::from(sets.into_iter().flat_map(|set| set.ranges))
+    }
+
+    /// Partition the set into `n` parts with numbers of points differing by at most one.
+    /// Ranges are only split when needed to keep the parts balanced. Always returns exactly
+    /// `n` parts, so splitting an empty set yields `n` empty sets.
+    /// Works on a normalized copy if the set isn't normalized.
+    pub fn split_into(&self, n: usize) -> Vec<RangeSet> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let normalized = self.normalized();
+        let total = normalized.num_blocks();
+        let mut ranges = normalized.ranges.iter().copied();
+        let mut leftover: Option<Range> = None;
+        (0..n as u64)
+            .map(|i| {
+                let mut remaining = total / n as u64 + u64::from(i < total % n as u64);
+                let mut part = Vec::new();
+                while remaining > 0 {
+                    let range = leftover.take().or_else(|| ranges.next()).expect("enough points");
+                    let len = (range.end - range.begin) as u64 + 1;
+                    if len <= remaining {
+                        part.push(range);
+                        remaining -= len;
+                    } else {
+                        let end = range.begin + remaining as u32 - 1;
+                        part.push(Range::new(range.begin, end));
+                        leftover = Some(Range::new(end + 1, range.end));
+                        remaining = 0;
+                    }
+                }
+                RangeSet { ranges: part }
+            })
+            .collect()
+    }
 }
 
 impl<T: IntoIterator<Item = Range>> From<T> for RangeSet {
@@ -56,19 +240,7 @@ impl<T: IntoIterator<Item = Range>> From<T> for RangeSet {
 impl Extend<Range> for RangeSet {
     fn extend<T: IntoIterator<Item = Range>>(&mut self, iter: T) {
         self.ranges.extend(iter);
-        self.ranges.sort();
-        let mut pi = 0;
-        for i in 1..self.ranges.len() {
-            let c = self.ranges[i];
-            let p = self.ranges[pi];
-            if c.begin > p.end + 1 {
-                pi += 1;
-                self.ranges[pi] = c;
-            } else {
-                self.ranges[pi] = Range::new(p.begin, max(c.end, p.end));
-            }
-        }
-        self.ranges.truncate(pi + 1);
+        self.normalize();
     }
 }
 
@@ -103,6 +275,50 @@ mod tests {
         assert!(!rs.has(35));
     }
 
+    #[test]
+    fn range_set_contains() {
+        // 3000 ranges of 10 blocks separated by gaps of 10 blocks, in reverse order
+        let ranges = (0..3000u32).map(|i| Range::new(i * 20, i * 20 + 9)).collect();
+        let mut rs = RangeSet { ranges };
+        rs.ranges.reverse();
+        rs.ranges.push(Range::new(5, 12));
+        rs.normalize();
+        assert_eq!(rs.ranges.len(), 3000);
+        assert_eq!(rs.ranges[0], Range::new(0, 12));
+        for block in 0..60_010u64 {
+            let expected = block % 20 < 10 && block < 60_000 || (10..=12).contains(&block);
+            assert_eq!(rs.contains(block), expected, "block {block}");
+        }
+        assert!(!rs.contains(u64::MAX));
+        assert!(RangeSet::from([Range::new(0, u32::MAX)]).contains(u32::MAX as u64));
+        assert!(!RangeSet::from([Range::new(0, u32::MAX)]).contains(u32::MAX as u64 + 1));
+    }
+
+    #[test]
+    fn range_set_num_blocks() {
+        assert_eq!(RangeSet::empty().num_blocks(), 0);
+        assert!(RangeSet::empty().is_empty());
+        let inverted = RangeSet {
+            ranges: vec![Range { begin: 2, end: 1 }],
+        };
+        assert!(inverted.is_empty());
+        assert_eq!(inverted.num_blocks(), 0);
+        assert_eq!(inverted.size(), 0);
+
+        let huge = RangeSet::from([Range::new(0, u32::MAX)]);
+        assert_eq!(huge.num_blocks(), u32::MAX as u64 + 1);
+        assert!(!huge.is_empty());
+        let near_max = RangeSet {
+            ranges: vec![Range::new(u32::MAX - 10, u32::MAX), Range::new(0, u32::MAX)],
+        };
+        assert_eq!(near_max.num_blocks(), u32::MAX as u64 + 1);
+
+        let overlapping = RangeSet {
+            ranges: vec![Range::new(5, 10), Range::new(0, 7), Range::new(20, 20)],
+        };
+        assert_eq!(overlapping.num_blocks(), 12);
+    }
+
     #[test]
     fn range_set_find_containing_range() {
         let rs = RangeSet::from(vec![Range::new(0, 10), Range::new(20, 30), Range::new(40, 50)]);
@@ -113,4 +329,111 @@ mod tests {
         assert_eq!(rs.find_containing_range(41), Some(Range::new(40, 50)));
         assert_eq!(rs.find_containing_range(15), None);
     }
+
+    #[test]
+    fn range_set_intersection_size() {
+        let a = RangeSet::from(vec![Range::new(0, 10), Range::new(20, 30), Range::new(40, 50)]);
+        let b = RangeSet::from(vec![Range::new(5, 25), Range::new(45, 45)]);
+
+        assert_eq!(a.size(), 33);
+        assert_eq!(a.intersection_size(&b), 13);
+        assert_eq!(b.intersection_size(&a), 13);
+        assert_eq!(a.intersection_size(&a), 33);
+        assert_eq!(a.intersection_size(&RangeSet::empty()), 0);
+    }
+
+    #[test]
+    fn range_set_union_all() {
+        let sets: Vec<RangeSet> = (0..50u32)
+            .map(|i| {
+                RangeSet::from(vec![
+                    Range::new(i * 7 % 100, i * 7 % 100 + i % 5),
+                    Range::new(200 + i * 3, 201 + i * 3),
+                ])
+            })
+            .collect();
+
+        let pairwise = sets.iter().fold(RangeSet::empty(), |acc, set| acc.union(set));
+        assert_eq!(RangeSet::union_all(sets), pairwise);
+        assert_eq!(RangeSet::union_all(vec![]), RangeSet::empty());
+    }
+
+    /// Pseudo-random sets of ranges within `0..200`
+    fn random_sets(n: usize) -> Vec<RangeSet> {
+        let mut seed = 0x2545f491u32;
+        let mut next = move |bound: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % bound
+        };
+        (0..n)
+            .map(|_| {
+                let ranges = (0..next(6)).map(|_| {
+                    let begin = next(190);
+                    Range::new(begin, begin + next(10))
+                });
+                RangeSet::from(ranges.collect::<Vec<_>>())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn range_set_normalization() {
+        let rs = RangeSet::from(vec![Range::new(11, 20), Range::new(0, 10)]);
+        assert_eq!(rs.ranges, vec![Range::new(0, 20)]);
+        let rs = RangeSet::from(vec![Range { begin: 5, end: 4 }, Range::new(7, 8)]);
+        assert_eq!(rs.ranges, vec![Range::new(7, 8)]);
+        let rs = RangeSet::from(vec![Range::new(u32::MAX, u32::MAX), Range::new(0, u32::MAX)]);
+        assert_eq!(rs.ranges, vec![Range::new(0, u32::MAX)]);
+    }
+
+    #[test]
+    fn range_set_algebra() {
+        let sets = random_sets(30);
+        for a in &sets {
+            for b in &sets {
+                let (union, intersection) = (a.union(b), a.intersection(b));
+                let difference = a.difference(b);
+                for point in 0..210 {
+                    let (in_a, in_b) = (a.has(point), b.has(point));
+                    assert_eq!(union.has(point), in_a || in_b, "{a:?} | {b:?} at {point}");
+                    assert_eq!(intersection.has(point), in_a && in_b, "{a:?} & {b:?} at {point}");
+                    assert_eq!(difference.has(point), in_a && !in_b, "{a:?} - {b:?} at {point}");
+                }
+                // Results are normalized
+                for result in [union, intersection, difference] {
+                    assert_eq!(RangeSet::from(result.ranges.clone()), result);
+                }
+            }
+        }
+        let a = RangeSet::from(vec![Range::new(0, 10)]);
+        let b = RangeSet::from(vec![Range::new(11, 20)]);
+        assert_eq!(a.union(&b).ranges, vec![Range::new(0, 20)]);
+    }
+
+    #[test]
+    fn range_set_split_into() {
+        let rs = RangeSet::from(vec![Range::new(0, 10), Range::new(20, 30), Range::new(40, 50)]);
+
+        let parts = rs.split_into(4);
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts.iter().map(RangeSet::size).collect::<Vec<_>>(), vec![9, 8, 8, 8]);
+        assert_eq!(parts[1].ranges, vec![Range::new(9, 10), Range::new(20, 25)]);
+        let union = RangeSet::from(parts.iter().flat_map(|p| p.ranges.iter().copied()));
+        assert_eq!(union, rs);
+
+        assert_eq!(rs.split_into(1), vec![rs.clone()]);
+        assert_eq!(rs.split_into(0), vec![]);
+        assert_eq!(RangeSet::empty().split_into(3), vec![RangeSet::empty(); 3]);
+
+        // Not normalized: overlapping and inverted ranges
+        let raw = RangeSet {
+            ranges: vec![Range::new(5, 15), Range { begin: 30, end: 20 }, Range::new(0, 10)],
+        };
+        let parts = raw.split_into(2);
+        assert_eq!(parts[0].ranges, vec![Range::new(0, 7)]);
+        assert_eq!(parts[1].ranges, vec![Range::new(8, 15)]);
+        assert_eq!(parts, RangeSet::from(raw.ranges.clone()).split_into(2));
+    }
 }
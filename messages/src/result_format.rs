@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+use crate::{OkResult, Query};
+
+/// Encodings of query result data.
+///
+/// Gateways can request one in `Query::result_format` and workers choose it with
+/// `Query::negotiate_result_format` if they support it, falling back to raw data otherwise.
+/// The format actually used is recorded in `OkResult::format`. Workers which predate the
+/// field can't verify signatures of queries requesting a format, so it must only be set for
+/// workers known to support it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ResultFormat {
+    /// Data passed through as produced by the query engine
+    #[default]
+    Raw,
+    Arrow,
+    Parquet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown result format: {0}")]
+pub struct UnknownResultFormat(pub String);
+
+impl ResultFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultFormat::Raw => "raw",
+            ResultFormat::Arrow => "arrow",
+            ResultFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl fmt::Display for ResultFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ResultFormat {
+    type Err = UnknownResultFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ResultFormat::Raw),
+            "arrow" => Ok(ResultFormat::Arrow),
+            "parquet" => Ok(ResultFormat::Parquet),
+            _ => Err(UnknownResultFormat(s.to_owned())),
+        }
+    }
+}
+
+impl Query {
+    /// Choose the format requested by the client if it's supported by the worker.
+    /// Returns `Raw` if no format was requested, or the requested one is unknown or unsupported.
+    pub fn negotiate_result_format(&self, supported: &[ResultFormat]) -> ResultFormat {
+        self.result_format
+            .as_deref()
+            .and_then(|f| f.parse().ok())
+            .filter(|f| supported.contains(f))
+            .unwrap_or_default()
+    }
+}
+
+impl OkResult {
+    /// Format of the result data. `SizeAndHash` of the result is computed over the data
+    /// in this format.
+    pub fn result_format(&self) -> Result<ResultFormat, UnknownResultFormat> {
+        self.format.as_deref().map_or(Ok(ResultFormat::Raw), str::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(format: Option<&str>) -> Query {
+        Query {
+            result_format: format.map(ToOwned::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_negotiate_result_format() {
+        let supported = [ResultFormat::Raw, ResultFormat::Arrow];
+        assert_eq!(query(None).negotiate_result_format(&supported), ResultFormat::Raw);
+        assert_eq!(query(Some("arrow")).negotiate_result_format(&supported), ResultFormat::Arrow);
+        assert_eq!(query(Some("parquet")).negotiate_result_format(&supported), ResultFormat::Raw);
+        assert_eq!(query(Some("csv")).negotiate_result_format(&supported), ResultFormat::Raw);
+
+        let result = OkResult {
+            format: Some("parquet".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(result.result_format(), Ok(ResultFormat::Parquet));
+        assert_eq!(OkResult::default().result_format(), Ok(ResultFormat::Raw));
+    }
+}
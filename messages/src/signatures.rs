@@ -15,6 +15,26 @@ pub fn msg_hash<M: ProstMsg>(msg: &M) -> Vec<u8> {
     result.to_vec()
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VerifyError {
+    #[error("Invalid message: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("Invalid signature")]
+    InvalidSignature,
+}
+
+/// Decode a signed message and check that it has been signed by `expected_author`
+pub fn verify_and_decode<M: SignedMessage + Default>(
+    raw: &[u8],
+    expected_author: &PeerId,
+) -> Result<M, VerifyError> {
+    let mut msg = M::decode(raw)?;
+    if !msg.verify_signature(expected_author) {
+        return Err(VerifyError::InvalidSignature);
+    }
+    Ok(msg)
+}
+
 fn verify_signature<T: SignedMessage>(peer_id: &PeerId, msg: &mut T) -> bool {
     let sig = msg.detach_signature();
     let encoded = msg.encode_to_vec();
@@ -82,3 +102,115 @@ impl SignedMessage for QueryExecuted {
         self.query.as_mut().is_some_and(|q| verify_signature(&client_id, q))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_signature() {
+        let client = Keypair::generate_ed25519();
+        let forger = Keypair::generate_ed25519();
+        let client_id = client.public().to_peer_id();
+        let query = Query {
+            query_id: Some("query".to_owned()),
+            dataset: Some("s3://ethereum-mainnet".to_owned()),
+            query: Some("{}".to_owned()),
+            ..Default::default()
+        };
+
+        let mut valid = query.clone();
+        valid.sign(&client);
+        assert!(valid.verify_signature(&client_id));
+        assert!(!valid.verify_signature(&forger.public().to_peer_id()));
+
+        // Signed by another key, but claimed to be sent by the client
+        let mut forged = query.clone();
+        forged.sign(&forger);
+        assert!(!forged.verify_signature(&client_id));
+
+        // Modified after signing
+        let mut tampered = valid.clone();
+        tampered.dataset = Some("s3://moonbeam-evm-1".to_owned());
+        assert!(!tampered.verify_signature(&client_id));
+    }
+
+    #[test]
+    fn test_verify_and_decode() {
+        let worker = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let worker_id = worker.public().to_peer_id();
+        let mut ping = Ping {
+            worker_id: Some(worker_id.to_string()),
+            version: Some("1.0.0".to_owned()),
+            ..Default::default()
+        };
+        ping.sign(&worker);
+        let raw = ping.encode_to_vec();
+
+        assert_eq!(verify_and_decode::<Ping>(&raw, &worker_id), Ok(ping.clone()));
+        assert!(matches!(
+            verify_and_decode::<Ping>(&raw[..raw.len() - 1], &worker_id),
+            Err(VerifyError::Decode(_))
+        ));
+        assert_eq!(
+            verify_and_decode::<Ping>(&raw, &other.public().to_peer_id()),
+            Err(VerifyError::InvalidSignature)
+        );
+
+        let mut forged = ping.clone();
+        forged.signature = vec![0; 64];
+        assert_eq!(
+            verify_and_decode::<Ping>(&forged.encode_to_vec(), &worker_id),
+            Err(VerifyError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_query_executed_signature() {
+        use crate::{query_executed, InputAndOutput, SizeAndHash};
+
+        let client = Keypair::generate_ed25519();
+        let worker = Keypair::generate_ed25519();
+        let worker_id = worker.public().to_peer_id();
+        let mut query = Query {
+            query_id: Some("query".to_owned()),
+            ..Default::default()
+        };
+        query.sign(&client);
+        let mut log = QueryExecuted {
+            client_id: client.public().to_peer_id().to_string(),
+            worker_id: worker_id.to_string(),
+            query: Some(query),
+            exec_time_ms: Some(100),
+            result: Some(query_executed::Result::Ok(InputAndOutput {
+                num_read_chunks: Some(1),
+                output: Some(SizeAndHash {
+                    size: Some(1000),
+                    sha3_256: vec![1; 32],
+                    ..Default::default()
+                }),
+            })),
+            seq_no: Some(1),
+            timestamp_ms: Some(1_700_000_000_000),
+            ..Default::default()
+        };
+        log.sign(&worker);
+        assert!(log.verify_signature(&worker_id));
+
+        // The signature covers timing and result fields, not only the query
+        let mut tampered = log.clone();
+        tampered.exec_time_ms = Some(1);
+        assert!(!tampered.verify_signature(&worker_id));
+
+        let mut tampered = log.clone();
+        tampered.timestamp_ms = Some(0);
+        assert!(!tampered.verify_signature(&worker_id));
+
+        let mut tampered = log.clone();
+        if let Some(query_executed::Result::Ok(result)) = &mut tampered.result {
+            result.output.as_mut().unwrap().sha3_256 = vec![2; 32];
+        }
+        assert!(!tampered.verify_signature(&worker_id));
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+
+use prost::Message;
+use sha3::{Digest, Sha3_256};
+
+use crate::{
+    data_chunk::DataChunk, DatasetId, InvalidDatasetId, Ping, Range, RangeSet, WorkerAssignment,
+    WorkerState, WorkerStatePart,
+};
+
+/// Upper bound on the size of `WorkerStatePart` fields other than the state itself
+const PART_OVERHEAD: usize = 18;
+/// Upper bound on the growth of length prefixes when adding ranges to a dataset entry
+const ENTRY_PREFIX_SLACK: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum WorkerStatePartsError {
+    #[error("Size budget too small to fit dataset {0}")]
+    BudgetTooSmall(String),
+    #[error("Part {0} is missing")]
+    MissingPart(u32),
+    #[error("Parts have inconsistent total count")]
+    InconsistentTotal,
+}
+
+/// Blocks gained and lost between two worker states, per dataset.
+/// Datasets without changes are omitted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerStateDiff {
+    pub added: BTreeMap<String, RangeSet>,
+    pub removed: BTreeMap<String, RangeSet>,
+}
+
+impl WorkerStateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidWorkerState {
+    #[error("Invalid chunk path: {0}")]
+    ChunkPath(String),
+    #[error("Invalid dataset ID '{id}': {error}")]
+    DatasetId { id: String, error: InvalidDatasetId },
+}
+
+fn validate_dataset_id(id: &str) -> Result<String, InvalidWorkerState> {
+    DatasetId::try_from(id)
+        .map(String::from)
+        .map_err(|error| InvalidWorkerState::DatasetId {
+            id: id.to_owned(),
+            error,
+        })
+}
+
+impl TryFrom<&WorkerAssignment> for WorkerState {
+    type Error = InvalidWorkerState;
+
+    /// Block ranges the worker should hold according to the assignment
+    fn try_from(assignment: &WorkerAssignment) -> Result<Self, Self::Error> {
+        let mut state = WorkerState::default();
+        for dataset in assignment.dataset_chunks.iter() {
+            let dataset_id = validate_dataset_id(&dataset.dataset_id)?;
+            let ranges = dataset
+                .chunks
+                .iter()
+                .map(|chunk| {
+                    let parsed: DataChunk = chunk
+                        .path
+                        .parse()
+                        .map_err(|_| InvalidWorkerState::ChunkPath(chunk.path.clone()))?;
+                    Ok(Range::from(parsed))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            state.datasets.entry(dataset_id).or_default().extend(ranges);
+        }
+        Ok(state)
+    }
+}
+
+impl TryFrom<&Ping> for WorkerState {
+    type Error = InvalidWorkerState;
+
+    /// Block ranges the worker reports to be storing
+    fn try_from(ping: &Ping) -> Result<Self, Self::Error> {
+        let mut state = WorkerState::default();
+        for dataset in ping.stored_ranges.iter() {
+            let dataset_id = validate_dataset_id(&dataset.url)?;
+            let ranges = dataset.ranges.iter().copied();
+            state.datasets.entry(dataset_id).or_default().extend(ranges);
+        }
+        Ok(state)
+    }
+}
+
+impl WorkerState {
+    /// Compute the changes needed to get from this state to `other`
+    pub fn diff(&self, other: &WorkerState) -> WorkerStateDiff {
+        let empty = RangeSet::empty();
+        let mut diff = WorkerStateDiff::default();
+        for (dataset, old) in self.datasets.iter() {
+            let new = other.datasets.get(dataset).unwrap_or(&empty);
+            let removed = old.difference(new);
+            if !removed.is_empty() {
+                diff.removed.insert(dataset.clone(), removed);
+            }
+        }
+        for (dataset, new) in other.datasets.iter() {
+            let old = self.datasets.get(dataset).unwrap_or(&empty);
+            let added = new.difference(old);
+            if !added.is_empty() {
+                diff.added.insert(dataset.clone(), added);
+            }
+        }
+        diff
+    }
+
+    /// Add ranges of all datasets from `other` to this state
+    pub fn merge(&mut self, other: WorkerState) {
+        for (dataset, ranges) in other.datasets {
+            match self.datasets.entry(dataset) {
+                Entry::Occupied(mut entry) => {
+                    let merged = entry.get().union(&ranges);
+                    entry.insert(merged);
+                }
+                Entry::Vacant(entry) => {
+                    let mut ranges = ranges;
+                    ranges.normalize();
+                    entry.insert(ranges);
+                }
+            }
+        }
+    }
+
+    /// Split the state into parts with encoded size of at most `max_size` bytes each.
+    /// Datasets are partitioned between parts, and range sets which don't fit into
+    /// a single part are split as well. Use `WorkerState::reassemble` to merge the parts.
+    pub fn split_by_size(
+        &self,
+        max_size: usize,
+    ) -> Result<Vec<WorkerStatePart>, WorkerStatePartsError> {
+        let budget = max_size.saturating_sub(PART_OVERHEAD);
+        let mut datasets: Vec<_> = self.datasets.iter().collect();
+        datasets.sort_by_key(|(dataset, _)| *dataset);
+
+        let mut states = Vec::new();
+        let mut current = WorkerState::default();
+        let mut current_size = 0;
+        for (dataset, ranges) in datasets {
+            for ranges in split_ranges(dataset, ranges, budget)? {
+                let entry_size = entry_size(dataset, &ranges);
+                if current_size + entry_size > budget {
+                    states.push(std::mem::take(&mut current));
+                    current_size = 0;
+                }
+                // Range set could have been split, so the dataset may be already present
+                current.datasets.entry(dataset.clone()).or_default().extend(ranges.ranges);
+                current_size += entry_size;
+            }
+        }
+        if !current.datasets.is_empty() || states.is_empty() {
+            states.push(current);
+        }
+
+        let total_parts = states.len() as u32;
+        Ok(states
+            .into_iter()
+            .enumerate()
+            .map(|(part_index, state)| WorkerStatePart {
+                part_index: part_index as u32,
+                total_parts,
+                state: Some(state),
+            })
+            .collect())
+    }
+
+    /// Merge parts created by `WorkerState::split_by_size`. Parts can be given in any order.
+    pub fn reassemble(
+        parts: impl IntoIterator<Item = WorkerStatePart>,
+    ) -> Result<Self, WorkerStatePartsError> {
+        let mut total = None;
+        let mut states = HashMap::new();
+        for part in parts {
+            if *total.get_or_insert(part.total_parts) != part.total_parts {
+                return Err(WorkerStatePartsError::InconsistentTotal);
+            }
+            states.insert(part.part_index, part.state.unwrap_or_default());
+        }
+        let mut result = WorkerState::default();
+        for index in 0..total.unwrap_or_default() {
+            let state = states.remove(&index).ok_or(WorkerStatePartsError::MissingPart(index))?;
+            for (dataset, ranges) in state.datasets {
+                result.datasets.entry(dataset).or_default().extend(ranges.ranges);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Fraction of the blocks in `assignment` which are covered by this state.
+    /// An empty assignment is always fully covered.
+    pub fn coverage(&self, assignment: &WorkerState) -> f64 {
+        let mut assigned = 0;
+        let mut covered = 0;
+        for (dataset, ranges) in assignment.datasets.iter() {
+            assigned += ranges.size();
+            if let Some(local) = self.datasets.get(dataset) {
+                covered += local.intersection_size(ranges);
+            }
+        }
+        if assigned == 0 {
+            return 1.0;
+        }
+        covered as f64 / assigned as f64
+    }
+
+    /// Encoding of the state which doesn't depend on the order of datasets or ranges.
+    /// Datasets are sorted by name, and each one is encoded as its name prefixed with
+    /// the length (u64, little-endian), followed by its normalized range set as
+    /// a length-delimited protobuf message. Datasets without blocks are skipped, so the
+    /// result is stable across processes and equal for states covering the same blocks.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut datasets: Vec<_> = self
+            .datasets
+            .iter()
+            .map(|(dataset, ranges)| {
+                let mut ranges = ranges.clone();
+                ranges.normalize();
+                (dataset, ranges)
+            })
+            .filter(|(_, ranges)| !ranges.ranges.is_empty())
+            .collect();
+        datasets.sort_by_key(|(dataset, _)| *dataset);
+        let mut buf = Vec::new();
+        for (dataset, ranges) in datasets {
+            buf.extend_from_slice(&(dataset.len() as u64).to_le_bytes());
+            buf.extend_from_slice(dataset.as_bytes());
+            ranges.encode_length_delimited(&mut buf).expect("Vec has unlimited capacity");
+        }
+        buf
+    }
+
+    /// SHA3-256 hash of `canonical_bytes`, stable across processes
+    pub fn hash(&self) -> [u8; 32] {
+        Sha3_256::digest(self.canonical_bytes()).into()
+    }
+}
+
+fn entry_size(dataset: &str, ranges: &RangeSet) -> usize {
+    WorkerState {
+        datasets: [(dataset.to_owned(), ranges.clone())].into(),
+    }
+    .encoded_len()
+}
+
+fn range_size(range: &Range) -> usize {
+    // Field tag and length prefix (single byte, because a range takes at most 12 bytes)
+    range.encoded_len() + 2
+}
+
+/// Split the range set into chunks, each fitting into `budget` as a single dataset entry
+fn split_ranges(
+    dataset: &str,
+    ranges: &RangeSet,
+    budget: usize,
+) -> Result<Vec<RangeSet>, WorkerStatePartsError> {
+    if entry_size(dataset, ranges) <= budget {
+        return Ok(vec![ranges.clone()]);
+    }
+    let base_size = entry_size(dataset, &RangeSet::empty()) + ENTRY_PREFIX_SLACK;
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_size = base_size;
+    for range in ranges.ranges.iter() {
+        let size = range_size(range);
+        if base_size + size > budget {
+            return Err(WorkerStatePartsError::BudgetTooSmall(dataset.to_owned()));
+        }
+        if chunk_size + size > budget {
+            chunks.push(RangeSet {
+                ranges: std::mem::take(&mut chunk),
+            });
+            chunk_size = base_size;
+        }
+        chunk.push(*range);
+        chunk_size += size;
+    }
+    if !chunk.is_empty() {
+        chunks.push(RangeSet { ranges: chunk });
+    }
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> WorkerState {
+        let ranges = |n: u32| RangeSet::from((0..n).map(|i| Range::new(i * 1000, i * 1000 + 500)));
+        WorkerState {
+            datasets: [
+                ("s3://ethereum-mainnet".to_owned(), ranges(100)),
+                ("s3://moonbeam-evm-1".to_owned(), ranges(10)),
+                ("s3://arbitrum-one".to_owned(), ranges(1000)),
+            ]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let datasets = [
+            ("s3://ethereum-mainnet", vec![Range::new(0, 999), Range::new(5000, 5999)]),
+            ("s3://moonbeam-evm-1", vec![Range::new(10, 20)]),
+            ("s3://arbitrum-one", vec![Range::new(100, 199), Range::new(300, 399)]),
+        ];
+        let state = WorkerState::from(
+            datasets
+                .iter()
+                .map(|(dataset, ranges)| (dataset.to_string(), RangeSet::from(ranges.clone())))
+                .collect::<HashMap<_, _>>(),
+        );
+
+        let mut shuffled = WorkerState::default();
+        for (dataset, ranges) in datasets.iter().rev() {
+            let ranges = ranges.iter().rev().copied().collect();
+            shuffled.datasets.insert(dataset.to_string(), RangeSet { ranges });
+        }
+        assert_eq!(shuffled.canonical_bytes(), state.canonical_bytes());
+        assert_eq!(shuffled.hash(), state.hash());
+
+        let mut changed = state.clone();
+        changed.datasets.get_mut("s3://moonbeam-evm-1").unwrap().ranges[0].end = 21;
+        assert_ne!(changed.hash(), state.hash());
+        assert_ne!(WorkerState::default().hash(), state.hash());
+
+        // Datasets without blocks don't affect the encoding
+        let mut with_empty = state.clone();
+        with_empty.datasets.insert("s3://base-1".to_owned(), RangeSet::empty());
+        with_empty.datasets.insert(
+            "s3://zksync".to_owned(),
+            RangeSet {
+                ranges: vec![Range { begin: 5, end: 4 }],
+            },
+        );
+        assert_eq!(with_empty.canonical_bytes(), state.canonical_bytes());
+        let empty = WorkerState::from(HashMap::from([("a".to_owned(), RangeSet::empty())]));
+        assert_eq!(empty.hash(), WorkerState::default().hash());
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let state = state();
+        let parts = state.split_by_size(1000).unwrap();
+        assert!(parts.len() > 1);
+        for part in parts.iter() {
+            assert!(part.encoded_len() <= 1000);
+            assert_eq!(part.total_parts as usize, parts.len());
+        }
+        let mut parts = parts;
+        parts.reverse();
+        assert_eq!(WorkerState::reassemble(parts).unwrap(), state);
+    }
+
+    #[test]
+    fn test_no_split_needed() {
+        let state = state();
+        let parts = state.split_by_size(1024 * 1024).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(WorkerState::reassemble(parts).unwrap(), state);
+
+        let parts = WorkerState::default().split_by_size(100).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(WorkerState::reassemble(parts).unwrap(), WorkerState::default());
+    }
+
+    #[test]
+    fn test_split_errors() {
+        assert_eq!(
+            state().split_by_size(20),
+            Err(WorkerStatePartsError::BudgetTooSmall("s3://arbitrum-one".to_owned()))
+        );
+        let mut parts = state().split_by_size(1000).unwrap();
+        parts.remove(1);
+        assert_eq!(WorkerState::reassemble(parts), Err(WorkerStatePartsError::MissingPart(1)));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let assignment = state();
+        assert_eq!(assignment.coverage(&assignment), 1.0);
+        assert_eq!(WorkerState::default().coverage(&assignment), 0.0);
+        assert_eq!(WorkerState::default().coverage(&WorkerState::default()), 1.0);
+
+        let mut partial = assignment.clone();
+        partial.datasets.remove("s3://arbitrum-one");
+        assert_eq!(partial.coverage(&assignment), (110 * 501) as f64 / (1110 * 501) as f64);
+    }
+
+    #[test]
+    fn test_state_from_assignment() {
+        use crate::{AssignedChunk, DatasetChunks};
+
+        let chunk = |path: &str| AssignedChunk {
+            path: path.to_owned(),
+            filenames: vec![],
+        };
+        let mut assignment = WorkerAssignment {
+            dataset_chunks: vec![DatasetChunks {
+                dataset_id: "s3://moonbeam-evm-1".to_owned(),
+                download_url: String::new(),
+                chunks: vec![
+                    chunk("0000000000/0000000000-0000000999-b0486318"),
+                    chunk("0000000000/0000001000-0000001999-c0486318"),
+                    chunk("0000000000/0000005000-0000005999-d0486318"),
+                ],
+            }],
+            ..Default::default()
+        };
+        let state = WorkerState::try_from(&assignment).unwrap();
+        assert_eq!(
+            state.datasets["s3://moonbeam-evm-1"],
+            RangeSet::from([Range::new(0, 1999), Range::new(5000, 5999)])
+        );
+
+        assignment.dataset_chunks[0].chunks.push(chunk("invalid"));
+        assert_eq!(
+            WorkerState::try_from(&assignment),
+            Err(InvalidWorkerState::ChunkPath("invalid".to_owned()))
+        );
+
+        assignment.dataset_chunks[0].chunks.pop();
+        assignment.dataset_chunks[0].dataset_id = "s3://moonbeam evm".to_owned();
+        assert_eq!(
+            WorkerState::try_from(&assignment),
+            Err(InvalidWorkerState::DatasetId {
+                id: "s3://moonbeam evm".to_owned(),
+                error: InvalidDatasetId::InvalidChar(' ')
+            })
+        );
+    }
+
+    #[test]
+    fn test_state_from_ping() {
+        use crate::DatasetRanges;
+
+        let mut ping = Ping {
+            stored_ranges: vec![
+                DatasetRanges {
+                    url: "s3://moonbeam-evm-1".to_owned(),
+                    ranges: vec![Range::new(10, 20), Range::new(0, 9)],
+                },
+                DatasetRanges {
+                    url: "s3://base-1".to_owned(),
+                    ranges: vec![],
+                },
+            ],
+            ..Default::default()
+        };
+        let state = WorkerState::try_from(&ping).unwrap();
+        assert_eq!(state.datasets["s3://moonbeam-evm-1"], RangeSet::from([Range::new(0, 20)]));
+        assert_eq!(state.datasets["s3://base-1"], RangeSet::empty());
+
+        ping.stored_ranges[1].url = String::new();
+        assert_eq!(
+            WorkerState::try_from(&ping),
+            Err(InvalidWorkerState::DatasetId {
+                id: String::new(),
+                error: InvalidDatasetId::Empty
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = state();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.datasets
+            .get_mut("s3://ethereum-mainnet")
+            .unwrap()
+            .ranges
+            .push(Range::new(100_000, 100_999));
+        new.datasets.get_mut("s3://moonbeam-evm-1").unwrap().ranges.remove(0);
+        new.datasets.remove("s3://arbitrum-one");
+        new.datasets
+            .insert("s3://base-1".to_owned(), RangeSet::from([Range::new(5, 10)]));
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added,
+            BTreeMap::from([
+                ("s3://base-1".to_owned(), RangeSet::from([Range::new(5, 10)])),
+                (
+                    "s3://ethereum-mainnet".to_owned(),
+                    RangeSet::from([Range::new(100_000, 100_999)])
+                ),
+            ])
+        );
+        assert_eq!(
+            diff.removed,
+            BTreeMap::from([
+                ("s3://arbitrum-one".to_owned(), old.datasets["s3://arbitrum-one"].clone()),
+                ("s3://moonbeam-evm-1".to_owned(), RangeSet::from([Range::new(0, 500)])),
+            ])
+        );
+        let reverse = new.diff(&old);
+        assert_eq!((reverse.added, reverse.removed), (diff.removed, diff.added));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut merged = WorkerState {
+            datasets: [
+                ("s3://moonbeam-evm-1".to_owned(), RangeSet::from([Range::new(0, 500)])),
+                ("s3://arbitrum-one".to_owned(), RangeSet::from([Range::new(10, 20)])),
+            ]
+            .into(),
+        };
+        merged.merge(WorkerState {
+            datasets: [
+                (
+                    "s3://moonbeam-evm-1".to_owned(),
+                    RangeSet::from([Range::new(400, 1000), Range::new(2000, 2500)]),
+                ),
+                (
+                    "s3://base-1".to_owned(),
+                    RangeSet {
+                        ranges: vec![Range::new(6, 10), Range::new(0, 5)],
+                    },
+                ),
+            ]
+            .into(),
+        });
+        assert_eq!(
+            merged.datasets,
+            HashMap::from([
+                (
+                    "s3://moonbeam-evm-1".to_owned(),
+                    RangeSet::from([Range::new(0, 1000), Range::new(2000, 2500)])
+                ),
+                ("s3://arbitrum-one".to_owned(), RangeSet::from([Range::new(10, 20)])),
+                ("s3://base-1".to_owned(), RangeSet::from([Range::new(0, 10)])),
+            ])
+        );
+
+        let state = state();
+        let mut copy = state.clone();
+        copy.merge(state.clone());
+        assert_eq!(copy, state);
+    }
+}
@@ -1,7 +1,12 @@
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use contract_client::{Allocation, U256};
 
-use futures::StreamExt;
-use futures_core::Stream;
+use futures::{stream, StreamExt};
 use libp2p::{
     request_response::OutboundRequestId,
     swarm::{NetworkBehaviour, SwarmEvent, ToSwarm},
@@ -9,16 +14,18 @@ use libp2p::{
 };
 use libp2p_swarm_derive::NetworkBehaviour;
 use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
 
 use subsquid_messages::{
-    gateway_log_msg, query_result, GatewayLogMsg, Ping, Query, QueryFinished, QueryResult,
-    QuerySubmitted,
+    gateway_log_msg, query_result, Compression, GatewayLogMsg, Ping, Query, QueryFinished,
+    QueryResult, QuerySubmitted,
 };
 
 use crate::{
     behaviour::{
-        base::{BaseBehaviour, BaseBehaviourEvent},
+        base::{BaseBehaviour, BaseBehaviourEvent, PeerRtts},
+        pubsub::SubscribedTopics,
         request_client::{ClientBehaviour, ClientConfig, ClientEvent},
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
@@ -42,6 +49,16 @@ pub enum GatewayEvent {
         peer_id: PeerId,
         result: QueryResult,
     },
+    /// Remaining allocation for the worker dropped to `allocation_low_threshold_pct`
+    /// of the allocated computation units. Emitted once per worker and epoch.
+    AllocationLow {
+        worker: PeerId,
+        remaining_pct: u32,
+    },
+    /// Pending query has been cancelled with `GatewayTransportHandle::cancel_query`
+    QueryCancelled {
+        query_id: String,
+    },
 }
 
 #[derive(NetworkBehaviour)]
@@ -59,7 +76,21 @@ pub struct GatewayConfig {
     pub max_query_size: u64,
     pub max_query_result_size: u64,
     pub max_query_log_size: u64,
+    /// Compression codecs accepted for query results, in the order of preference.
+    /// Set on queries which don't specify them, if the worker supports it
+    /// (see `negotiation_min_worker_version`).
+    pub accepted_compression: Vec<Compression>,
+    /// Lowest worker version (as reported in pings) which understands the
+    /// `accepted_compression` and `result_format` query fields. Older workers drop these
+    /// fields when decoding and fail to verify the query signature, so they're removed from
+    /// queries to workers below this version or not seen in pings yet.
+    /// `None` disables both fields for all workers.
+    pub negotiation_min_worker_version: Option<semver::Version>,
+    /// Remaining allocation (percent of allocated computation units) at which
+    /// `GatewayEvent::AllocationLow` is emitted
+    pub allocation_low_threshold_pct: u32,
     pub queries_queue_size: usize,
+    pub probes_queue_size: usize,
     pub logs_queue_size: usize,
     pub events_queue_size: usize,
     pub shutdown_timeout: Duration,
@@ -74,7 +105,11 @@ impl GatewayConfig {
             max_query_size: MAX_QUERY_SIZE,
             max_query_result_size: MAX_QUERY_RESULT_SIZE,
             max_query_log_size: MAX_GATEWAY_LOG_SIZE,
+            accepted_compression: Vec::new(),
+            negotiation_min_worker_version: None,
+            allocation_low_threshold_pct: 10,
             queries_queue_size: 100,
+            probes_queue_size: 100,
             logs_queue_size: 100,
             events_queue_size: 100,
             shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
@@ -86,6 +121,12 @@ pub struct GatewayBehaviour {
     inner: InnerBehaviour,
     logs_collector_id: PeerId,
     query_ids: BTreeMap<OutboundRequestId, String>,
+    accepted_compression: Vec<String>,
+    negotiation_min_worker_version: Option<semver::Version>,
+    // Worker versions from the latest pings
+    worker_versions: HashMap<PeerId, semver::Version>,
+    // Callers waiting for the reachability of probed peers
+    pending_probes: HashMap<PeerId, Vec<oneshot::Sender<bool>>>,
 }
 
 impl GatewayBehaviour {
@@ -111,12 +152,26 @@ impl GatewayBehaviour {
             inner,
             logs_collector_id: config.logs_collector_id,
             query_ids: Default::default(),
+            accepted_compression: config
+                .accepted_compression
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+            negotiation_min_worker_version: config.negotiation_min_worker_version,
+            worker_versions: Default::default(),
+            pending_probes: Default::default(),
         }
         .into()
     }
     fn on_base_event(&mut self, ev: BaseBehaviourEvent) -> Option<GatewayEvent> {
         match ev {
             BaseBehaviourEvent::Ping { peer_id, ping } => self.on_ping(peer_id, ping),
+            BaseBehaviourEvent::PeerProbed { peer_id, reachable } => {
+                for result_tx in self.pending_probes.remove(&peer_id).unwrap_or_default() {
+                    _ = result_tx.send(reachable);
+                }
+                None
+            }
             _ => None,
         }
     }
@@ -130,6 +185,7 @@ impl GatewayBehaviour {
                 return None;
             }
         }
+        self.worker_versions.insert(peer_id, ping.sem_version());
         Some(GatewayEvent::Ping { peer_id, ping })
     }
 
@@ -183,7 +239,7 @@ impl GatewayBehaviour {
         log::debug!("Query {query_id} timed out");
         Some(GatewayEvent::QueryResult {
             peer_id,
-            result: QueryResult::new(query_id, query_result::Result::Timeout(())),
+            result: QueryResult::new(query_id, query_result::Result::timeout()),
         })
     }
 
@@ -204,6 +260,11 @@ impl GatewayBehaviour {
             Some(id) => id.clone(),
             None => return log::error!("Query without ID dropped"),
         };
+        if !self.supports_negotiation(&peer_id) {
+            strip_negotiation_fields(&mut query);
+        } else if query.accepted_compression.is_empty() {
+            query.accepted_compression = self.accepted_compression.clone();
+        }
         self.inner.base.sign(&mut query);
         match self.inner.query.try_send_request(peer_id, query) {
             Ok(req_id) => {
@@ -213,6 +274,38 @@ impl GatewayBehaviour {
         }
     }
 
+    fn supports_negotiation(&self, peer_id: &PeerId) -> bool {
+        match (&self.negotiation_min_worker_version, self.worker_versions.get(peer_id)) {
+            (Some(min_version), Some(version)) => version >= min_version,
+            _ => false,
+        }
+    }
+
+    pub fn cancel_query(&mut self, query_id: String) -> Option<GatewayEvent> {
+        let req_id = self
+            .query_ids
+            .iter()
+            .find_map(|(req_id, id)| (*id == query_id).then_some(*req_id));
+        let Some(req_id) = req_id else {
+            log::debug!("Cannot cancel query {query_id}: not pending");
+            return None;
+        };
+        log::debug!("Cancelling query {query_id}");
+        self.query_ids.remove(&req_id);
+        self.inner.query.cancel_request(req_id);
+        Some(GatewayEvent::QueryCancelled { query_id })
+    }
+
+    /// Check if the peer is reachable, dialing it if there's no outbound connection.
+    /// The result is dropped if too many probes are ongoing.
+    pub fn probe_peer(&mut self, peer_id: PeerId, result_tx: oneshot::Sender<bool>) {
+        match self.inner.base.try_probe_peer(peer_id) {
+            Ok(true) => _ = result_tx.send(true),
+            Ok(false) => self.pending_probes.entry(peer_id).or_default().push(result_tx),
+            Err(QueueFull) => log::warn!("Too many ongoing probes. Cannot probe {peer_id}"),
+        }
+    }
+
     pub fn send_log_msg(&mut self, msg: GatewayLogMsg) {
         log::debug!("Sending log message: {msg:?}");
         if self.inner.logs.try_send_request(self.logs_collector_id, msg).is_err() {
@@ -242,9 +335,160 @@ impl BehaviourWrapper for GatewayBehaviour {
     }
 }
 
+/// Remove query fields which workers predating result negotiation can't decode
+fn strip_negotiation_fields(query: &mut Query) {
+    if !query.accepted_compression.is_empty() || query.result_format.is_some() {
+        log::debug!("Worker doesn't support result negotiation, using defaults");
+    }
+    query.accepted_compression.clear();
+    query.result_format = None;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct WorkerAllocation {
+    allocated: u64,
+    consumed: u64,
+    low_reported: bool,
+}
+
+impl WorkerAllocation {
+    fn remaining_pct(&self) -> u32 {
+        let remaining = self.allocated.saturating_sub(self.consumed) as u128;
+        (remaining * 100 / self.allocated.max(1) as u128) as u32
+    }
+}
+
+/// Tracks computation units consumed by queries against the allocations for the current epoch
+#[derive(Debug)]
+struct AllocationTracker {
+    epoch: Option<u32>,
+    workers: HashMap<PeerId, WorkerAllocation>,
+    low_threshold_pct: u32,
+}
+
+impl AllocationTracker {
+    fn new(low_threshold_pct: u32) -> Self {
+        Self {
+            epoch: None,
+            workers: Default::default(),
+            low_threshold_pct,
+        }
+    }
+
+    /// Update allocated units. Consumed units are reset when the epoch changes.
+    fn set_allocations(&mut self, epoch: u32, allocations: &[Allocation]) {
+        if self.epoch != Some(epoch) {
+            log::debug!("Resetting allocation tracking for epoch {epoch}");
+            self.epoch = Some(epoch);
+            self.workers.clear();
+        }
+        for allocation in allocations {
+            let allocated = allocation.computation_units.min(U256::from(u64::MAX)).as_u64();
+            let worker = self.workers.entry(allocation.worker_peer_id).or_default();
+            worker.allocated = allocated;
+            if worker.remaining_pct() > self.low_threshold_pct {
+                worker.low_reported = false;
+            }
+        }
+    }
+
+    fn consume(&mut self, worker_id: PeerId, units: u64) -> Option<GatewayEvent> {
+        let worker = self.workers.get_mut(&worker_id)?;
+        worker.consumed = worker.consumed.saturating_add(units);
+        let remaining_pct = worker.remaining_pct();
+        if worker.low_reported || remaining_pct > self.low_threshold_pct {
+            return None;
+        }
+        worker.low_reported = true;
+        Some(GatewayEvent::AllocationLow {
+            worker: worker_id,
+            remaining_pct,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum BudgetError {
+    #[error("Query rate limit exceeded")]
+    RateLimited,
+    #[error("Not enough computation units left ({remaining} < {cost})")]
+    Exhausted { remaining: u64, cost: u64 },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendQueryError {
+    #[error(transparent)]
+    QueueFull(#[from] QueueFull),
+    #[error(transparent)]
+    Budget(#[from] BudgetError),
+}
+
+/// Combined limit on queries sent by the gateway: computation units left for the current
+/// epoch and a per-second query rate (token bucket with a burst of one second of queries).
+#[derive(Debug, Clone)]
+pub struct QueryBudget {
+    remaining_units: u64,
+    max_rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl QueryBudget {
+    pub fn new(epoch_units: u64, max_queries_per_sec: u32) -> Self {
+        Self {
+            remaining_units: epoch_units,
+            max_rate: max_queries_per_sec as f64,
+            tokens: max_queries_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Computation units left for the current epoch
+    pub fn remaining_units(&self) -> u64 {
+        self.remaining_units
+    }
+
+    /// Reset the epoch allocation on epoch transition. The rate limit state is kept.
+    pub fn reset_epoch(&mut self, epoch_units: u64) {
+        self.remaining_units = epoch_units;
+    }
+
+    /// Charge a query costing `cost` units if both the allocation and rate limit allow it.
+    /// Nothing is charged on error.
+    pub fn try_charge(&mut self, cost: u64) -> Result<(), BudgetError> {
+        self.try_charge_at(cost, Instant::now())
+    }
+
+    fn try_charge_at(&mut self, cost: u64, now: Instant) -> Result<(), BudgetError> {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.max_rate).min(self.max_rate);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return Err(BudgetError::RateLimited);
+        }
+        if self.remaining_units < cost {
+            return Err(BudgetError::Exhausted {
+                remaining: self.remaining_units,
+                cost,
+            });
+        }
+        self.tokens -= 1.0;
+        self.remaining_units -= cost;
+        Ok(())
+    }
+
+    /// Undo a successful `try_charge` of a query which hasn't been sent
+    fn refund(&mut self, cost: u64) {
+        self.tokens = (self.tokens + 1.0).min(self.max_rate);
+        self.remaining_units = self.remaining_units.saturating_add(cost);
+    }
+}
+
 struct GatewayTransport {
     swarm: Swarm<Wrapped<GatewayBehaviour>>,
     queries_rx: Receiver<(PeerId, Query)>,
+    cancellations_rx: Receiver<String>,
+    probes_rx: Receiver<(PeerId, oneshot::Sender<bool>)>,
     logs_rx: Receiver<GatewayLogMsg>,
     events_tx: Sender<GatewayEvent>,
 }
@@ -257,12 +501,20 @@ impl GatewayTransport {
                 _ = cancel_token.cancelled() => break,
                 ev = self.swarm.select_next_some() => self.on_swarm_event(ev),
                 Some((peer_id, query)) = self.queries_rx.recv() => self.swarm.behaviour_mut().send_query(peer_id, query),
+                Some(query_id) = self.cancellations_rx.recv() => self.cancel_query(query_id),
+                Some((peer_id, tx)) = self.probes_rx.recv() => self.swarm.behaviour_mut().probe_peer(peer_id, tx),
                 Some(log_msg) = self.logs_rx.recv() => self.swarm.behaviour_mut().send_log_msg(log_msg),
             }
         }
         log::info!("Shutting down gateway P2P transport");
     }
 
+    fn cancel_query(&mut self, query_id: String) {
+        if let Some(ev) = self.swarm.behaviour_mut().cancel_query(query_id) {
+            self.events_tx.send_lossy(ev)
+        }
+    }
+
     fn on_swarm_event(&mut self, ev: SwarmEvent<GatewayEvent>) {
         log::trace!("Swarm event: {ev:?}");
         record_event(&ev);
@@ -275,28 +527,93 @@ impl GatewayTransport {
 #[derive(Clone)]
 pub struct GatewayTransportHandle {
     queries_tx: Sender<(PeerId, Query)>,
+    cancellations_tx: Sender<String>,
+    probes_tx: Sender<(PeerId, oneshot::Sender<bool>)>,
     logs_tx: Sender<GatewayLogMsg>,
+    events_tx: Sender<GatewayEvent>,
+    peer_rtts: PeerRtts,
+    subscribed_topics: SubscribedTopics,
+    allocations: Arc<Mutex<AllocationTracker>>,
+    budget: Arc<Mutex<Option<QueryBudget>>>,
     _task_manager: Arc<TaskManager>,
 }
 
 impl GatewayTransportHandle {
     fn new(
         queries_tx: Sender<(PeerId, Query)>,
+        cancellations_tx: Sender<String>,
+        probes_tx: Sender<(PeerId, oneshot::Sender<bool>)>,
         logs_tx: Sender<GatewayLogMsg>,
+        events_tx: Sender<GatewayEvent>,
         transport: GatewayTransport,
-        shutdown_timeout: Duration,
+        config: &GatewayConfig,
     ) -> Self {
-        let mut task_manager = TaskManager::new(shutdown_timeout);
+        let peer_rtts = transport.swarm.behaviour().inner.base.peer_rtts();
+        let subscribed_topics = transport.swarm.behaviour().inner.base.subscribed_topics();
+        let allocations = AllocationTracker::new(config.allocation_low_threshold_pct);
+        let mut task_manager = TaskManager::new(config.shutdown_timeout);
         task_manager.spawn(|c| transport.run(c));
         Self {
             queries_tx,
+            cancellations_tx,
+            probes_tx,
             logs_tx,
+            events_tx,
+            peer_rtts,
+            subscribed_topics,
+            allocations: Arc::new(Mutex::new(allocations)),
+            budget: Default::default(),
             _task_manager: Arc::new(task_manager),
         }
     }
-    pub fn send_query(&self, peer_id: PeerId, query: Query) -> Result<(), QueueFull> {
+
+    /// Queue the query, charging `cost` computation units to the query budget if one is set
+    /// with `set_query_budget`. Nothing is charged if the query can't be queued.
+    pub fn send_query(
+        &self,
+        peer_id: PeerId,
+        query: Query,
+        cost: u64,
+    ) -> Result<(), SendQueryError> {
+        let mut budget = self.budget.lock().expect("lock poisoned");
+        if let Some(budget) = budget.as_mut() {
+            budget.try_charge(cost)?;
+        }
         log::debug!("Queueing query {query:?}");
-        self.queries_tx.try_send((peer_id, query))
+        self.queries_tx.try_send((peer_id, query)).map_err(|e| {
+            if let Some(budget) = budget.as_mut() {
+                budget.refund(cost);
+            }
+            e.into()
+        })
+    }
+
+    /// Limit queries sent with `send_query`. `None` removes the limit.
+    pub fn set_query_budget(&self, budget: Option<QueryBudget>) {
+        *self.budget.lock().expect("lock poisoned") = budget;
+    }
+
+    /// Reset the computation units of the query budget on epoch transition
+    pub fn reset_budget_epoch(&self, epoch_units: u64) {
+        if let Some(budget) = self.budget.lock().expect("lock poisoned").as_mut() {
+            budget.reset_epoch(epoch_units);
+        }
+    }
+
+    /// Computation units left in the query budget, if one is set
+    pub fn remaining_budget(&self) -> Option<u64> {
+        self.budget
+            .lock()
+            .expect("lock poisoned")
+            .as_ref()
+            .map(QueryBudget::remaining_units)
+    }
+
+    /// Stop waiting for the result of the query, freeing its slot in the outbound buffer.
+    /// The query protocol has no cancellation message, so the worker isn't notified.
+    pub fn cancel_query(&self, query_id: String) -> Result<(), QueueFull> {
+        log::debug!("Queueing cancellation of query {query_id}");
+        self.cancellations_tx.try_send(query_id)
     }
 
     pub fn query_submitted(&self, msg: QuerySubmitted) -> Result<(), QueueFull> {
@@ -310,22 +627,319 @@ impl GatewayTransportHandle {
         let msg = gateway_log_msg::Msg::QueryFinished(msg).into();
         self.logs_tx.try_send(msg)
     }
+
+    /// Update the gateway's allocations (e.g. from `Client::current_allocations`) used to
+    /// detect low remaining allocation. Consumption tracking is reset on epoch change.
+    pub fn update_allocations(&self, epoch: u32, allocations: &[Allocation]) {
+        self.allocations
+            .lock()
+            .expect("lock poisoned")
+            .set_allocations(epoch, allocations)
+    }
+
+    /// Record computation units consumed by a query to the worker. Emits
+    /// `GatewayEvent::AllocationLow` when the remaining allocation drops below the threshold.
+    pub fn record_consumed(&self, worker_id: PeerId, units: u64) {
+        let ev = self.allocations.lock().expect("lock poisoned").consume(worker_id, units);
+        if let Some(ev) = ev {
+            log::info!("Allocation running low: {ev:?}");
+            self.events_tx.send_lossy(ev);
+        }
+    }
+
+    /// Smoothed round-trip time to the peer, if it's connected and has been pinged
+    pub fn peer_rtt(&self, peer_id: PeerId) -> Option<Duration> {
+        self.peer_rtts.get(&peer_id)
+    }
+
+    /// Names of the gossipsub topics the node is subscribed to, including subscriptions
+    /// made after startup
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.subscribed_topics.get()
+    }
+
+    /// Check if the peer is reachable, dialing it if needed. Unreachable peers are reported
+    /// after `BaseConfig::probe_timeout`.
+    /// Returns `None` if the transport is not running or overloaded.
+    pub async fn probe_peer(&self, peer_id: PeerId) -> Option<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.probes_tx.try_send((peer_id, tx)).ok()?;
+        rx.await.ok()
+    }
+}
+
+/// Result of probing a single worker with `check_workers_health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkerHealth {
+    pub peer_id: PeerId,
+    /// `None` if the probe couldn't be started, e.g. because the transport is overloaded
+    pub reachable: Option<bool>,
+    /// Time it took to confirm reachability (including dialing) or to give up
+    pub probe_time: Duration,
+    /// Round-trip time measured by ping, if it's available already
+    pub rtt: Option<Duration>,
+}
+
+/// Summary of a `check_workers_health` run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthSummary {
+    pub total: usize,
+    pub reachable: usize,
+    pub unreachable: usize,
+    /// Workers which couldn't be probed
+    pub unknown: usize,
+    pub median_rtt: Option<Duration>,
+}
+
+impl HealthSummary {
+    pub fn new(results: &[WorkerHealth]) -> Self {
+        let count = |reachable| results.iter().filter(|r| r.reachable == reachable).count();
+        let mut rtts: Vec<_> = results.iter().filter_map(|r| r.rtt).collect();
+        rtts.sort();
+        Self {
+            total: results.len(),
+            reachable: count(Some(true)),
+            unreachable: count(Some(false)),
+            unknown: count(None),
+            median_rtt: rtts.get(rtts.len() / 2).copied(),
+        }
+    }
+}
+
+/// Probe all `workers` (e.g. `contract_client::Client::active_workers`), at most
+/// `max_concurrent` at a time. Workers which don't respond within `timeout` are reported
+/// as unreachable.
+pub async fn check_workers_health(
+    handle: &GatewayTransportHandle,
+    workers: impl IntoIterator<Item = PeerId>,
+    max_concurrent: usize,
+    timeout: Duration,
+) -> Vec<WorkerHealth> {
+    stream::iter(workers)
+        .map(|peer_id| async move {
+            let start = Instant::now();
+            let reachable = match tokio::time::timeout(timeout, handle.probe_peer(peer_id)).await {
+                Ok(reachable) => reachable,
+                Err(_) => Some(false),
+            };
+            WorkerHealth {
+                peer_id,
+                reachable,
+                probe_time: start.elapsed(),
+                rtt: handle.peer_rtt(peer_id),
+            }
+        })
+        .buffer_unordered(max_concurrent.max(1))
+        .collect()
+        .await
 }
 
 pub fn start_transport(
     swarm: Swarm<Wrapped<GatewayBehaviour>>,
     config: GatewayConfig,
-) -> (impl Stream<Item = GatewayEvent>, GatewayTransportHandle) {
+) -> (Receiver<GatewayEvent>, GatewayTransportHandle) {
     let (queries_tx, queries_rx) = new_queue(config.queries_queue_size, "queries");
+    let (cancellations_tx, cancellations_rx) =
+        new_queue(config.queries_queue_size, "cancellations");
+    let (probes_tx, probes_rx) = new_queue(config.probes_queue_size, "probes");
     let (logs_tx, logs_rx) = new_queue(config.logs_queue_size, "logs");
     let (events_tx, events_rx) = new_queue(config.events_queue_size, "events");
     let transport = GatewayTransport {
         swarm,
         queries_rx,
+        cancellations_rx,
+        probes_rx,
         logs_rx,
-        events_tx,
+        events_tx: events_tx.clone(),
     };
-    let handle =
-        GatewayTransportHandle::new(queries_tx, logs_tx, transport, config.shutdown_timeout);
+    let handle = GatewayTransportHandle::new(
+        queries_tx,
+        cancellations_tx,
+        probes_tx,
+        logs_tx,
+        events_tx,
+        transport,
+        &config,
+    );
     (events_rx, handle)
 }
+
+/// Select the candidate for `key` using highest-random-weight (rendezvous) hashing.
+/// The same key always maps to the same candidate, and removing a candidate only
+/// reroutes the keys which were mapped to it.
+pub fn consistent_select(candidates: &[PeerId], key: u64) -> Option<PeerId> {
+    candidates
+        .iter()
+        .copied()
+        .max_by_key(|peer_id| (rendezvous_weight(peer_id, key), *peer_id))
+}
+
+fn rendezvous_weight(peer_id: &PeerId, key: u64) -> u64 {
+    // FNV-1a of the peer ID, mixed with the key using the SplitMix64 finalizer
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in peer_id.to_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    let mut x = hash ^ key;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_summary() {
+        let health = |reachable, rtt_ms: Option<u64>| WorkerHealth {
+            peer_id: PeerId::random(),
+            reachable,
+            probe_time: Duration::ZERO,
+            rtt: rtt_ms.map(Duration::from_millis),
+        };
+        let results = vec![
+            health(Some(true), Some(30)),
+            health(Some(true), Some(10)),
+            health(Some(true), Some(20)),
+            health(Some(false), None),
+            health(None, None),
+        ];
+        assert_eq!(
+            HealthSummary::new(&results),
+            HealthSummary {
+                total: 5,
+                reachable: 3,
+                unreachable: 1,
+                unknown: 1,
+                median_rtt: Some(Duration::from_millis(20)),
+            }
+        );
+        assert_eq!(HealthSummary::new(&[]), HealthSummary::default());
+    }
+
+    #[test]
+    fn test_stripped_query_verifies_on_old_workers() {
+        use libp2p::identity::Keypair;
+        use subsquid_messages::signatures::SignedMessage;
+
+        let keypair = Keypair::generate_ed25519();
+        let gateway_id = keypair.public().to_peer_id();
+        // Old workers drop unknown fields when decoding, before verifying the signature
+        let decoded_by_old_worker = |query: &Query| Query {
+            accepted_compression: Vec::new(),
+            result_format: None,
+            ..query.clone()
+        };
+        let mut query = Query {
+            query_id: Some("query".to_string()),
+            query: Some("{}".to_string()),
+            accepted_compression: vec![Compression::Zstd.to_string()],
+            result_format: Some("arrow".to_string()),
+            ..Default::default()
+        };
+
+        let mut with_fields = query.clone();
+        with_fields.sign(&keypair);
+        assert!(with_fields.clone().verify_signature(&gateway_id));
+        assert!(!decoded_by_old_worker(&with_fields).verify_signature(&gateway_id));
+
+        strip_negotiation_fields(&mut query);
+        query.sign(&keypair);
+        assert!(query.clone().verify_signature(&gateway_id));
+        assert!(decoded_by_old_worker(&query).verify_signature(&gateway_id));
+    }
+
+    #[test]
+    fn test_allocation_tracker() {
+        let worker = PeerId::random();
+        let allocations = vec![Allocation {
+            worker_peer_id: worker,
+            worker_onchain_id: U256::one(),
+            computation_units: U256::from(100),
+        }];
+        let mut tracker = AllocationTracker::new(10);
+        assert!(tracker.consume(PeerId::random(), 10).is_none());
+
+        tracker.set_allocations(1, &allocations);
+        assert!(tracker.consume(worker, 89).is_none());
+        assert_eq!(
+            tracker.consume(worker, 1),
+            Some(GatewayEvent::AllocationLow {
+                worker,
+                remaining_pct: 10
+            })
+        );
+        // Reported once per epoch
+        assert!(tracker.consume(worker, 5).is_none());
+        tracker.set_allocations(1, &allocations);
+        assert!(tracker.consume(worker, 1).is_none());
+
+        // Reset on epoch change
+        tracker.set_allocations(2, &allocations);
+        assert!(tracker.consume(worker, 50).is_none());
+        assert_eq!(
+            tracker.consume(worker, 45),
+            Some(GatewayEvent::AllocationLow {
+                worker,
+                remaining_pct: 5
+            })
+        );
+    }
+
+    #[test]
+    fn test_query_budget() {
+        let start = Instant::now();
+        let mut budget = QueryBudget::new(100, 2);
+        budget.last_refill = start;
+
+        assert_eq!(budget.try_charge_at(40, start), Ok(()));
+        assert_eq!(budget.try_charge_at(40, start), Ok(()));
+        assert_eq!(budget.try_charge_at(1, start), Err(BudgetError::RateLimited));
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            budget.try_charge_at(40, later),
+            Err(BudgetError::Exhausted {
+                remaining: 20,
+                cost: 40
+            })
+        );
+        assert_eq!(budget.try_charge_at(20, later), Ok(()));
+        assert_eq!(budget.remaining_units(), 0);
+
+        budget.reset_epoch(50);
+        assert_eq!(budget.try_charge_at(50, later), Ok(()));
+        assert_eq!(budget.try_charge_at(0, later), Err(BudgetError::RateLimited));
+
+        budget.refund(50);
+        assert_eq!(budget.remaining_units(), 50);
+        assert_eq!(budget.try_charge_at(50, later), Ok(()));
+    }
+
+    #[test]
+    fn test_consistent_select() {
+        assert_eq!(consistent_select(&[], 1), None);
+
+        let candidates: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        let before: Vec<PeerId> =
+            (0..1000).map(|key| consistent_select(&candidates, key).unwrap()).collect();
+        for (key, selected) in before.iter().enumerate() {
+            assert_eq!(consistent_select(&candidates, key as u64), Some(*selected));
+        }
+        assert!(candidates.iter().all(|peer_id| before.contains(peer_id)));
+
+        let removed = candidates[2];
+        let remaining: Vec<PeerId> =
+            candidates.iter().copied().filter(|peer_id| *peer_id != removed).collect();
+        for (key, selected) in before.into_iter().enumerate() {
+            let after = consistent_select(&remaining, key as u64).unwrap();
+            if selected == removed {
+                assert_ne!(after, removed);
+            } else {
+                assert_eq!(after, selected);
+            }
+        }
+    }
+}
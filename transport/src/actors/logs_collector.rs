@@ -1,7 +1,6 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use futures::StreamExt;
-use futures_core::Stream;
 use libp2p::{
     swarm::{NetworkBehaviour, SwarmEvent, ToSwarm},
     PeerId, Swarm,
@@ -12,17 +11,20 @@ use tokio_util::sync::CancellationToken;
 
 use subsquid_messages::{
     gateway_log_msg, signatures::SignedMessage, GatewayLogMsg, LogsCollected, QueryExecuted,
-    QueryFinished, QueryLogs, QuerySubmitted,
+    QueryFinished, QueryLogs, QuerySubmitted, WorkerLogsAck,
 };
 
 use crate::{
     behaviour::{
         base::{BaseBehaviour, BaseBehaviourEvent},
+        pubsub::SubscribedTopics,
         request_server::{Request, ServerBehaviour},
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
-    codec::{ProtoCodec, ACK_SIZE},
-    protocol::{GATEWAY_LOGS_PROTOCOL, MAX_GATEWAY_LOG_SIZE},
+    codec::{ProtoCodec, ACK_SIZE, WORKER_LOGS_ACK_SIZE},
+    protocol::{
+        GATEWAY_LOGS_PROTOCOL, MAX_GATEWAY_LOG_SIZE, MAX_WORKER_LOGS_SIZE, WORKER_LOGS_PROTOCOL,
+    },
     record_event,
     util::{new_queue, Receiver, Sender, TaskManager, DEFAULT_SHUTDOWN_TIMEOUT},
     QueueFull,
@@ -45,11 +47,13 @@ pub enum LogsCollectorEvent {
 pub struct InnerBehaviour {
     base: Wrapped<BaseBehaviour>,
     gateway_logs: Wrapped<ServerBehaviour<ProtoCodec<GatewayLogMsg, u32>>>,
+    worker_logs: Wrapped<ServerBehaviour<ProtoCodec<QueryLogs, WorkerLogsAck>>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogsCollectorConfig {
     pub max_gateway_log_size: u64,
+    pub max_worker_logs_size: u64,
     pub logs_collected_queue_size: usize,
     pub events_queue_size: usize,
     pub shutdown_timeout: Duration,
@@ -59,6 +63,7 @@ impl Default for LogsCollectorConfig {
     fn default() -> Self {
         Self {
             max_gateway_log_size: MAX_GATEWAY_LOG_SIZE,
+            max_worker_logs_size: MAX_WORKER_LOGS_SIZE,
             logs_collected_queue_size: 100,
             events_queue_size: 100,
             shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
@@ -68,6 +73,8 @@ impl Default for LogsCollectorConfig {
 
 pub struct LogsCollectorBehaviour {
     inner: InnerBehaviour,
+    // Last saved sequence numbers, used to acknowledge logs sent by workers
+    last_collected: HashMap<PeerId, u64>,
 }
 
 impl LogsCollectorBehaviour {
@@ -82,7 +89,13 @@ impl LogsCollectorBehaviour {
                     GATEWAY_LOGS_PROTOCOL,
                 )
                 .into(),
+                worker_logs: ServerBehaviour::new(
+                    ProtoCodec::new(config.max_worker_logs_size, WORKER_LOGS_ACK_SIZE),
+                    WORKER_LOGS_PROTOCOL,
+                )
+                .into(),
             },
+            last_collected: Default::default(),
         }
         .into()
     }
@@ -100,6 +113,23 @@ impl LogsCollectorBehaviour {
         (!logs.is_empty()).then_some(LogsCollectorEvent::WorkerLogs { peer_id, logs })
     }
 
+    fn on_worker_logs_request(
+        &mut self,
+        Request {
+            peer_id,
+            request,
+            response_channel,
+        }: Request<QueryLogs, WorkerLogsAck>,
+    ) -> Option<LogsCollectorEvent> {
+        // Acknowledge the logs which have already been saved. Logs from this request will be
+        // acknowledged with the next one, after they are reported by `logs_collected`.
+        let ack = WorkerLogsAck {
+            last_seq_no: self.last_collected.get(&peer_id).copied(),
+        };
+        _ = self.inner.worker_logs.try_send_response(response_channel, ack);
+        self.on_worker_logs(peer_id, request)
+    }
+
     fn on_gateway_log(
         &mut self,
         peer_id: PeerId,
@@ -122,6 +152,12 @@ impl LogsCollectorBehaviour {
     }
 
     pub fn logs_collected(&mut self, logs_collected: LogsCollected) {
+        for (peer_id, seq_no) in logs_collected.sequence_numbers.iter() {
+            match peer_id.parse() {
+                Ok(peer_id) => _ = self.last_collected.insert(peer_id, *seq_no),
+                Err(_) => log::warn!("Invalid peer ID in logs collected message: {peer_id}"),
+            }
+        }
         self.inner.base.publish_logs_collected(logs_collected)
     }
 }
@@ -151,6 +187,7 @@ impl BehaviourWrapper for LogsCollectorBehaviour {
                 _ = self.inner.gateway_logs.try_send_response(response_channel, 1);
                 self.on_gateway_log(peer_id, request)
             }
+            InnerBehaviourEvent::WorkerLogs(req) => self.on_worker_logs_request(req),
             _ => None,
         };
         ev.map(ToSwarm::GenerateEvent)
@@ -188,6 +225,7 @@ impl LogsCollectorTransport {
 #[derive(Clone)]
 pub struct LogsCollectorTransportHandle {
     logs_collected_tx: Sender<LogsCollected>,
+    subscribed_topics: SubscribedTopics,
     _task_manager: Arc<TaskManager>,
 }
 
@@ -197,10 +235,12 @@ impl LogsCollectorTransportHandle {
         transport: LogsCollectorTransport,
         shutdown_timeout: Duration,
     ) -> Self {
+        let subscribed_topics = transport.swarm.behaviour().inner.base.subscribed_topics();
         let mut task_manager = TaskManager::new(shutdown_timeout);
         task_manager.spawn(|c| transport.run(c));
         Self {
             logs_collected_tx,
+            subscribed_topics,
             _task_manager: Arc::new(task_manager),
         }
     }
@@ -208,12 +248,18 @@ impl LogsCollectorTransportHandle {
         log::debug!("Queueing LogsCollected message: {logs_collected:?}");
         self.logs_collected_tx.try_send(logs_collected)
     }
+
+    /// Names of the gossipsub topics the node is subscribed to, including subscriptions
+    /// made after startup
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.subscribed_topics.get()
+    }
 }
 
 pub fn start_transport(
     swarm: Swarm<Wrapped<LogsCollectorBehaviour>>,
     config: LogsCollectorConfig,
-) -> (impl Stream<Item = LogsCollectorEvent>, LogsCollectorTransportHandle) {
+) -> (Receiver<LogsCollectorEvent>, LogsCollectorTransportHandle) {
     let (logs_collected_tx, logs_collected_rx) =
         new_queue(config.logs_collected_queue_size, "logs_collected");
     let (events_tx, events_rx) = new_queue(config.events_queue_size, "events");
@@ -1,23 +1,40 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use futures::StreamExt;
+use futures::{
+    stream::{self, FusedStream},
+    StreamExt,
+};
 use futures_core::Stream;
 use libp2p::{
-    swarm::{NetworkBehaviour, SwarmEvent, ToSwarm},
-    PeerId, Swarm,
+    multiaddr::Protocol,
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, NetworkBehaviour, SwarmEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, Swarm,
 };
 use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{oneshot, watch},
+    time::Instant,
+};
 use tokio_util::sync::CancellationToken;
 
-use subsquid_messages::{LogsCollected, Ping, QueryLogs};
+use subsquid_messages::{LogsCollected, Ping, QueryLogs, RangeSet, WorkerState, WorkerStats};
 
 use crate::{
     behaviour::{
         base::{BaseBehaviour, BaseBehaviourEvent},
+        pubsub::SubscribedTopics,
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
     record_event,
-    util::{new_queue, Sender, TaskManager, DEFAULT_SHUTDOWN_TIMEOUT},
+    util::{new_queue, Receiver, Sender, TaskManager, DEFAULT_SHUTDOWN_TIMEOUT},
+    ProbeError, RelayDialError,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -31,13 +48,23 @@ pub enum ObserverEvent {
         peer_id: PeerId,
         query_logs: QueryLogs,
     },
+    /// Outcome of a dial started with `ObserverTransportHandle::dial_via_relay`
+    RelayDialed {
+        peer_id: PeerId,
+        relay: Multiaddr,
+        result: Result<(), String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObserverConfig {
     pub logs_collector_id: PeerId,
     pub events_queue_size: usize,
+    pub probe_requests_queue_size: usize,
+    pub relay_dials_queue_size: usize,
     pub shutdown_timeout: Duration,
+    /// Workers which haven't sent a ping for this long are evicted from the worker states
+    pub worker_state_max_age: Duration,
 }
 
 impl ObserverConfig {
@@ -45,14 +72,272 @@ impl ObserverConfig {
         Self {
             logs_collector_id,
             events_queue_size: 100,
+            probe_requests_queue_size: 10,
+            relay_dials_queue_size: 10,
             shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            worker_state_max_age: Duration::from_secs(120),
+        }
+    }
+}
+
+const WORKER_STATES_EVICTION_INTERVAL: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_LATENCY_BUCKETS_MS: [u64; 12] =
+    [10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000, 60000];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    pub snapshot_interval: Duration,
+    /// Upper bounds of query latency histogram buckets (in milliseconds)
+    pub latency_buckets_ms: Vec<u64>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: Duration::from_secs(60),
+            latency_buckets_ms: DEFAULT_LATENCY_BUCKETS_MS.to_vec(),
+        }
+    }
+}
+
+/// Histogram of query execution times with fixed bucket boundaries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    /// Inclusive upper bounds of the buckets, in milliseconds
+    pub bounds_ms: Vec<u64>,
+    /// Number of queries in each bucket. The last one counts queries above the highest bound.
+    pub counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new(mut bounds_ms: Vec<u64>) -> Self {
+        bounds_ms.sort();
+        bounds_ms.dedup();
+        let counts = vec![0; bounds_ms.len() + 1];
+        Self { bounds_ms, counts }
+    }
+
+    pub fn record(&mut self, latency_ms: u64) {
+        let bucket = self.bounds_ms.partition_point(|bound| *bound < latency_ms);
+        self.counts[bucket] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket containing the `q`-quantile (0.0..=1.0) of recorded latencies.
+    /// `u64::MAX` means the quantile is above the highest bucket bound.
+    /// Returns `None` if no latencies were recorded.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(self.bounds_ms.get(bucket).copied().unwrap_or(u64::MAX));
+            }
+        }
+        unreachable!("rank doesn't exceed total count")
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            p50_ms: self.quantile(0.5),
+            p95_ms: self.quantile(0.95),
+            p99_ms: self.quantile(0.99),
+        }
+    }
+}
+
+/// Query latency percentiles, approximated by histogram bucket bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+}
+
+/// Per-worker query statistics aggregated over a single epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochStats {
+    /// On-chain epoch number
+    pub epoch: u32,
+    pub workers: HashMap<PeerId, WorkerStats>,
+    pub latencies: HashMap<PeerId, LatencyHistogram>,
+    /// The epoch has ended, no more updates to it will be emitted.
+    pub is_final: bool,
+}
+
+impl EpochStats {
+    fn new(epoch: u32) -> Self {
+        Self {
+            epoch,
+            workers: Default::default(),
+            latencies: Default::default(),
+            is_final: false,
+        }
+    }
+
+    pub fn latency_summaries(&self) -> HashMap<PeerId, LatencySummary> {
+        self.latencies
+            .iter()
+            .map(|(peer_id, hist)| (*peer_id, hist.summary()))
+            .collect()
+    }
+}
+
+struct StatsAggregator {
+    latency_buckets_ms: Vec<u64>,
+    current: Option<EpochStats>,
+    ready: VecDeque<EpochStats>,
+}
+
+impl StatsAggregator {
+    fn new(config: &StatsConfig) -> Self {
+        Self {
+            latency_buckets_ms: config.latency_buckets_ms.clone(),
+            current: None,
+            ready: Default::default(),
+        }
+    }
+
+    /// Queries are assigned to the epoch which is current when their logs are received.
+    /// Timestamps reported by workers are not used, so that a skewed clock can't move stats
+    /// between epochs.
+    fn add_logs(&mut self, peer_id: PeerId, query_logs: &QueryLogs) {
+        let Some(stats) = self.current.as_mut() else {
+            let num_queries = query_logs.queries_executed.len();
+            log::warn!(
+                "Dropping {num_queries} query logs from {peer_id} received before the current epoch is known"
+            );
+            return;
+        };
+        for query in query_logs.queries_executed.iter() {
+            stats.workers.entry(peer_id).or_default().add_query(query);
+            if let Some(exec_time_ms) = query.exec_time_ms {
+                stats
+                    .latencies
+                    .entry(peer_id)
+                    .or_insert_with(|| LatencyHistogram::new(self.latency_buckets_ms.clone()))
+                    .record(exec_time_ms as u64);
+            }
+        }
+    }
+
+    /// Finalize the current epoch if `epoch` is a later one.
+    fn advance_to(&mut self, epoch: u32) {
+        if matches!(&self.current, Some(stats) if stats.epoch >= epoch) {
+            return;
+        }
+        if let Some(mut stats) = self.current.replace(EpochStats::new(epoch)) {
+            stats.is_final = true;
+            self.ready.push_back(stats);
+        }
+    }
+
+    fn tick(&mut self) {
+        if let Some(stats) = &self.current {
+            self.ready.push_back(stats.clone());
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut stats) = self.current.take() {
+            stats.is_final = true;
+            self.ready.push_back(stats);
+        }
+    }
+}
+
+/// Aggregate query logs from observer `events` into per-epoch, per-worker statistics
+/// and query latency histograms. Epoch transitions are taken from `epochs`, e.g.
+/// `EpochWatcher::into_stream`, and queries are attributed to the epoch which is current
+/// when they are received. A snapshot of the current epoch is emitted every
+/// `snapshot_interval`, and a final one when the epoch ends.
+pub fn aggregated_stream(
+    events: impl Stream<Item = ObserverEvent> + Send + 'static,
+    epochs: impl Stream<Item = u32> + Send + 'static,
+    config: StatsConfig,
+) -> impl Stream<Item = EpochStats> {
+    let interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + config.snapshot_interval,
+        config.snapshot_interval,
+    );
+    let aggregator = StatsAggregator::new(&config);
+    let state = (Box::pin(events.fuse()), Box::pin(epochs.fuse()), interval, aggregator);
+    stream::unfold(state, |(mut events, mut epochs, mut interval, mut aggregator)| async move {
+        loop {
+            if let Some(stats) = aggregator.ready.pop_front() {
+                return Some((stats, (events, epochs, interval, aggregator)));
+            }
+            if events.is_terminated() {
+                return None;
+            }
+            tokio::select! {
+                // Epochs are applied before the logs received at the same time
+                biased;
+                Some(epoch) = epochs.next() => aggregator.advance_to(epoch),
+                ev = events.next() => match ev {
+                    Some(ObserverEvent::WorkerQueryLogs { peer_id, query_logs }) => {
+                        aggregator.add_logs(peer_id, &query_logs)
+                    }
+                    Some(_) => {}
+                    None => aggregator.finish(),
+                },
+                _ = interval.tick() => aggregator.tick(),
+            }
+        }
+    })
+}
+
+/// Index of data ranges held by each live worker, grouped by dataset
+#[derive(Debug, Default)]
+struct CoverageIndex {
+    datasets: HashMap<String, HashMap<PeerId, RangeSet>>,
+}
+
+impl CoverageIndex {
+    fn update(&mut self, peer_id: PeerId, state: &WorkerState) {
+        self.remove(&peer_id);
+        for (dataset, ranges) in state.iter() {
+            self.datasets
+                .entry(dataset.clone())
+                .or_default()
+                .insert(peer_id, ranges.clone());
         }
     }
+
+    fn remove(&mut self, peer_id: &PeerId) {
+        self.datasets.retain(|_, workers| {
+            workers.remove(peer_id);
+            !workers.is_empty()
+        });
+    }
+
+    fn coverage(&self, dataset: &str, block: u32) -> Vec<PeerId> {
+        let Some(workers) = self.datasets.get(dataset) else {
+            return vec![];
+        };
+        workers
+            .iter()
+            .filter(|(_, ranges)| ranges.has(block))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
 }
 
+type ProbeResultSender = oneshot::Sender<Result<PeerId, ProbeError>>;
+
 pub struct ObserverBehaviour {
     base: Wrapped<BaseBehaviour>,
     logs_collector_id: PeerId,
+    addr_probes: HashMap<ConnectionId, ProbeResultSender>,
 }
 
 impl ObserverBehaviour {
@@ -64,10 +349,16 @@ impl ObserverBehaviour {
         Self {
             base: base.into(),
             logs_collector_id,
+            addr_probes: Default::default(),
         }
         .into()
     }
 
+    pub fn probe_addr(&mut self, addr: Multiaddr, keep: bool, result_tx: ProbeResultSender) {
+        let connection_id = self.base.probe_addr(addr, keep);
+        self.addr_probes.insert(connection_id, result_tx);
+    }
+
     fn on_base_event(&mut self, ev: BaseBehaviourEvent) -> Option<ObserverEvent> {
         match ev {
             BaseBehaviourEvent::LogsCollected {
@@ -86,6 +377,15 @@ impl ObserverBehaviour {
                 peer_id,
                 query_logs,
             }),
+            BaseBehaviourEvent::AddrProbed {
+                connection_id,
+                result,
+            } => {
+                if let Some(result_tx) = self.addr_probes.remove(&connection_id) {
+                    _ = result_tx.send(result);
+                }
+                None
+            }
             _ => None,
         }
     }
@@ -107,18 +407,48 @@ impl BehaviourWrapper for ObserverBehaviour {
     }
 }
 
+#[derive(Debug)]
+struct ProbeRequest {
+    addr: Multiaddr,
+    keep: bool,
+    result_tx: ProbeResultSender,
+}
+
+#[derive(Debug)]
+struct RelayDialRequest {
+    peer_id: PeerId,
+    relay: Multiaddr,
+    result_tx: oneshot::Sender<Result<(), RelayDialError>>,
+}
+
 struct ObserverTransport {
     swarm: Swarm<Wrapped<ObserverBehaviour>>,
+    probe_requests_rx: Receiver<ProbeRequest>,
+    relay_dials_rx: Receiver<RelayDialRequest>,
+    relay_dials: HashMap<ConnectionId, (PeerId, Multiaddr)>,
     events_tx: Sender<ObserverEvent>,
+    worker_states_tx: watch::Sender<HashMap<PeerId, WorkerState>>,
+    coverage: Arc<RwLock<CoverageIndex>>,
+    last_pings: HashMap<PeerId, Instant>,
+    worker_state_max_age: Duration,
 }
 
 impl ObserverTransport {
     pub async fn run(mut self, cancel_token: CancellationToken) {
         log::info!("Starting observer P2P transport");
+        let mut eviction_interval = tokio::time::interval(WORKER_STATES_EVICTION_INTERVAL);
         loop {
             tokio::select! {
                 _ = cancel_token.cancelled() => break,
                 ev = self.swarm.select_next_some() => self.on_swarm_event(ev),
+                _ = eviction_interval.tick() => self.evict_worker_states(),
+                Some(req) = self.probe_requests_rx.recv() => {
+                    self.swarm.behaviour_mut().probe_addr(req.addr, req.keep, req.result_tx)
+                }
+                Some(req) = self.relay_dials_rx.recv() => {
+                    let result = self.dial_via_relay(req.peer_id, req.relay);
+                    _ = req.result_tx.send(result);
+                }
             }
         }
         log::info!("Shutting down observer P2P transport");
@@ -127,33 +457,342 @@ impl ObserverTransport {
     fn on_swarm_event(&mut self, ev: SwarmEvent<ObserverEvent>) {
         log::trace!("Swarm event: {ev:?}");
         record_event(&ev);
-        if let SwarmEvent::Behaviour(ev) = ev {
-            self.events_tx.send_lossy(ev)
+        match ev {
+            SwarmEvent::Behaviour(ev) => {
+                if let ObserverEvent::Ping { peer_id, ping } = &ev {
+                    self.update_worker_state(*peer_id, ping);
+                }
+                self.events_tx.send_lossy(ev)
+            }
+            SwarmEvent::ConnectionEstablished { connection_id, .. } => {
+                self.finish_relay_dial(connection_id, Ok(()))
+            }
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                error,
+                ..
+            } => self.finish_relay_dial(connection_id, Err(error.to_string())),
+            _ => {}
+        }
+    }
+
+    fn dial_via_relay(&mut self, peer_id: PeerId, relay: Multiaddr) -> Result<(), RelayDialError> {
+        if !self.swarm.behaviour().base.relay_enabled() {
+            return Err(RelayDialError::RelayNotEnabled);
+        }
+        let Some(Protocol::P2p(relay_id)) = relay.iter().last() else {
+            return Err(RelayDialError::InvalidRelayAddr(format!(
+                "{relay} doesn't end with the relay's peer ID"
+            )));
+        };
+        if relay_id == peer_id {
+            return Err(RelayDialError::InvalidRelayAddr(format!(
+                "{relay} is the address of the dialed peer"
+            )));
+        }
+        log::debug!("Dialing {peer_id} via relay {relay}");
+        let addr = relay.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id));
+        let opts = DialOpts::peer_id(peer_id)
+            .addresses(vec![addr])
+            .condition(PeerCondition::Always)
+            .build();
+        let connection_id = opts.connection_id();
+        self.swarm.dial(opts).map_err(|e| RelayDialError::Dial(e.to_string()))?;
+        self.relay_dials.insert(connection_id, (peer_id, relay));
+        Ok(())
+    }
+
+    fn finish_relay_dial(&mut self, connection_id: ConnectionId, result: Result<(), String>) {
+        if let Some((peer_id, relay)) = self.relay_dials.remove(&connection_id) {
+            log::debug!("Dialing {peer_id} via relay {relay} finished: {result:?}");
+            self.events_tx.send_lossy(ObserverEvent::RelayDialed {
+                peer_id,
+                relay,
+                result,
+            })
+        }
+    }
+
+    fn update_worker_state(&mut self, peer_id: PeerId, ping: &Ping) {
+        let state = match WorkerState::try_from(ping) {
+            Ok(state) => state,
+            Err(e) => return log::warn!("Invalid worker state in ping from {peer_id}: {e}"),
+        };
+        self.last_pings.insert(peer_id, Instant::now());
+        let modified = self.worker_states_tx.send_if_modified(|states| {
+            states.insert(peer_id, state.clone()).as_ref() != Some(&state)
+        });
+        if modified {
+            self.coverage.write().expect("lock poisoned").update(peer_id, &state);
         }
     }
+
+    fn evict_worker_states(&mut self) {
+        let max_age = self.worker_state_max_age;
+        self.last_pings.retain(|peer_id, last_ping| {
+            let live = last_ping.elapsed() < max_age;
+            if !live {
+                log::debug!("Evicting state of worker {peer_id}");
+                self.coverage.write().expect("lock poisoned").remove(peer_id);
+            }
+            live
+        });
+        self.worker_states_tx.send_if_modified(|states| {
+            let len = states.len();
+            states.retain(|peer_id, _| self.last_pings.contains_key(peer_id));
+            states.len() != len
+        });
+    }
 }
 
 #[derive(Clone)]
 pub struct ObserverTransportHandle {
+    probe_requests_tx: Sender<ProbeRequest>,
+    relay_dials_tx: Sender<RelayDialRequest>,
+    worker_states_rx: watch::Receiver<HashMap<PeerId, WorkerState>>,
+    coverage: Arc<RwLock<CoverageIndex>>,
+    subscribed_topics: SubscribedTopics,
     _task_manager: Arc<TaskManager>,
 }
 
 impl ObserverTransportHandle {
-    fn new(transport: ObserverTransport, shutdown_timeout: Duration) -> Self {
+    fn new(
+        probe_requests_tx: Sender<ProbeRequest>,
+        relay_dials_tx: Sender<RelayDialRequest>,
+        transport: ObserverTransport,
+        worker_states_rx: watch::Receiver<HashMap<PeerId, WorkerState>>,
+        shutdown_timeout: Duration,
+    ) -> Self {
+        let coverage = transport.coverage.clone();
+        let subscribed_topics = transport.swarm.behaviour().base.subscribed_topics();
         let mut task_manager = TaskManager::new(shutdown_timeout);
         task_manager.spawn(|c| transport.run(c));
         Self {
+            probe_requests_tx,
+            relay_dials_tx,
+            worker_states_rx,
+            coverage,
+            subscribed_topics,
             _task_manager: Arc::new(task_manager),
         }
     }
+
+    /// Latest state reported in pings by each live worker
+    pub fn worker_states_snapshot(&self) -> HashMap<PeerId, WorkerState> {
+        self.worker_states_rx.borrow().clone()
+    }
+
+    /// Dial `addr` and return the ID of the peer behind it, learned with identify.
+    /// Unless `keep` is set, the connection is closed afterwards and the peer is not kept
+    /// in the routing table.
+    pub async fn probe_peer(&self, addr: Multiaddr, keep: bool) -> Result<PeerId, ProbeError> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let req = ProbeRequest {
+            addr,
+            keep,
+            result_tx,
+        };
+        self.probe_requests_tx.try_send(req).map_err(|_| ProbeError::QueueFull)?;
+        result_rx.await.map_err(|_| ProbeError::TransportStopped)?
+    }
+
+    /// Dial `peer_id` through the circuit relay at `relay`, which has to end with
+    /// the relay's peer ID. Returns once the dial is started, its outcome is reported
+    /// with `ObserverEvent::RelayDialed`.
+    ///
+    /// Requires the relay client transport, i.e. the transport has to be built with
+    /// `P2PTransportBuilder::with_relay(true)`. Otherwise, `RelayDialError::RelayNotEnabled`
+    /// is returned.
+    pub async fn dial_via_relay(
+        &self,
+        peer_id: PeerId,
+        relay: Multiaddr,
+    ) -> Result<(), RelayDialError> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let req = RelayDialRequest {
+            peer_id,
+            relay,
+            result_tx,
+        };
+        self.relay_dials_tx.try_send(req).map_err(|_| RelayDialError::QueueFull)?;
+        result_rx.await.map_err(|_| RelayDialError::TransportStopped)?
+    }
+
+    /// Live workers which hold `block` of `dataset`, according to their latest pings
+    pub fn coverage(&self, dataset: &str, block: u32) -> Vec<PeerId> {
+        self.coverage.read().expect("lock poisoned").coverage(dataset, block)
+    }
+
+    /// Names of the gossipsub topics the node is subscribed to, including subscriptions
+    /// made after startup
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.subscribed_topics.get()
+    }
+
+    /// Stream of worker states snapshots, emitted whenever a worker's state changes
+    /// or a worker is evicted
+    pub fn worker_states_updates(&self) -> impl Stream<Item = HashMap<PeerId, WorkerState>> {
+        let mut rx = self.worker_states_rx.clone();
+        rx.mark_unchanged();
+        stream::unfold(rx, |mut rx| async move {
+            rx.changed().await.ok()?;
+            let states = rx.borrow_and_update().clone();
+            Some((states, rx))
+        })
+    }
 }
 
 pub fn start_transport(
     swarm: Swarm<Wrapped<ObserverBehaviour>>,
     config: ObserverConfig,
-) -> (impl Stream<Item = ObserverEvent>, ObserverTransportHandle) {
+) -> (Receiver<ObserverEvent>, ObserverTransportHandle) {
     let (events_tx, events_rx) = new_queue(config.events_queue_size, "events");
-    let transport = ObserverTransport { swarm, events_tx };
-    let handle = ObserverTransportHandle::new(transport, config.shutdown_timeout);
+    let (probe_requests_tx, probe_requests_rx) =
+        new_queue(config.probe_requests_queue_size, "probe_requests");
+    let (relay_dials_tx, relay_dials_rx) = new_queue(config.relay_dials_queue_size, "relay_dials");
+    let (worker_states_tx, worker_states_rx) = watch::channel(Default::default());
+    let transport = ObserverTransport {
+        swarm,
+        probe_requests_rx,
+        relay_dials_rx,
+        relay_dials: Default::default(),
+        events_tx,
+        worker_states_tx,
+        coverage: Default::default(),
+        last_pings: Default::default(),
+        worker_state_max_age: config.worker_state_max_age,
+    };
+    let handle = ObserverTransportHandle::new(
+        probe_requests_tx,
+        relay_dials_tx,
+        transport,
+        worker_states_rx,
+        config.shutdown_timeout,
+    );
     (events_rx, handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use subsquid_messages::{query_executed, InputAndOutput, QueryExecuted, Range, SizeAndHash};
+
+    use super::*;
+
+    fn query(timestamp_ms: u64, size: u32) -> QueryExecuted {
+        QueryExecuted {
+            timestamp_ms: Some(timestamp_ms),
+            exec_time_ms: Some(size * 10),
+            result: Some(query_executed::Result::Ok(InputAndOutput {
+                num_read_chunks: Some(1),
+                output: Some(SizeAndHash {
+                    size: Some(size),
+                    sha3_256: vec![],
+                    ..Default::default()
+                }),
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stats_aggregation() {
+        let worker = PeerId::random();
+        let mut aggregator = StatsAggregator::new(&StatsConfig::default());
+
+        // Nothing to attribute the logs to before the epoch is known
+        aggregator.add_logs(worker, &vec![query(0, 1)].into());
+        aggregator.tick();
+        assert!(aggregator.ready.is_empty());
+
+        aggregator.advance_to(5);
+        // Timestamps reported by the worker don't matter, only the epoch when logs are received
+        aggregator.add_logs(worker, &vec![query(10, 5), query(20_000_000, 7)].into());
+        aggregator.tick();
+        let snapshot = aggregator.ready.pop_front().unwrap();
+        assert_eq!(snapshot.epoch, 5);
+        assert!(!snapshot.is_final);
+
+        // Lower epochs don't finalize the current one
+        aggregator.advance_to(4);
+        assert!(aggregator.ready.is_empty());
+
+        aggregator.advance_to(6);
+        aggregator.add_logs(worker, &vec![query(30, 3)].into());
+        let stats = aggregator.ready.pop_front().unwrap();
+        assert_eq!(stats.epoch, 5);
+        assert!(stats.is_final);
+        assert_eq!(
+            stats.workers[&worker],
+            WorkerStats {
+                queries: 2,
+                served_bytes: 12
+            }
+        );
+        assert_eq!(stats.latencies[&worker].counts[..4], [0, 0, 1, 1]);
+
+        aggregator.finish();
+        let stats = aggregator.ready.pop_front().unwrap();
+        assert_eq!(stats.epoch, 6);
+        assert!(stats.is_final);
+        assert_eq!(
+            stats.workers[&worker],
+            WorkerStats {
+                queries: 1,
+                served_bytes: 3
+            }
+        );
+        assert!(aggregator.ready.is_empty());
+    }
+
+    #[test]
+    fn test_coverage_index() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let state = |ranges: Vec<(u32, u32)>| {
+            let ranges = RangeSet {
+                ranges: ranges.into_iter().map(|(begin, end)| Range::new(begin, end)).collect(),
+            };
+            WorkerState::from(HashMap::from([("dataset".to_string(), ranges)]))
+        };
+        let mut index = CoverageIndex::default();
+        index.update(a, &state(vec![(0, 100)]));
+        index.update(b, &state(vec![(50, 150)]));
+
+        let mut covering = index.coverage("dataset", 75);
+        covering.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(covering, expected);
+        assert_eq!(index.coverage("dataset", 120), vec![b]);
+        assert_eq!(index.coverage("dataset", 200), vec![]);
+        assert_eq!(index.coverage("other", 75), vec![]);
+
+        index.update(a, &state(vec![(200, 300)]));
+        assert_eq!(index.coverage("dataset", 75), vec![b]);
+        assert_eq!(index.coverage("dataset", 250), vec![a]);
+
+        index.remove(&b);
+        assert_eq!(index.coverage("dataset", 75), vec![]);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut hist = LatencyHistogram::new(vec![100, 10, 1000]);
+        assert_eq!(hist.bounds_ms, vec![10, 100, 1000]);
+        assert_eq!(hist.quantile(0.5), None);
+
+        for latency in [5, 10, 11, 50, 90, 100, 200, 300, 900, 5000] {
+            hist.record(latency);
+        }
+        assert_eq!(hist.counts, vec![2, 4, 3, 1]);
+        assert_eq!(
+            hist.summary(),
+            LatencySummary {
+                p50_ms: Some(100),
+                p95_ms: Some(u64::MAX),
+                p99_ms: Some(u64::MAX),
+            }
+        );
+        assert_eq!(hist.quantile(0.0), Some(10));
+        assert_eq!(hist.quantile(0.9), Some(1000));
+    }
+}
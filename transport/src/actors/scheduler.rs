@@ -1,21 +1,27 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use futures::StreamExt;
 
-use futures_core::Stream;
 use libp2p::{
+    request_response::OutboundRequestId,
     swarm::{NetworkBehaviour, SwarmEvent, ToSwarm},
     PeerId, Swarm,
 };
 use libp2p_swarm_derive::NetworkBehaviour;
 use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
 
-use subsquid_messages::{Ping, Pong};
+use subsquid_messages::{pong, Ping, Pong, WorkerState};
 
 use crate::{
     behaviour::{
         base::{BaseBehaviour, BaseBehaviourEvent},
+        pubsub::SubscribedTopics,
         request_client::{ClientBehaviour, ClientConfig, ClientEvent},
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
@@ -23,7 +29,7 @@ use crate::{
     protocol::{MAX_PONG_SIZE, PONG_PROTOCOL},
     record_event,
     util::{new_queue, Receiver, Sender, TaskManager, DEFAULT_SHUTDOWN_TIMEOUT},
-    QueueFull,
+    DeliveryError, QueueFull,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,7 +54,13 @@ pub struct SchedulerConfig {
     pub max_pong_size: u64,
     pub pongs_queue_size: usize,
     pub probes_queue_size: usize,
+    /// Capacity of the queue of events emitted to the host. The swarm never blocks on it:
+    /// when it's full, new events are dropped and counted by the `dropped` metric
+    /// (`queue_size` shows the current fill level), both labeled with `queue_name="events"`.
     pub events_queue_size: usize,
+    /// If set, the next epoch's assignment should be computed and sent this long before
+    /// the epoch starts (see `SchedulerTransportHandle::pre_assignment_due`)
+    pub assignment_lookahead: Option<Duration>,
     pub shutdown_timeout: Duration,
 }
 
@@ -60,6 +72,7 @@ impl Default for SchedulerConfig {
             pongs_queue_size: 1000,
             probes_queue_size: 1000,
             events_queue_size: 1000,
+            assignment_lookahead: None,
             shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
         }
     }
@@ -67,6 +80,7 @@ impl Default for SchedulerConfig {
 
 pub struct SchedulerBehaviour {
     inner: InnerBehaviour,
+    delivery_confirmations: HashMap<OutboundRequestId, oneshot::Sender<bool>>,
 }
 
 impl SchedulerBehaviour {
@@ -82,6 +96,7 @@ impl SchedulerBehaviour {
                 )
                 .into(),
             },
+            delivery_confirmations: Default::default(),
         }
         .into()
     }
@@ -114,13 +129,23 @@ impl SchedulerBehaviour {
 
     fn on_pong_event(&mut self, ev: ClientEvent<u32>) -> Option<SchedulerEvent> {
         match ev {
-            ClientEvent::Response { .. } => {} // response is just ACK, no useful information
+            // response is just ACK, no useful information
+            ClientEvent::Response { req_id, .. } => self.confirm_delivery(req_id, true),
             ClientEvent::PeerUnknown { peer_id } => self.inner.base.find_and_dial(peer_id),
-            ClientEvent::Timeout { peer_id, .. } => log::warn!("Sending pong to {peer_id} failed"),
+            ClientEvent::Timeout { peer_id, req_id } => {
+                log::warn!("Sending pong to {peer_id} failed");
+                self.confirm_delivery(req_id, false);
+            }
         }
         None
     }
 
+    fn confirm_delivery(&mut self, req_id: OutboundRequestId, delivered: bool) {
+        if let Some(confirmation) = self.delivery_confirmations.remove(&req_id) {
+            _ = confirmation.send(delivered);
+        }
+    }
+
     pub fn send_pong(&mut self, peer_id: PeerId, pong: Pong) {
         log::debug!("Sending pong to {peer_id}");
         if self.inner.pong.try_send_request(peer_id, pong).is_err() {
@@ -128,6 +153,25 @@ impl SchedulerBehaviour {
         }
     }
 
+    /// Send pong to the peer and report via `confirmation` whether it has been delivered
+    pub fn send_pong_confirmed(
+        &mut self,
+        peer_id: PeerId,
+        pong: Pong,
+        confirmation: oneshot::Sender<bool>,
+    ) {
+        log::debug!("Sending pong to {peer_id} with delivery confirmation");
+        match self.inner.pong.try_send_request(peer_id, pong) {
+            Ok(req_id) => {
+                self.delivery_confirmations.insert(req_id, confirmation);
+            }
+            Err(_) => {
+                log::error!("Cannot send pong to {peer_id}: outbound queue full");
+                _ = confirmation.send(false);
+            }
+        }
+    }
+
     pub fn try_probe_peer(&mut self, peer_id: PeerId) -> Result<bool, QueueFull> {
         self.inner.base.try_probe_peer(peer_id)
     }
@@ -156,6 +200,7 @@ impl BehaviourWrapper for SchedulerBehaviour {
 struct SchedulerTransport {
     swarm: Swarm<Wrapped<SchedulerBehaviour>>,
     pongs_rx: Receiver<(PeerId, Pong)>,
+    confirmed_pongs_rx: Receiver<(PeerId, Pong, oneshot::Sender<bool>)>,
     probes_rx: Receiver<PeerId>,
     events_tx: Sender<SchedulerEvent>,
 }
@@ -168,6 +213,7 @@ impl SchedulerTransport {
                 _ = cancel_token.cancelled() => break,
                 ev = self.swarm.select_next_some() => self.on_swarm_event(ev),
                 Some((peer_id, pong)) = self.pongs_rx.recv() => self.swarm.behaviour_mut().send_pong(peer_id, pong),
+                Some((peer_id, pong, confirmation)) = self.confirmed_pongs_rx.recv() => self.swarm.behaviour_mut().send_pong_confirmed(peer_id, pong, confirmation),
                 Some(peer_id) = self.probes_rx.recv() => self.probe_peer(peer_id),
             }
         }
@@ -198,50 +244,254 @@ impl SchedulerTransport {
 #[derive(Clone)]
 pub struct SchedulerTransportHandle {
     pongs_tx: Sender<(PeerId, Pong)>,
+    confirmed_pongs_tx: Sender<(PeerId, Pong, oneshot::Sender<bool>)>,
     probes_tx: Sender<PeerId>,
+    // Most recent assignments sent to workers
+    assignments: Arc<RwLock<HashMap<PeerId, WorkerState>>>,
+    // Assignments sent ahead of their epoch, with the epoch
+    pre_assignments: Arc<RwLock<HashMap<PeerId, (u32, WorkerState)>>>,
+    assignment_lookahead: Option<Duration>,
+    subscribed_topics: SubscribedTopics,
     _task_manager: Arc<TaskManager>,
 }
 
 impl SchedulerTransportHandle {
     fn new(
         pongs_tx: Sender<(PeerId, Pong)>,
+        confirmed_pongs_tx: Sender<(PeerId, Pong, oneshot::Sender<bool>)>,
         probes_tx: Sender<PeerId>,
         transport: SchedulerTransport,
-        shutdown_timeout: Duration,
+        config: &SchedulerConfig,
     ) -> Self {
-        let mut task_manager = TaskManager::new(shutdown_timeout);
+        let subscribed_topics = transport.swarm.behaviour().inner.base.subscribed_topics();
+        let mut task_manager = TaskManager::new(config.shutdown_timeout);
         task_manager.spawn(|c| transport.run(c));
         Self {
             pongs_tx,
+            confirmed_pongs_tx,
             probes_tx,
+            assignments: Default::default(),
+            pre_assignments: Default::default(),
+            assignment_lookahead: config.assignment_lookahead,
+            subscribed_topics,
             _task_manager: Arc::new(task_manager),
         }
     }
+
     pub fn send_pong(&self, peer_id: PeerId, pong: Pong) -> Result<(), QueueFull> {
         log::debug!("Queueing pong to {peer_id}: {pong:?}");
+        self.record_assignment(peer_id, &pong);
+        self.pongs_tx.try_send((peer_id, pong))
+    }
+
+    /// Send pong directly to the peer and wait until its delivery is acknowledged.
+    /// Useful for re-sending a dropped assignment to a single worker.
+    pub async fn send_pong_confirmed(
+        &self,
+        peer_id: PeerId,
+        pong: Pong,
+    ) -> Result<(), DeliveryError> {
+        log::debug!("Queueing pong with delivery confirmation to {peer_id}: {pong:?}");
+        self.record_assignment(peer_id, &pong);
+        let (tx, rx) = oneshot::channel();
+        self.confirmed_pongs_tx.try_send((peer_id, pong, tx))?;
+        match rx.await {
+            Ok(true) => Ok(()),
+            _ => Err(DeliveryError::NotDelivered),
+        }
+    }
+
+    /// Data the worker should be holding according to the most recent assignment sent to it.
+    /// Returns `None` if the worker hasn't been sent an active assignment.
+    pub fn assignment_for(&self, peer_id: PeerId) -> Option<WorkerState> {
+        self.assignments.read().expect("lock poisoned").get(&peer_id).cloned()
+    }
+
+    /// Compute the data each worker would be holding after receiving the given pongs,
+    /// without sending them or recording them as the most recent assignments.
+    /// Workers which wouldn't get an active assignment are omitted from the result.
+    /// Together with `assignment_for`, it allows diffing a proposed assignment against
+    /// the current one before sending it.
+    pub fn compute_assignment_dry_run<'a>(
+        &self,
+        pongs: impl IntoIterator<Item = (PeerId, &'a Pong)>,
+    ) -> HashMap<PeerId, WorkerState> {
+        pongs
+            .into_iter()
+            .filter_map(|(peer_id, pong)| Some((peer_id, assigned_state(peer_id, pong)?)))
+            .collect()
+    }
+
+    /// Whether the next epoch's assignment should be sent now, given the time left until
+    /// the next epoch. Always `false` if `assignment_lookahead` is not configured.
+    pub fn pre_assignment_due(&self, time_to_next_epoch: Duration) -> bool {
+        self.assignment_lookahead
+            .is_some_and(|lookahead| time_to_next_epoch <= lookahead)
+    }
+
+    /// Send an assignment for the upcoming `epoch`. Workers with `stage_future_assignments`
+    /// hold it back until the epoch starts. It's not reported by `assignment_for` until
+    /// `activate_pre_assignments` is called for the epoch.
+    pub fn send_pre_assignment(
+        &self,
+        peer_id: PeerId,
+        mut pong: Pong,
+        epoch: u32,
+    ) -> Result<(), QueueFull> {
+        log::debug!("Queueing pre-assignment for epoch {epoch} to {peer_id}");
+        if let Some(pong::Status::Active(assignment)) = &mut pong.status {
+            assignment.epoch = Some(epoch);
+        }
+        let mut pre_assignments = self.pre_assignments.write().expect("lock poisoned");
+        match assigned_state(peer_id, &pong) {
+            Some(state) => pre_assignments.insert(peer_id, (epoch, state)),
+            None => pre_assignments.remove(&peer_id),
+        };
         self.pongs_tx.try_send((peer_id, pong))
     }
 
+    /// Record the assignments sent ahead for `epoch` or earlier as the most recent ones.
+    /// Should be called when `epoch` starts.
+    pub fn activate_pre_assignments(&self, epoch: u32) {
+        let mut pre_assignments = self.pre_assignments.write().expect("lock poisoned");
+        let mut assignments = self.assignments.write().expect("lock poisoned");
+        pre_assignments.retain(|peer_id, (pre_epoch, state)| {
+            if *pre_epoch > epoch {
+                return true;
+            }
+            assignments.insert(*peer_id, std::mem::take(state));
+            false
+        });
+    }
+
+    fn record_assignment(&self, peer_id: PeerId, pong: &Pong) {
+        let state = assigned_state(peer_id, pong);
+        let mut assignments = self.assignments.write().expect("lock poisoned");
+        match state {
+            Some(state) => assignments.insert(peer_id, state),
+            None => assignments.remove(&peer_id),
+        };
+    }
+
     pub fn probe_peer(&self, peer_id: PeerId) -> Result<(), QueueFull> {
         log::debug!("Queueing probe of peer {peer_id}");
         self.probes_tx.try_send(peer_id)
     }
+
+    /// Names of the gossipsub topics the node is subscribed to, including subscriptions
+    /// made after startup
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.subscribed_topics.get()
+    }
+}
+
+/// Data the worker should be holding after receiving the pong, if it contains an active assignment
+fn assigned_state(peer_id: PeerId, pong: &Pong) -> Option<WorkerState> {
+    match &pong.status {
+        Some(pong::Status::Active(assignment)) => match WorkerState::try_from(assignment) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::warn!("Invalid assignment for {peer_id}: {e}");
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Distribution of data between workers in an assignment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BalanceStats {
+    pub min_blocks: u64,
+    pub max_blocks: u64,
+    pub mean_blocks: f64,
+    /// Gini coefficient of blocks per worker: 0.0 for a perfectly even distribution,
+    /// approaching 1.0 when all the data is assigned to a single worker
+    pub imbalance: f64,
+}
+
+/// Compute how evenly blocks (summed over all datasets) are distributed between workers.
+/// Returns all zeros for an empty assignment.
+pub fn assignment_balance(assignment: &HashMap<PeerId, WorkerState>) -> BalanceStats {
+    let mut blocks: Vec<u64> = assignment
+        .values()
+        .map(|state| state.datasets.values().map(|ranges| ranges.size()).sum())
+        .collect();
+    if blocks.is_empty() {
+        return BalanceStats::default();
+    }
+    blocks.sort();
+    let n = blocks.len() as f64;
+    let total: u64 = blocks.iter().sum();
+    let imbalance = if total == 0 {
+        0.0
+    } else {
+        let weighted_sum: f64 =
+            blocks.iter().enumerate().map(|(i, x)| (i + 1) as f64 * *x as f64).sum();
+        2.0 * weighted_sum / (n * total as f64) - (n + 1.0) / n
+    };
+    BalanceStats {
+        min_blocks: blocks[0],
+        max_blocks: blocks[blocks.len() - 1],
+        mean_blocks: total as f64 / n,
+        imbalance,
+    }
 }
 
 pub fn start_transport(
     swarm: Swarm<Wrapped<SchedulerBehaviour>>,
     config: SchedulerConfig,
-) -> (impl Stream<Item = SchedulerEvent>, SchedulerTransportHandle) {
+) -> (Receiver<SchedulerEvent>, SchedulerTransportHandle) {
     let (pongs_tx, pongs_rx) = new_queue(config.pongs_queue_size, "pongs");
+    let (confirmed_pongs_tx, confirmed_pongs_rx) =
+        new_queue(config.pongs_queue_size, "confirmed_pongs");
     let (probes_tx, probes_rx) = new_queue(config.probes_queue_size, "probes");
     let (events_tx, events_rx) = new_queue(config.events_queue_size, "events");
     let transport = SchedulerTransport {
         swarm,
         pongs_rx,
+        confirmed_pongs_rx,
         probes_rx,
         events_tx,
     };
     let handle =
-        SchedulerTransportHandle::new(pongs_tx, probes_tx, transport, config.shutdown_timeout);
+        SchedulerTransportHandle::new(pongs_tx, confirmed_pongs_tx, probes_tx, transport, &config);
     (events_rx, handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use subsquid_messages::{Range, RangeSet};
+
+    use super::*;
+
+    fn state(blocks: u32) -> WorkerState {
+        let mut state = WorkerState::default();
+        if blocks > 0 {
+            state
+                .datasets
+                .insert("dataset".to_owned(), RangeSet::from([Range::new(0, blocks - 1)]));
+        }
+        state
+    }
+
+    #[test]
+    fn test_assignment_balance() {
+        assert_eq!(assignment_balance(&HashMap::new()), BalanceStats::default());
+
+        let even: HashMap<_, _> = (0..4).map(|_| (PeerId::random(), state(100))).collect();
+        let stats = assignment_balance(&even);
+        assert_eq!((stats.min_blocks, stats.max_blocks), (100, 100));
+        assert_eq!(stats.mean_blocks, 100.0);
+        assert!(stats.imbalance.abs() < 1e-9);
+
+        let skewed: HashMap<_, _> = [0, 0, 0, 400]
+            .into_iter()
+            .map(|blocks| (PeerId::random(), state(blocks)))
+            .collect();
+        let stats = assignment_balance(&skewed);
+        assert_eq!((stats.min_blocks, stats.max_blocks), (0, 400));
+        assert_eq!(stats.mean_blocks, 100.0);
+        assert!((stats.imbalance - 0.75).abs() < 1e-9);
+    }
+}
@@ -1,54 +1,112 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use futures::StreamExt;
-use futures_core::Stream;
+use contract_client::{ClientError, U256};
+use futures::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
 use libp2p::{
     request_response::ResponseChannel,
     swarm::{NetworkBehaviour, SwarmEvent, ToSwarm},
-    PeerId, Swarm,
+    Multiaddr, PeerId, StreamProtocol, Swarm,
 };
 use libp2p_swarm_derive::NetworkBehaviour;
 
 use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
 use tokio_util::sync::CancellationToken;
 
 use subsquid_messages::{
-    signatures::SignedMessage, LogsCollected, Ping, Pong, Query, QueryExecuted, QueryResult,
+    pong, query_result, signatures::SignedMessage, LogsCollected, OkResult, Ping, Pong, Query,
+    QueryExecuted, QueryLogs, QueryResult, ResultFormat, SizeAndHash, WorkerAssignment,
+    WorkerLogsAck, WorkerState,
 };
 
 use crate::{
     behaviour::{
-        base::{BaseBehaviour, BaseBehaviourEvent},
+        base::{bundle_messages, BaseBehaviour, BaseBehaviourEvent},
+        pubsub::SubscribedTopics,
+        request_client::{ClientBehaviour, ClientConfig, ClientEvent},
         request_server::{Request, ServerBehaviour},
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
-    codec::{ProtoCodec, ACK_SIZE},
+    codec::{ProtoCodec, ACK_SIZE, WORKER_LOGS_ACK_SIZE},
     protocol::{
-        MAX_PONG_SIZE, MAX_QUERY_RESULT_SIZE, MAX_QUERY_SIZE, PONG_PROTOCOL, QUERY_PROTOCOL,
+        MAX_PENDING_WORKER_LOGS, MAX_PONG_SIZE, MAX_QUERY_RESULT_SIZE, MAX_QUERY_SIZE,
+        MAX_WORKER_LOGS_SIZE, PONG_PROTOCOL, QUERY_PROTOCOL, QUERY_RESULT_OVERHEAD,
+        WORKER_LOGS_PROTOCOL,
     },
     record_event,
     util::{new_queue, Receiver, Sender, TaskManager, DEFAULT_SHUTDOWN_TIMEOUT},
-    QueueFull,
+    QueueFull, StreamCounts,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WorkerEvent {
     /// Pong message received from the scheduler
     Pong(Pong),
-    /// Query received from a gateway
-    Query { peer_id: PeerId, query: Query },
+    /// Query received from a gateway. `cancellation` is cancelled when the query exceeds
+    /// `execution_timeout`, and the host should stop executing it then.
+    Query {
+        peer_id: PeerId,
+        query: Query,
+        #[serde(skip)]
+        cancellation: QueryCancellation,
+    },
     /// Logs up to `last_seq_no` have been saved by logs collector
     LogsCollected { last_seq_no: Option<u64> },
+    /// Worker started reconciling its state to the assignment for `epoch`
+    AssignmentStarted { epoch: u32 },
+    /// Worker finished reconciling its state to the assignment for `epoch`
+    AssignmentApplied { epoch: u32, state_hash: [u8; 32] },
+    /// Public address of the worker has been confirmed by AutoNAT.
+    /// The host can use it to register the worker's address on chain.
+    ExternalAddrConfirmed { addr: Multiaddr },
+    /// Query exceeded `execution_timeout` and has been answered with `Timeout`.
+    /// Its `cancellation` has been cancelled, any later result will be discarded.
+    QueryTimedOut { query_id: String },
+    /// Stored bytes reported in pings rose above the high watermark (`high == true`)
+    /// or, after that, fell below the low watermark (`high == false`)
+    StorageThresholdCrossed {
+        used: u64,
+        total: u64,
+        pct: u32,
+        high: bool,
+    },
+    /// Assignment made for an epoch older than allowed by `max_assignment_age_epochs`
+    /// has been dropped instead of being passed to the host as `Pong`
+    StaleAssignmentIgnored { epoch: u32, current_epoch: u32 },
+    /// Assignment made for a future epoch is held back until the epoch starts,
+    /// when it's passed to the host as `Pong` (see `stage_future_assignments`)
+    AssignmentStaged { epoch: u32 },
 }
 
 type PongBehaviour = Wrapped<ServerBehaviour<ProtoCodec<Pong, u32>>>;
 type QueryBehaviour = Wrapped<ServerBehaviour<ProtoCodec<Query, QueryResult>>>;
+type LogsBehaviour = Wrapped<ClientBehaviour<ProtoCodec<QueryLogs, WorkerLogsAck>>>;
 
 #[derive(NetworkBehaviour)]
 pub struct InnerBehaviour {
     base: Wrapped<BaseBehaviour>,
     pong: PongBehaviour,
     query: QueryBehaviour,
+    logs: LogsBehaviour,
+}
+
+/// How the worker sends query logs to the logs collector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogsTransport {
+    /// Publish logs on the gossipsub topic. They are acknowledged with `LogsCollected` messages,
+    /// but never re-sent, because every re-publish would be propagated to the whole network.
+    #[default]
+    Gossipsub,
+    /// Send logs directly to the logs collector, which acknowledges them in the response
+    RequestResponse,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,9 +116,53 @@ pub struct WorkerConfig {
     pub max_pong_size: u64,
     pub max_query_size: u64,
     pub max_query_result_size: u64,
+    /// Maximum size of query result data. Results are assembled with
+    /// `WorkerTransportHandle::result_writer`, which fails with `ResultTooLarge` as soon as
+    /// more data is written, and larger results are never sent. Together with
+    /// `QUERY_RESULT_OVERHEAD` it must fit in `max_query_result_size`.
+    pub max_result_bytes: usize,
+    /// If set, the worker rejects queries with `NotReady` until the fraction of its assignment
+    /// covered by the local state reaches the threshold (see `WorkerTransportHandle::update_readiness`)
+    pub readiness_threshold: Option<f64>,
+    /// If set, queries without a result after this time are answered with `Timeout`
+    /// and their `QueryCancellation` is cancelled, independently of network timeouts.
+    pub execution_timeout: Option<Duration>,
+    /// If not empty, only these datasets are kept in the assignment and served
+    pub allowed_datasets: HashSet<String>,
+    /// Datasets removed from the assignment and not served, regardless of `allowed_datasets`
+    pub denied_datasets: HashSet<String>,
+    /// Total storage available to the worker (bytes). If set, `stored_bytes` reported in pings
+    /// is compared against the watermarks to emit `WorkerEvent::StorageThresholdCrossed`
+    pub storage_capacity: Option<u64>,
+    /// Storage usage (percent of capacity) above which the high event is emitted
+    pub storage_high_watermark_pct: u32,
+    /// Storage usage (percent of capacity) below which the low event is emitted
+    pub storage_low_watermark_pct: u32,
+    /// If set, assignments made for epochs older than the current epoch (read from the chain)
+    /// minus this number are ignored. Assignments without an epoch are always accepted.
+    pub max_assignment_age_epochs: Option<u32>,
+    /// If set, assignments made for a future epoch (e.g. pre-assigned by a scheduler with
+    /// `assignment_lookahead`) are held back until that epoch starts on chain.
+    /// Only the latest such assignment is kept.
+    pub stage_future_assignments: bool,
+    /// Queries from gateways whose operator stake is lower are answered with `NoAllocation`.
+    /// Stakes are read from the chain once per gateway per epoch. Zero disables the check.
+    pub min_gateway_stake: U256,
+    /// Maximum number of queries from a single gateway held while its stake is being read.
+    /// Queries above the limit are answered with a server error.
+    pub max_queries_awaiting_stake: usize,
+    pub logs_transport: LogsTransport,
+    pub logs_config: ClientConfig,
+    pub max_logs_size: u64,
+    pub max_pending_logs: usize,
+    /// Logs sent with `LogsTransport::RequestResponse` which haven't been acknowledged
+    /// are re-sent on this interval. Has to be non-zero.
+    pub logs_resend_interval: Duration,
     pub pings_queue_size: usize,
     pub query_results_queue_size: usize,
     pub logs_queue_size: usize,
+    /// Concurrent `protocol_stats` and `external_addresses` calls
+    pub status_requests_queue_size: usize,
     pub events_queue_size: usize,
     pub shutdown_timeout: Duration,
 }
@@ -73,21 +175,334 @@ impl WorkerConfig {
             max_pong_size: MAX_PONG_SIZE,
             max_query_size: MAX_QUERY_SIZE,
             max_query_result_size: MAX_QUERY_RESULT_SIZE,
+            max_result_bytes: (MAX_QUERY_RESULT_SIZE - QUERY_RESULT_OVERHEAD) as usize,
+            readiness_threshold: None,
+            execution_timeout: None,
+            allowed_datasets: Default::default(),
+            denied_datasets: Default::default(),
+            storage_capacity: None,
+            storage_high_watermark_pct: 90,
+            storage_low_watermark_pct: 80,
+            max_assignment_age_epochs: None,
+            stage_future_assignments: false,
+            min_gateway_stake: U256::zero(),
+            max_queries_awaiting_stake: 100,
+            logs_transport: LogsTransport::default(),
+            logs_config: Default::default(),
+            max_logs_size: MAX_WORKER_LOGS_SIZE,
+            max_pending_logs: MAX_PENDING_WORKER_LOGS,
+            logs_resend_interval: Duration::from_secs(60),
             pings_queue_size: 100,
             query_results_queue_size: 100,
             logs_queue_size: 100,
+            status_requests_queue_size: 10,
             events_queue_size: 100,
             shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
         }
     }
 }
 
+#[derive(Debug, Clone, Default)]
+struct DatasetFilter {
+    allowed: HashSet<String>,
+    denied: HashSet<String>,
+}
+
+impl DatasetFilter {
+    fn allows(&self, dataset: &str) -> bool {
+        (self.allowed.is_empty() || self.allowed.contains(dataset))
+            && !self.denied.contains(dataset)
+    }
+
+    /// Remove excluded datasets from the assignment. Returns the removed dataset IDs.
+    fn apply(&self, assignment: &mut WorkerAssignment) -> Vec<String> {
+        let mut removed = Vec::new();
+        assignment.dataset_chunks.retain(|dataset| {
+            let allowed = self.allows(&dataset.dataset_id);
+            if !allowed {
+                removed.push(dataset.dataset_id.clone());
+            }
+            allowed
+        });
+        removed
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Query result too large ({size} > {limit} bytes)")]
+pub struct ResultTooLarge {
+    pub size: u64,
+    pub limit: u64,
+}
+
+impl ResultTooLarge {
+    fn check(size: u64, limit: usize) -> Result<(), Self> {
+        let limit = limit as u64;
+        if size > limit {
+            Err(Self { size, limit })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl From<ResultTooLarge> for query_result::Result {
+    fn from(err: ResultTooLarge) -> Self {
+        Self::server_error(err)
+    }
+}
+
+/// Buffer for query result data limited to `max_result_bytes`. The write exceeding the limit
+/// fails with `ResultTooLarge` and drops the data buffered so far, so query execution can be
+/// aborted before an oversized result is held in memory.
+#[derive(Debug)]
+pub struct ResultWriter {
+    buf: Vec<u8>,
+    written: u64,
+    limit: usize,
+}
+
+impl ResultWriter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            written: 0,
+            limit,
+        }
+    }
+
+    /// Result data, if it didn't exceed the limit
+    pub fn finish(self) -> Result<Vec<u8>, ResultTooLarge> {
+        ResultTooLarge::check(self.written, self.limit)?;
+        Ok(self.buf)
+    }
+}
+
+impl std::io::Write for ResultWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.written = self.written.saturating_add(data.len() as u64);
+        if let Err(e) = ResultTooLarge::check(self.written, self.limit) {
+            self.buf = Vec::new();
+            return Err(std::io::Error::other(e));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Tracks storage usage against watermarks. The low event is only emitted after a high one,
+/// and the gap between the watermarks prevents flapping around a single threshold.
+#[derive(Debug, Clone)]
+struct StorageMonitor {
+    capacity: u64,
+    high_watermark_pct: u32,
+    low_watermark_pct: u32,
+    above_high: bool,
+}
+
+impl StorageMonitor {
+    fn new(config: &WorkerConfig) -> Option<Self> {
+        Some(Self {
+            capacity: config.storage_capacity?,
+            high_watermark_pct: config.storage_high_watermark_pct,
+            low_watermark_pct: config.storage_low_watermark_pct,
+            above_high: false,
+        })
+    }
+
+    fn update(&mut self, used: u64) -> Option<WorkerEvent> {
+        let pct = (used as u128 * 100 / self.capacity.max(1) as u128) as u32;
+        let crossed = if self.above_high {
+            pct < self.low_watermark_pct
+        } else {
+            pct >= self.high_watermark_pct
+        };
+        if !crossed {
+            return None;
+        }
+        self.above_high = !self.above_high;
+        Some(WorkerEvent::StorageThresholdCrossed {
+            used,
+            total: self.capacity,
+            pct,
+            high: self.above_high,
+        })
+    }
+}
+
+/// Signed logs which haven't been acknowledged by the logs collector yet
+#[derive(Debug)]
+struct PendingLogs {
+    logs: BTreeMap<u64, QueryExecuted>,
+    max_len: usize,
+}
+
+impl PendingLogs {
+    fn new(max_len: usize) -> Self {
+        Self {
+            logs: Default::default(),
+            max_len,
+        }
+    }
+
+    /// Buffer the log, dropping the oldest one if the limit is exceeded
+    fn insert(&mut self, seq_no: u64, log: QueryExecuted) {
+        self.logs.insert(seq_no, log);
+        if self.logs.len() > self.max_len {
+            let (seq_no, _) = self.logs.pop_first().expect("not empty");
+            log::warn!("Too many unacknowledged logs. Dropped log {seq_no}");
+        }
+    }
+
+    /// Drop all logs up to and including `last_seq_no`
+    fn acknowledge(&mut self, last_seq_no: u64) {
+        self.logs = match last_seq_no.checked_add(1) {
+            Some(next_seq_no) => self.logs.split_off(&next_seq_no),
+            None => Default::default(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        self.logs.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.logs.len()
+    }
+
+    fn to_vec(&self) -> Vec<QueryExecuted> {
+        self.logs.values().cloned().collect()
+    }
+}
+
+type StakeLookup = BoxFuture<'static, (PeerId, Result<U256, ClientError>)>;
+
+/// Checks gateways' operator stakes against `min_gateway_stake`, caching results per epoch
+struct GatewayStakeChecker {
+    min_stake: U256,
+    max_awaiting: usize,
+    client: Box<dyn contract_client::Client>,
+    // Whether the gateway's stake is sufficient and the epoch it was checked in
+    cache: HashMap<PeerId, (Option<u32>, bool)>,
+    // Queries received while the gateway's stake is being looked up
+    awaiting: HashMap<PeerId, Vec<Query>>,
+}
+
+impl GatewayStakeChecker {
+    fn new(config: &WorkerConfig, client: Box<dyn contract_client::Client>) -> Option<Self> {
+        (!config.min_gateway_stake.is_zero()).then(|| Self {
+            min_stake: config.min_gateway_stake,
+            max_awaiting: config.max_queries_awaiting_stake,
+            client,
+            cache: Default::default(),
+            awaiting: Default::default(),
+        })
+    }
+
+    fn cached(&self, peer_id: &PeerId, epoch: Option<u32>) -> Option<bool> {
+        match self.cache.get(peer_id) {
+            Some((checked_at, sufficient)) if *checked_at == epoch => Some(*sufficient),
+            _ => None,
+        }
+    }
+
+    /// Hold the query until the gateway's stake is known. Returns the lookup to be polled
+    /// if there isn't one in progress already, or the query back if too many are held.
+    fn defer(&mut self, peer_id: PeerId, query: Query) -> Result<Option<StakeLookup>, Query> {
+        let queries = self.awaiting.entry(peer_id).or_default();
+        if queries.len() >= self.max_awaiting {
+            return Err(query);
+        }
+        queries.push(query);
+        if queries.len() > 1 {
+            return Ok(None);
+        }
+        let client = self.client.clone_client();
+        Ok(Some(Box::pin(async move { (peer_id, client.gateway_stake(peer_id).await) })))
+    }
+
+    /// Returns whether the stake is sufficient and the queries waiting for it.
+    /// If the stake couldn't be read, the queries are accepted and the result is not cached.
+    fn resolve(
+        &mut self,
+        peer_id: PeerId,
+        result: Result<U256, ClientError>,
+        epoch: Option<u32>,
+    ) -> (bool, Vec<Query>) {
+        let queries = self.awaiting.remove(&peer_id).unwrap_or_default();
+        match result {
+            Ok(stake) => {
+                let sufficient = stake >= self.min_stake;
+                if !sufficient {
+                    log::info!("Gateway {peer_id} has insufficient stake: {stake}");
+                }
+                self.cache.insert(peer_id, (epoch, sufficient));
+                (sufficient, queries)
+            }
+            Err(e) => {
+                log::warn!("Couldn't read stake of gateway {peer_id}: {e:?}");
+                (true, queries)
+            }
+        }
+    }
+}
+
+/// Cancellation of a query passed to the host with `WorkerEvent::Query`.
+/// Clones share the state and compare equal.
+#[derive(Debug, Clone, Default)]
+pub struct QueryCancellation(Arc<CancellationToken>);
+
+impl QueryCancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Wait until the query is cancelled
+    pub async fn cancelled(&self) {
+        self.0.cancelled().await
+    }
+
+    /// Token cancelled together with the query, e.g. to pass to the execution task
+    pub fn token(&self) -> CancellationToken {
+        self.0.as_ref().clone()
+    }
+
+    fn cancel(&self) {
+        self.0.cancel()
+    }
+}
+
+impl PartialEq for QueryCancellation {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for QueryCancellation {}
+
+struct PendingQuery {
+    resp_chan: ResponseChannel<QueryResult>,
+    accepted_compression: Vec<String>,
+    requested_format: Option<String>,
+    received_at: Instant,
+    cancellation: QueryCancellation,
+}
+
 pub struct WorkerBehaviour {
     inner: InnerBehaviour,
     local_peer_id: String,
     scheduler_id: PeerId,
     logs_collector_id: PeerId,
-    query_response_channels: HashMap<String, ResponseChannel<QueryResult>>,
+    // Response channels and codecs accepted by the client for pending queries
+    pending_queries: HashMap<String, PendingQuery>,
+    dataset_filter: DatasetFilter,
+    logs_transport: LogsTransport,
+    pending_logs: PendingLogs,
+    max_logs_size: usize,
 }
 
 impl WorkerBehaviour {
@@ -114,11 +529,24 @@ impl WorkerBehaviour {
                     QUERY_PROTOCOL,
                 )
                 .into(),
+                logs: ClientBehaviour::new(
+                    ProtoCodec::new(config.max_logs_size, WORKER_LOGS_ACK_SIZE),
+                    WORKER_LOGS_PROTOCOL,
+                    config.logs_config,
+                )
+                .into(),
             },
             local_peer_id: local_peer_id.to_base58(),
             scheduler_id: config.scheduler_id,
             logs_collector_id: config.logs_collector_id,
-            query_response_channels: Default::default(),
+            pending_queries: Default::default(),
+            dataset_filter: DatasetFilter {
+                allowed: config.allowed_datasets,
+                denied: config.denied_datasets,
+            },
+            logs_transport: config.logs_transport,
+            pending_logs: PendingLogs::new(config.max_pending_logs),
+            max_logs_size: config.max_logs_size as usize,
         }
         .into()
     }
@@ -129,6 +557,9 @@ impl WorkerBehaviour {
                 peer_id,
                 logs_collected,
             } => self.on_logs_collected(peer_id, logs_collected),
+            BaseBehaviourEvent::ExternalAddrConfirmed { addr } => {
+                Some(WorkerEvent::ExternalAddrConfirmed { addr })
+            }
             _ => None,
         }
     }
@@ -145,6 +576,42 @@ impl WorkerBehaviour {
         log::debug!("Received logs collected message");
         // Extract last_seq_no for the local worker
         let last_seq_no = logs_collected.sequence_numbers.remove(&self.local_peer_id);
+        self.on_logs_acknowledged(last_seq_no)
+    }
+
+    fn on_logs_event(&mut self, ev: ClientEvent<WorkerLogsAck>) -> Option<WorkerEvent> {
+        match ev {
+            ClientEvent::Response {
+                peer_id, response, ..
+            } => self.on_logs_ack(peer_id, response),
+            ClientEvent::PeerUnknown { peer_id } => {
+                self.inner.base.find_and_dial(peer_id);
+                None
+            }
+            ClientEvent::Timeout { .. } => {
+                log::warn!("Sending logs to collector timed out");
+                None
+            }
+        }
+    }
+
+    fn on_logs_ack(&mut self, peer_id: PeerId, ack: WorkerLogsAck) -> Option<WorkerEvent> {
+        if peer_id != self.logs_collector_id {
+            log::warn!("Peer {peer_id} impersonating logs collector");
+            return None;
+        }
+        // Nothing has been saved yet, the logs will be acknowledged with a later request
+        let last_seq_no = ack.last_seq_no?;
+        self.on_logs_acknowledged(Some(last_seq_no))
+    }
+
+    /// Prune logs acknowledged either with a `LogsCollected` message or in the response
+    /// to sent logs, so that both paths agree on what is still pending
+    fn on_logs_acknowledged(&mut self, last_seq_no: Option<u64>) -> Option<WorkerEvent> {
+        if let Some(last_seq_no) = last_seq_no {
+            log::debug!("Logs up to {last_seq_no} acknowledged by logs collector");
+            self.pending_logs.acknowledge(last_seq_no);
+        }
         Some(WorkerEvent::LogsCollected { last_seq_no })
     }
 
@@ -156,7 +623,14 @@ impl WorkerBehaviour {
     ) -> Option<WorkerEvent> {
         // Verify query signature
         if !query.verify_signature(&peer_id) {
-            log::warn!("Dropping query with invalid signature from {peer_id}");
+            log::warn!("Rejecting query with invalid signature from {peer_id}");
+            if let (Some(query_id), Some(resp_chan)) = (query.query_id, resp_chan) {
+                let result = QueryResult::new(
+                    query_id,
+                    query_result::Result::bad_request("Invalid query signature"),
+                );
+                _ = self.inner.query.try_send_response(resp_chan, result);
+            }
             return None;
         }
         // Check if query has ID
@@ -168,17 +642,48 @@ impl WorkerBehaviour {
             }
         };
         log::debug!("Query {query_id} verified");
+        let dataset = query.dataset.as_deref().unwrap_or_default();
+        if !self.dataset_filter.allows(dataset) {
+            log::debug!("Rejecting query {query_id} for excluded dataset {dataset}");
+            if let Some(resp_chan) = resp_chan {
+                let result = QueryResult::new(query_id, query_result::Result::no_allocation());
+                _ = self.inner.query.try_send_response(resp_chan, result);
+            }
+            return None;
+        }
+        let cancellation = QueryCancellation::default();
         if let Some(resp_chan) = resp_chan {
-            self.query_response_channels.insert(query_id, resp_chan);
+            let pending = PendingQuery {
+                resp_chan,
+                accepted_compression: query.accepted_compression.clone(),
+                requested_format: query.result_format.clone(),
+                received_at: Instant::now(),
+                cancellation: cancellation.clone(),
+            };
+            self.pending_queries.insert(query_id, pending);
         }
-        Some(WorkerEvent::Query { peer_id, query })
+        Some(WorkerEvent::Query {
+            peer_id,
+            query,
+            cancellation,
+        })
+    }
+
+    /// Cancellation of the pending query, or a new one if the query isn't pending
+    fn query_cancellation(&self, query: &Query) -> QueryCancellation {
+        query
+            .query_id
+            .as_ref()
+            .and_then(|query_id| self.pending_queries.get(query_id))
+            .map(|pending| pending.cancellation.clone())
+            .unwrap_or_default()
     }
 
     fn on_pong_event(
         &mut self,
         Request {
             peer_id,
-            request,
+            mut request,
             response_channel,
         }: Request<Pong, u32>,
     ) -> Option<WorkerEvent> {
@@ -189,6 +694,12 @@ impl WorkerBehaviour {
         log::debug!("Received pong from scheduler: {request:?}");
         // Send minimal response to avoid getting errors
         _ = self.inner.pong.try_send_response(response_channel, 1);
+        if let Some(pong::Status::Active(assignment)) = &mut request.status {
+            let removed = self.dataset_filter.apply(assignment);
+            if !removed.is_empty() {
+                log::info!("Assignment trimmed by dataset filter. Removed datasets: {removed:?}");
+            }
+        }
         Some(WorkerEvent::Pong(request))
     }
 
@@ -196,21 +707,135 @@ impl WorkerBehaviour {
         self.inner.base.publish_ping(ping);
     }
 
-    pub fn send_query_result(&mut self, result: QueryResult) {
+    pub fn send_query_result(&mut self, mut result: QueryResult) {
         log::debug!("Sending query result {result:?}");
-        let resp_chan = match self.query_response_channels.remove(&result.query_id) {
-            Some(ch) => ch,
+        let PendingQuery {
+            resp_chan,
+            accepted_compression,
+            requested_format,
+            ..
+        } = match self.pending_queries.remove(&result.query_id) {
+            Some(pending) => pending,
             None => return log::error!("No response channel for query: {}", result.query_id),
         };
+        // Compression can only be used if the client has declared it can decompress the result
+        if let Some(query_result::Result::Ok(OkResult {
+            compression: Some(codec),
+            ..
+        })) = &result.result
+        {
+            if !accepted_compression.contains(codec) {
+                let msg = format!("Result compressed with codec not accepted by client: {codec}");
+                log::error!("{msg}. query_id={}", result.query_id);
+                result.result = Some(query_result::Result::server_error(msg));
+            }
+        }
+        // Data can only be encoded in the requested format or left raw
+        if let Some(query_result::Result::Ok(ok)) = &mut result.result {
+            let format = ok.format.as_deref().unwrap_or(ResultFormat::Raw.as_str());
+            if format != ResultFormat::Raw.as_str() && Some(format) != requested_format.as_deref() {
+                let msg = format!("Result encoded in format not requested by client: {format}");
+                log::error!("{msg}. query_id={}", result.query_id);
+                result.result = Some(query_result::Result::server_error(msg));
+            } else if requested_format.is_some() && ok.format.is_none() {
+                // Let the client know that the requested format hasn't been used
+                ok.format = Some(ResultFormat::Raw.to_string());
+            }
+        }
         self.inner
             .query
             .try_send_response(resp_chan, result)
             .unwrap_or_else(|e| log::error!("Cannot send result for query {}", e.query_id));
     }
 
-    pub fn send_logs(&mut self, logs: Vec<QueryExecuted>) {
+    /// Answer queries pending for longer than `timeout` with `Timeout` and cancel them.
+    /// Returns IDs of the expired queries.
+    pub fn expire_queries(&mut self, timeout: Duration) -> Vec<String> {
+        let expired: Vec<String> = self
+            .pending_queries
+            .iter()
+            .filter(|(_, pending)| pending.received_at.elapsed() > timeout)
+            .map(|(query_id, _)| query_id.clone())
+            .collect();
+        for query_id in expired.iter() {
+            log::warn!("Query {query_id} execution timed out");
+            if let Some(pending) = self.pending_queries.get(query_id) {
+                pending.cancellation.cancel();
+            }
+            let result = QueryResult::new(query_id.clone(), query_result::Result::timeout());
+            self.send_query_result(result);
+        }
+        expired
+    }
+
+    /// Open stream counts of the request-response protocols. Gossipsub, Kademlia and other
+    /// protocols of the base behaviour don't expose theirs, so they're not included.
+    pub fn protocol_stats(&self) -> HashMap<StreamProtocol, StreamCounts> {
+        [
+            (
+                QUERY_PROTOCOL,
+                StreamCounts {
+                    inbound: self.inner.query.ongoing_requests(),
+                    outbound: 0,
+                },
+            ),
+            (
+                PONG_PROTOCOL,
+                StreamCounts {
+                    inbound: self.inner.pong.ongoing_requests(),
+                    outbound: 0,
+                },
+            ),
+            (
+                WORKER_LOGS_PROTOCOL,
+                StreamCounts {
+                    inbound: 0,
+                    outbound: self.inner.logs.ongoing_requests(),
+                },
+            ),
+        ]
+        .into_iter()
+        .map(|(protocol, counts)| (StreamProtocol::new(protocol), counts))
+        .collect()
+    }
+
+    pub fn send_logs(&mut self, mut logs: Vec<QueryExecuted>) {
         log::debug!("Sending query logs");
-        self.inner.base.publish_worker_logs(logs);
+        let resendable = self.logs_transport == LogsTransport::RequestResponse;
+        for log in logs.iter_mut() {
+            self.inner.base.sign(log);
+            // Logs without sequence numbers can't be acknowledged, so they're sent only once
+            if let Some(seq_no) = log.seq_no.filter(|_| resendable) {
+                self.pending_logs.insert(seq_no, log.clone());
+            }
+        }
+        self.transmit_logs(logs);
+    }
+
+    /// Re-send all logs which haven't been acknowledged yet, e.g. because the logs collector
+    /// has been restarted before saving them. Only logs sent directly to the logs collector
+    /// are kept for re-sending.
+    pub fn resend_pending_logs(&mut self) {
+        if self.pending_logs.is_empty() {
+            return;
+        }
+        log::debug!("Re-sending {} unacknowledged query logs", self.pending_logs.len());
+        let logs = self.pending_logs.to_vec();
+        self.transmit_logs(logs);
+    }
+
+    fn transmit_logs(&mut self, logs: Vec<QueryExecuted>) {
+        match self.logs_transport {
+            LogsTransport::Gossipsub => self.inner.base.publish_worker_logs(logs),
+            LogsTransport::RequestResponse => {
+                for bundle in bundle_messages(logs, self.max_logs_size) {
+                    let request = bundle.into();
+                    if self.inner.logs.try_send_request(self.logs_collector_id, request).is_err() {
+                        log::error!("Cannot send query logs: outbound queue full");
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -234,29 +859,65 @@ impl BehaviourWrapper for WorkerBehaviour {
                 request,
                 response_channel,
             }) => self.on_query(peer_id, request, Some(response_channel)),
+            InnerBehaviourEvent::Logs(ev) => self.on_logs_event(ev),
         };
         ev.map(ToSwarm::GenerateEvent)
     }
 }
 
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const EPOCH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Request for the transport state, answered by the swarm loop
+#[derive(Debug)]
+enum StatusRequest {
+    ProtocolStats(oneshot::Sender<HashMap<StreamProtocol, StreamCounts>>),
+    ExternalAddresses(oneshot::Sender<Vec<Multiaddr>>),
+}
+
 struct WorkerTransport {
     swarm: Swarm<Wrapped<WorkerBehaviour>>,
     pings_rx: Receiver<Ping>,
     query_results_rx: Receiver<QueryResult>,
     logs_rx: Receiver<Vec<QueryExecuted>>,
+    status_requests_rx: Receiver<StatusRequest>,
     events_tx: Sender<WorkerEvent>,
+    logs_resend_interval: Duration,
+    resend_logs: bool,
+    execution_timeout: Option<Duration>,
+    storage_monitor: Option<StorageMonitor>,
+    max_assignment_age_epochs: Option<u32>,
+    stage_future_assignments: bool,
+    staged_assignment: Option<(u32, Pong)>,
+    contract_client: Option<Box<dyn contract_client::Client>>,
+    current_epoch: watch::Sender<Option<u32>>,
+    stake_checker: Option<GatewayStakeChecker>,
+    stake_lookups: FuturesUnordered<StakeLookup>,
+    ready: Arc<AtomicBool>,
 }
 
 impl WorkerTransport {
     pub async fn run(mut self, cancel_token: CancellationToken) {
         log::info!("Starting worker P2P transport");
+        let mut logs_resend_interval = tokio::time::interval(self.logs_resend_interval);
+        let mut expiry_interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        if let Some(client) = self.contract_client.take() {
+            let epoch_tx = self.current_epoch.clone();
+            tokio::spawn(watch_epoch(client, epoch_tx, cancel_token.child_token()));
+        }
+        let mut epoch_rx = self.current_epoch.subscribe();
         loop {
             tokio::select! {
                  _ = cancel_token.cancelled() => break,
+                _ = logs_resend_interval.tick(), if self.resend_logs => self.swarm.behaviour_mut().resend_pending_logs(),
+                _ = expiry_interval.tick(), if self.execution_timeout.is_some() => self.expire_queries(),
                 ev = self.swarm.select_next_some() => self.on_swarm_event(ev),
-                Some(ping) = self.pings_rx.recv() => self.swarm.behaviour_mut().send_ping(ping),
+                Some(ping) = self.pings_rx.recv() => self.send_ping(ping),
                 Some(res) = self.query_results_rx.recv() => self.swarm.behaviour_mut().send_query_result(res),
                 Some(logs) = self.logs_rx.recv() => self.swarm.behaviour_mut().send_logs(logs),
+                Some(req) = self.status_requests_rx.recv() => self.on_status_request(req),
+                Some((peer_id, result)) = self.stake_lookups.next() => self.on_stake_lookup(peer_id, result),
+                Ok(()) = epoch_rx.changed(), if self.staged_assignment.is_some() => self.activate_staged_assignment(),
             }
         }
         log::info!("Shutting down worker P2P transport");
@@ -265,8 +926,193 @@ impl WorkerTransport {
     fn on_swarm_event(&mut self, ev: SwarmEvent<WorkerEvent>) {
         log::trace!("Swarm event: {ev:?}");
         record_event(&ev);
-        if let SwarmEvent::Behaviour(ev) = ev {
-            self.events_tx.send_lossy(ev)
+        match ev {
+            SwarmEvent::Behaviour(WorkerEvent::Query { peer_id, query, .. })
+                if !self.ready.load(Ordering::Relaxed) =>
+            {
+                self.reject_query(peer_id, query)
+            }
+            SwarmEvent::Behaviour(WorkerEvent::Query { peer_id, query, .. }) => {
+                self.on_query(peer_id, query)
+            }
+            SwarmEvent::Behaviour(WorkerEvent::Pong(pong)) => self.on_pong(pong),
+            SwarmEvent::Behaviour(ev) => self.events_tx.send_lossy(ev),
+            _ => {}
+        }
+    }
+
+    fn on_query(&mut self, peer_id: PeerId, query: Query) {
+        let epoch = *self.current_epoch.borrow();
+        let Some(checker) = &mut self.stake_checker else {
+            return self.forward_query(peer_id, query);
+        };
+        match checker.cached(&peer_id, epoch) {
+            Some(true) => self.forward_query(peer_id, query),
+            Some(false) => self.reject_insufficient_stake(peer_id, query),
+            None => match checker.defer(peer_id, query) {
+                Ok(lookup) => self.stake_lookups.extend(lookup),
+                Err(query) => self.reject_awaiting_stake(peer_id, query),
+            },
+        }
+    }
+
+    fn on_stake_lookup(&mut self, peer_id: PeerId, result: Result<U256, ClientError>) {
+        let epoch = *self.current_epoch.borrow();
+        let Some(checker) = &mut self.stake_checker else {
+            return;
+        };
+        let (sufficient, queries) = checker.resolve(peer_id, result, epoch);
+        for query in queries {
+            if sufficient {
+                self.forward_query(peer_id, query);
+            } else {
+                self.reject_insufficient_stake(peer_id, query);
+            }
+        }
+    }
+
+    fn forward_query(&mut self, peer_id: PeerId, query: Query) {
+        let cancellation = self.swarm.behaviour().query_cancellation(&query);
+        self.events_tx.send_lossy(WorkerEvent::Query {
+            peer_id,
+            query,
+            cancellation,
+        })
+    }
+
+    fn reject_insufficient_stake(&mut self, peer_id: PeerId, query: Query) {
+        let query_id = query.query_id.unwrap_or_default();
+        log::debug!("Gateway stake too low. Rejecting query {query_id} from {peer_id}");
+        let result = QueryResult::new(query_id, query_result::Result::no_allocation());
+        self.swarm.behaviour_mut().send_query_result(result);
+    }
+
+    fn reject_awaiting_stake(&mut self, peer_id: PeerId, query: Query) {
+        let query_id = query.query_id.unwrap_or_default();
+        log::warn!(
+            "Too many queries awaiting stake check. Rejecting query {query_id} from {peer_id}"
+        );
+        let result = QueryResult::new(
+            query_id,
+            query_result::Result::server_error("Too many queries awaiting gateway stake check"),
+        );
+        self.swarm.behaviour_mut().send_query_result(result);
+    }
+
+    fn on_pong(&mut self, pong: Pong) {
+        let current_epoch = *self.current_epoch.borrow();
+        let ev = match stale_assignment(&pong, current_epoch, self.max_assignment_age_epochs) {
+            Some((epoch, current_epoch)) => {
+                log::warn!(
+                    "Ignoring stale assignment for epoch {epoch} (current: {current_epoch})"
+                );
+                WorkerEvent::StaleAssignmentIgnored {
+                    epoch,
+                    current_epoch,
+                }
+            }
+            None if self.stage_future_assignments => {
+                match future_assignment(&pong, current_epoch) {
+                    Some(epoch) => {
+                        log::info!("Staging assignment for epoch {epoch}");
+                        self.staged_assignment = Some((epoch, pong));
+                        WorkerEvent::AssignmentStaged { epoch }
+                    }
+                    None => WorkerEvent::Pong(pong),
+                }
+            }
+            None => WorkerEvent::Pong(pong),
+        };
+        self.events_tx.send_lossy(ev)
+    }
+
+    fn activate_staged_assignment(&mut self) {
+        let Some(current_epoch) = *self.current_epoch.borrow() else {
+            return;
+        };
+        if !matches!(self.staged_assignment, Some((epoch, _)) if epoch <= current_epoch) {
+            return;
+        }
+        let (epoch, pong) = self.staged_assignment.take().expect("assignment staged");
+        log::info!("Epoch {current_epoch} started. Activating assignment for epoch {epoch}");
+        self.events_tx.send_lossy(WorkerEvent::Pong(pong))
+    }
+
+    fn on_status_request(&mut self, req: StatusRequest) {
+        match req {
+            StatusRequest::ProtocolStats(tx) => {
+                _ = tx.send(self.swarm.behaviour().protocol_stats());
+            }
+            StatusRequest::ExternalAddresses(tx) => {
+                _ = tx.send(self.swarm.external_addresses().cloned().collect());
+            }
+        }
+    }
+
+    fn send_ping(&mut self, ping: Ping) {
+        if let (Some(monitor), Some(used)) = (&mut self.storage_monitor, ping.stored_bytes) {
+            if let Some(ev) = monitor.update(used) {
+                log::info!("Storage threshold crossed: {ev:?}");
+                self.events_tx.send_lossy(ev);
+            }
+        }
+        self.swarm.behaviour_mut().send_ping(ping);
+    }
+
+    fn expire_queries(&mut self) {
+        let Some(timeout) = self.execution_timeout else {
+            return;
+        };
+        for query_id in self.swarm.behaviour_mut().expire_queries(timeout) {
+            self.events_tx.send_lossy(WorkerEvent::QueryTimedOut { query_id });
+        }
+    }
+
+    fn reject_query(&mut self, peer_id: PeerId, query: Query) {
+        let query_id = query.query_id.unwrap_or_default();
+        log::debug!("Worker not ready. Rejecting query {query_id} from {peer_id}");
+        let result = QueryResult::new(query_id, query_result::Result::not_ready());
+        self.swarm.behaviour_mut().send_query_result(result);
+    }
+}
+
+/// Returns the assignment epoch and the current epoch if the pong carries an assignment
+/// older than allowed
+fn stale_assignment(
+    pong: &Pong,
+    current_epoch: Option<u32>,
+    max_age_epochs: Option<u32>,
+) -> Option<(u32, u32)> {
+    let Some(pong::Status::Active(assignment)) = &pong.status else {
+        return None;
+    };
+    let (epoch, current_epoch, max_age) = (assignment.epoch?, current_epoch?, max_age_epochs?);
+    (epoch.saturating_add(max_age) < current_epoch).then_some((epoch, current_epoch))
+}
+
+/// Returns the assignment epoch if the pong carries an assignment made for an epoch
+/// later than the current one
+fn future_assignment(pong: &Pong, current_epoch: Option<u32>) -> Option<u32> {
+    let Some(pong::Status::Active(assignment)) = &pong.status else {
+        return None;
+    };
+    let (epoch, current_epoch) = (assignment.epoch?, current_epoch?);
+    (epoch > current_epoch).then_some(epoch)
+}
+
+async fn watch_epoch(
+    client: Box<dyn contract_client::Client>,
+    epoch_tx: watch::Sender<Option<u32>>,
+    cancel_token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(EPOCH_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            _ = interval.tick() => match client.current_epoch().await {
+                Ok(epoch) => _ = epoch_tx.send_replace(Some(epoch)),
+                Err(e) => log::warn!("Error reading current epoch: {e:?}"),
+            },
         }
     }
 }
@@ -276,6 +1122,12 @@ pub struct WorkerTransportHandle {
     pings_tx: Sender<Ping>,
     query_results_tx: Sender<QueryResult>,
     logs_tx: Sender<Vec<QueryExecuted>>,
+    status_requests_tx: Sender<StatusRequest>,
+    events_tx: Sender<WorkerEvent>,
+    max_result_bytes: usize,
+    readiness_threshold: Option<f64>,
+    ready: Arc<AtomicBool>,
+    subscribed_topics: SubscribedTopics,
     _task_manager: Arc<TaskManager>, // This ensures that transport is stopped when the last handle is dropped
 }
 
@@ -284,15 +1136,25 @@ impl WorkerTransportHandle {
         pings_tx: Sender<Ping>,
         query_results_tx: Sender<QueryResult>,
         logs_tx: Sender<Vec<QueryExecuted>>,
+        status_requests_tx: Sender<StatusRequest>,
+        events_tx: Sender<WorkerEvent>,
         transport: WorkerTransport,
-        shutdown_timeout: Duration,
+        config: &WorkerConfig,
     ) -> Self {
-        let mut task_manager = TaskManager::new(shutdown_timeout);
+        let ready = transport.ready.clone();
+        let subscribed_topics = transport.swarm.behaviour().inner.base.subscribed_topics();
+        let mut task_manager = TaskManager::new(config.shutdown_timeout);
         task_manager.spawn(|c| transport.run(c));
         Self {
             pings_tx,
             query_results_tx,
             logs_tx,
+            status_requests_tx,
+            events_tx,
+            max_result_bytes: config.max_result_bytes,
+            readiness_threshold: config.readiness_threshold,
+            ready,
+            subscribed_topics,
             _task_manager: Arc::new(task_manager),
         }
     }
@@ -302,7 +1164,56 @@ impl WorkerTransportHandle {
         self.pings_tx.try_send(ping)
     }
 
-    pub fn send_query_result(&self, result: QueryResult) -> Result<(), QueueFull> {
+    /// Maximum size of query result data (`max_result_bytes`)
+    pub fn max_result_bytes(&self) -> usize {
+        self.max_result_bytes
+    }
+
+    /// Buffer for assembling query result data which fails once `max_result_bytes` is exceeded
+    pub fn result_writer(&self) -> ResultWriter {
+        ResultWriter::new(self.max_result_bytes)
+    }
+
+    /// Check the size of result data before reading it, e.g. from a precomputed `SizeAndHash`.
+    /// A missing size is treated as zero.
+    pub fn check_result_size(&self, output: &SizeAndHash) -> Result<(), ResultTooLarge> {
+        ResultTooLarge::check(output.size.unwrap_or_default() as u64, self.max_result_bytes)
+    }
+
+    /// Check if the worker is accepting queries
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Names of the gossipsub topics the node is subscribed to, including subscriptions
+    /// made after startup
+    pub fn subscribed_topics(&self) -> Vec<String> {
+        self.subscribed_topics.get()
+    }
+
+    /// Update readiness based on the fraction of `assignment` covered by the local `state`.
+    /// Has no effect if `readiness_threshold` is not configured. Returns the current readiness.
+    pub fn update_readiness(&self, assignment: &WorkerState, state: &WorkerState) -> bool {
+        let Some(threshold) = self.readiness_threshold else {
+            return true;
+        };
+        let coverage = state.coverage(assignment);
+        let ready = coverage >= threshold;
+        if self.ready.swap(ready, Ordering::Relaxed) != ready {
+            log::info!("Worker ready={ready} (assignment coverage {:.1}%)", coverage * 100.0);
+        }
+        ready
+    }
+
+    /// Results with more than `max_result_bytes` of data are replaced with a server error.
+    /// Assemble the data with `result_writer` to abort execution before that happens.
+    pub fn send_query_result(&self, mut result: QueryResult) -> Result<(), QueueFull> {
+        if let Some(query_result::Result::Ok(OkResult { data, .. })) = &result.result {
+            if let Err(e) = ResultTooLarge::check(data.len() as u64, self.max_result_bytes) {
+                log::warn!("{e}. query_id={}", result.query_id);
+                result.result = Some(e.into());
+            }
+        }
         log::debug!("Queueing query result {result:?}");
         self.query_results_tx.try_send(result)
     }
@@ -311,30 +1222,290 @@ impl WorkerTransportHandle {
         log::debug!("Queueing {} query logs", logs.len());
         self.logs_tx.try_send(logs)
     }
+
+    /// Number of currently open streams of each request-response protocol, e.g. to check
+    /// whether the worker is limited by the number of concurrent queries.
+    /// Returns `None` if the transport is not running or overloaded.
+    pub async fn protocol_stats(&self) -> Option<HashMap<StreamProtocol, StreamCounts>> {
+        let (tx, rx) = oneshot::channel();
+        self.status_requests_tx.try_send(StatusRequest::ProtocolStats(tx)).ok()?;
+        rx.await.ok()
+    }
+
+    /// External addresses the worker currently advertises, including the ones confirmed
+    /// by AutoNAT. libp2p doesn't expose the confidence or source of the addresses.
+    /// Returns `None` if the transport is not running or overloaded.
+    pub async fn external_addresses(&self) -> Option<Vec<Multiaddr>> {
+        let (tx, rx) = oneshot::channel();
+        self.status_requests_tx.try_send(StatusRequest::ExternalAddresses(tx)).ok()?;
+        rx.await.ok()
+    }
+
+    /// Report that the worker started reconciling its state to the assignment for `epoch`
+    pub fn assignment_started(&self, epoch: u32) {
+        log::info!("Applying assignment for epoch {epoch}");
+        self.events_tx.send_lossy(WorkerEvent::AssignmentStarted { epoch })
+    }
+
+    /// Report that the worker's state has been reconciled to the assignment for `epoch`
+    pub fn assignment_applied(&self, epoch: u32, state: &WorkerState) {
+        log::info!("Assignment for epoch {epoch} applied");
+        let state_hash = state.hash();
+        self.events_tx.send_lossy(WorkerEvent::AssignmentApplied { epoch, state_hash })
+    }
 }
 
 pub fn start_transport(
     swarm: Swarm<Wrapped<WorkerBehaviour>>,
     config: WorkerConfig,
-) -> (impl Stream<Item = WorkerEvent>, WorkerTransportHandle) {
+    contract_client: Box<dyn contract_client::Client>,
+) -> (Receiver<WorkerEvent>, WorkerTransportHandle) {
     let (pings_tx, pings_rx) = new_queue(config.pings_queue_size, "pings");
     let (query_results_tx, query_results_rx) =
         new_queue(config.query_results_queue_size, "query_results");
     let (logs_tx, logs_rx) = new_queue(config.logs_queue_size, "logs");
+    let (status_requests_tx, status_requests_rx) =
+        new_queue(config.status_requests_queue_size, "status_requests");
     let (events_tx, events_rx) = new_queue(config.events_queue_size, "events");
+    let stake_checker = GatewayStakeChecker::new(&config, contract_client.clone_client());
+    // Current epoch is only needed to detect stale and future assignments and expire cached stakes
+    let watch_epoch = config.max_assignment_age_epochs.is_some()
+        || config.stage_future_assignments
+        || stake_checker.is_some();
     let transport = WorkerTransport {
         swarm,
         pings_rx,
         query_results_rx,
         logs_rx,
-        events_tx,
+        status_requests_rx,
+        events_tx: events_tx.clone(),
+        logs_resend_interval: config.logs_resend_interval,
+        resend_logs: config.logs_transport == LogsTransport::RequestResponse,
+        execution_timeout: config.execution_timeout,
+        storage_monitor: StorageMonitor::new(&config),
+        max_assignment_age_epochs: config.max_assignment_age_epochs,
+        stage_future_assignments: config.stage_future_assignments,
+        staged_assignment: None,
+        contract_client: watch_epoch.then_some(contract_client),
+        current_epoch: watch::Sender::new(None),
+        stake_checker,
+        stake_lookups: Default::default(),
+        ready: Arc::new(AtomicBool::new(config.readiness_threshold.is_none())),
     };
     let handle = WorkerTransportHandle::new(
         pings_tx,
         query_results_tx,
         logs_tx,
+        status_requests_tx,
+        events_tx,
         transport,
-        config.shutdown_timeout,
+        &config,
     );
     (events_rx, handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use subsquid_messages::DatasetChunks;
+
+    use super::*;
+
+    #[test]
+    fn test_query_cancellation() {
+        let cancellation = QueryCancellation::default();
+        let token = cancellation.token();
+        let clone = cancellation.clone();
+        assert_eq!(clone, cancellation);
+        assert_ne!(QueryCancellation::default(), cancellation);
+
+        cancellation.cancel();
+        assert!(clone.is_cancelled());
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_dataset_filter() {
+        let mut assignment = WorkerAssignment {
+            dataset_chunks: ["a", "b", "c"]
+                .into_iter()
+                .map(|dataset_id| DatasetChunks {
+                    dataset_id: dataset_id.to_owned(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let filter = DatasetFilter::default();
+        assert!(filter.allows("a"));
+        assert!(filter.apply(&mut assignment.clone()).is_empty());
+
+        let filter = DatasetFilter {
+            allowed: HashSet::from(["a".to_owned(), "b".to_owned()]),
+            denied: HashSet::from(["b".to_owned()]),
+        };
+        assert!(filter.allows("a"));
+        assert!(!filter.allows("b"));
+        assert!(!filter.allows("c"));
+        assert_eq!(filter.apply(&mut assignment), vec!["b", "c"]);
+        assert_eq!(assignment.dataset_chunks.len(), 1);
+        assert_eq!(assignment.dataset_chunks[0].dataset_id, "a");
+    }
+
+    #[test]
+    fn test_stale_assignment() {
+        let pong = |epoch| Pong {
+            status: Some(pong::Status::Active(WorkerAssignment {
+                epoch,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        assert_eq!(stale_assignment(&pong(Some(8)), Some(10), Some(1)), Some((8, 10)));
+        assert_eq!(stale_assignment(&pong(Some(9)), Some(10), Some(1)), None);
+        assert_eq!(stale_assignment(&pong(Some(9)), Some(10), Some(0)), Some((9, 10)));
+        assert_eq!(stale_assignment(&pong(Some(11)), Some(10), Some(0)), None);
+        // Not enough information to decide
+        assert_eq!(stale_assignment(&pong(Some(1)), Some(10), None), None);
+        assert_eq!(stale_assignment(&pong(Some(1)), None, Some(0)), None);
+        assert_eq!(stale_assignment(&pong(None), Some(10), Some(0)), None);
+        let jailed = Pong {
+            status: Some(pong::Status::Jailed("reason".to_owned())),
+            ..Default::default()
+        };
+        assert_eq!(stale_assignment(&jailed, Some(10), Some(0)), None);
+    }
+
+    #[test]
+    fn test_future_assignment() {
+        let pong = |epoch| Pong {
+            status: Some(pong::Status::Active(WorkerAssignment {
+                epoch,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        assert_eq!(future_assignment(&pong(Some(11)), Some(10)), Some(11));
+        assert_eq!(future_assignment(&pong(Some(10)), Some(10)), None);
+        assert_eq!(future_assignment(&pong(Some(9)), Some(10)), None);
+        // Without the current epoch, the assignment is passed through immediately
+        assert_eq!(future_assignment(&pong(Some(11)), None), None);
+        assert_eq!(future_assignment(&pong(None), Some(10)), None);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_stake_checker() {
+        let (rich, poor) = (PeerId::random(), PeerId::random());
+        let client = contract_client::MockClient::new(contract_client::MockData {
+            gateway_stakes: HashMap::from([(rich, 100.into()), (poor, 99.into())]),
+            ..Default::default()
+        });
+        let mut config = WorkerConfig::new(PeerId::random(), PeerId::random());
+        assert!(GatewayStakeChecker::new(&config, Box::new(client.clone())).is_none());
+        config.min_gateway_stake = 100.into();
+        config.max_queries_awaiting_stake = 2;
+        let mut checker = GatewayStakeChecker::new(&config, Box::new(client)).unwrap();
+
+        for gateway in [rich, poor] {
+            assert_eq!(checker.cached(&gateway, Some(1)), None);
+            let lookup = checker.defer(gateway, Query::default()).unwrap().unwrap();
+            // Only one lookup per gateway at a time
+            assert!(checker.defer(gateway, Query::default()).unwrap().is_none());
+            // Queries above the limit are returned
+            assert!(checker.defer(gateway, Query::default()).is_err());
+            let (peer_id, result) = lookup.await;
+            let (sufficient, queries) = checker.resolve(peer_id, result, Some(1));
+            assert_eq!(sufficient, gateway == rich);
+            assert_eq!(queries.len(), 2);
+            assert_eq!(checker.cached(&gateway, Some(1)), Some(gateway == rich));
+            // Cached result expires with the epoch
+            assert_eq!(checker.cached(&gateway, Some(2)), None);
+        }
+    }
+
+    #[test]
+    fn test_pending_logs() {
+        let log = |seq_no| QueryExecuted {
+            seq_no: Some(seq_no),
+            ..Default::default()
+        };
+        let seq_nos = |logs: &PendingLogs| -> Vec<Option<u64>> {
+            logs.to_vec().iter().map(|log| log.seq_no).collect()
+        };
+        let mut logs = PendingLogs::new(3);
+        for seq_no in [1, 2, 3, 4] {
+            logs.insert(seq_no, log(seq_no));
+        }
+        // The oldest log is dropped when the limit is exceeded
+        assert_eq!(seq_nos(&logs), vec![Some(2), Some(3), Some(4)]);
+
+        logs.acknowledge(2);
+        assert_eq!(seq_nos(&logs), vec![Some(3), Some(4)]);
+        // Older and repeated acknowledgements don't bring logs back or remove newer ones
+        logs.acknowledge(1);
+        logs.acknowledge(2);
+        assert_eq!(seq_nos(&logs), vec![Some(3), Some(4)]);
+        // Logs sent after the acknowledged ones are still pending
+        logs.insert(5, log(5));
+        logs.acknowledge(4);
+        assert_eq!(seq_nos(&logs), vec![Some(5)]);
+        logs.acknowledge(u64::MAX);
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn test_storage_monitor() {
+        let mut config = WorkerConfig::new(PeerId::random(), PeerId::random());
+        assert!(StorageMonitor::new(&config).is_none());
+        config.storage_capacity = Some(1000);
+        let mut monitor = StorageMonitor::new(&config).unwrap();
+
+        let crossed = |ev: Option<WorkerEvent>| match ev {
+            Some(WorkerEvent::StorageThresholdCrossed { pct, high, .. }) => Some((pct, high)),
+            _ => None,
+        };
+        // Low event is never emitted before a high one
+        assert_eq!(crossed(monitor.update(100)), None);
+        assert_eq!(crossed(monitor.update(899)), None);
+        assert_eq!(crossed(monitor.update(900)), Some((90, true)));
+        assert_eq!(crossed(monitor.update(950)), None);
+        // Hysteresis: dropping below the high watermark alone doesn't trigger the low event
+        assert_eq!(crossed(monitor.update(850)), None);
+        assert_eq!(crossed(monitor.update(920)), None);
+        assert_eq!(crossed(monitor.update(799)), Some((79, false)));
+        assert_eq!(crossed(monitor.update(500)), None);
+        assert_eq!(crossed(monitor.update(1000)), Some((100, true)));
+    }
+
+    #[test]
+    fn test_result_writer() {
+        use std::io::{Read, Write};
+
+        let mut writer = ResultWriter::new(1024);
+        writer.write_all(&[1; 1000]).unwrap();
+        writer.write_all(&[2; 24]).unwrap();
+        let data = writer.finish().unwrap();
+        assert_eq!(data.len(), 1024);
+
+        // The write exceeding the limit fails and releases the buffered data,
+        // so the rest of the result is never produced
+        let mut writer = ResultWriter::new(1024);
+        let mut endless = std::io::repeat(0).take(u64::MAX);
+        let err = std::io::copy(&mut endless, &mut writer).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<ResultTooLarge>().unwrap();
+        assert_eq!(err.limit, 1024);
+        assert!(err.size <= 1024 + 64 * 1024);
+        assert_eq!(writer.buf.capacity(), 0);
+        assert!(writer.write(&[0]).is_err());
+        assert!(writer.finish().is_err());
+
+        assert!(ResultTooLarge::check(1024, 1024).is_ok());
+        assert_eq!(
+            ResultTooLarge::check(1025, 1024),
+            Err(ResultTooLarge {
+                size: 1025,
+                limit: 1024
+            })
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroU8,
+    sync::{Arc, RwLock},
     task::{Context, Poll},
     time::Duration,
     vec,
@@ -7,7 +9,7 @@ use std::{
 
 use bimap::BiHashMap;
 use contract_client::NodeStream;
-use futures::StreamExt;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
 use futures_bounded::FuturesMap;
 use libp2p::{
     allow_block_list,
@@ -15,7 +17,7 @@ use libp2p::{
     autonat,
     autonat::NatStatus,
     core::ConnectedPoint,
-    dcutr, identify,
+    dcutr, gossipsub, identify,
     identity::Keypair,
     kad,
     kad::{
@@ -24,24 +26,26 @@ use libp2p::{
     },
     ping, relay,
     swarm::{
-        behaviour::ConnectionEstablished,
+        behaviour::{toggle::Toggle, ConnectionEstablished},
         dial_opts::{DialOpts, PeerCondition},
-        ConnectionClosed, DialFailure, FromSwarm, NetworkBehaviour, ToSwarm,
+        CloseConnection, ConnectionClosed, ConnectionId, DialFailure, FromSwarm, NetworkBehaviour,
+        ToSwarm,
     },
-    StreamProtocol,
+    Multiaddr, StreamProtocol,
 };
+use libp2p_connection_limits::ConnectionLimits;
 use libp2p_swarm_derive::NetworkBehaviour;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 
 use subsquid_messages::{
-    signatures::SignedMessage, worker_logs_msg, LogsCollected, Ping, QueryExecuted, QueryLogs,
-    WorkerLogsMsg,
+    signatures::{verify_and_decode, SignedMessage},
+    worker_logs_msg, LogsCollected, Ping, QueryExecuted, QueryLogs, WorkerLogsMsg,
 };
 
 use crate::{
     behaviour::{
-        pubsub::{PubsubBehaviour, PubsubMsg},
+        pubsub::{PubsubBehaviour, PubsubMsg, SubscribedTopics},
         wrapped::{BehaviourWrapper, TToSwarm, Wrapped},
     },
     cli::BootNode,
@@ -51,7 +55,7 @@ use crate::{
     },
     record_event,
     util::addr_is_reachable,
-    PeerId, QueueFull,
+    PeerId, ProbeError, PubsubSigning, QueueFull,
 };
 
 #[cfg(feature = "metrics")]
@@ -61,11 +65,12 @@ use crate::metrics::{ACTIVE_CONNECTIONS, ONGOING_PROBES, ONGOING_QUERIES};
 pub struct InnerBehaviour {
     identify: identify::Behaviour,
     kademlia: kad::Behaviour<MemoryStore>,
-    relay: relay::client::Behaviour,
+    relay: Toggle<relay::client::Behaviour>,
     dcutr: dcutr::Behaviour,
     ping: ping::Behaviour,
     autonat: autonat::Behaviour,
     allow: allow_block_list::Behaviour<AllowedPeers>,
+    conn_limits: libp2p_connection_limits::Behaviour,
     pubsub: Wrapped<PubsubBehaviour>,
 }
 
@@ -74,10 +79,34 @@ pub struct BaseConfig {
     pub nodes_update_interval: Duration,
     pub autonat_timeout: Duration,
     pub identify_interval: Duration,
+    /// Push identify info to connected peers when listen addresses change,
+    /// instead of waiting for the next `identify_interval`
+    pub identify_push_listen_addr_updates: bool,
+    /// Number of peers whose identify info is cached to be reported as their addresses.
+    /// Caching is disabled if zero.
+    pub identify_cache_size: usize,
     pub request_timeout: Duration,
     pub probe_timeout: Duration,
     pub max_concurrent_probes: usize,
     pub max_pubsub_msg_size: usize,
+    /// Gossipsub peer scoring parameters and thresholds. Scoring is disabled if not set.
+    #[serde(skip)]
+    pub pubsub_peer_score: Option<(gossipsub::PeerScoreParams, gossipsub::PeerScoreThresholds)>,
+    /// Gossipsub message signing policy. Non-default policies are meant for test setups only.
+    pub pubsub_signing: PubsubSigning,
+    /// Topics for which publishing is retried if there are not enough peers (e.g. during
+    /// warm-up, before the mesh forms), with the time after which the message is dropped.
+    pub pubsub_publish_retry: HashMap<String, Duration>,
+    /// Publish own messages to all peers subscribed to the topic, not only to the mesh.
+    /// Speeds up propagation in small networks, but is costly in large ones.
+    pub pubsub_flood_publish: bool,
+    /// Number of addresses of a single peer dialed concurrently
+    pub dial_concurrency_factor: NonZeroU8,
+    /// Maximum number of concurrent outgoing dials. Unlimited if not set.
+    pub max_pending_outgoing_dials: Option<u32>,
+    /// Add only publicly reachable addresses reported by identify to the DHT.
+    /// Disable for local test setups, where all addresses are private.
+    pub filter_unreachable_addrs: bool,
 }
 
 impl Default for BaseConfig {
@@ -86,14 +115,114 @@ impl Default for BaseConfig {
             nodes_update_interval: Duration::from_secs(300),
             autonat_timeout: Duration::from_secs(60),
             identify_interval: Duration::from_secs(60),
+            identify_push_listen_addr_updates: true,
+            identify_cache_size: 100,
             request_timeout: Duration::from_secs(60),
             probe_timeout: Duration::from_secs(60),
             max_concurrent_probes: 1000,
             max_pubsub_msg_size: MAX_PUBSUB_MSG_SIZE,
+            pubsub_peer_score: None,
+            pubsub_signing: PubsubSigning::default(),
+            pubsub_publish_retry: Default::default(),
+            pubsub_flood_publish: true,
+            dial_concurrency_factor: NonZeroU8::new(8).expect("non-zero"),
+            max_pending_outgoing_dials: None,
+            filter_unreachable_addrs: true,
         }
     }
 }
 
+pub const DEFAULT_MAX_BOOT_NODE_REDIAL_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Score assigned to a boot node. Boot nodes with higher priority are dialed first.
+/// If `redial_backoff` is set, the boot node is re-dialed after losing connection to it
+/// or failing to dial it. The backoff doubles with every consecutive re-dial, up to
+/// `max_redial_backoff`, and is reset once a connection is established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootNodeScore {
+    pub priority: i32,
+    pub redial_backoff: Option<Duration>,
+    pub max_redial_backoff: Duration,
+}
+
+impl Default for BootNodeScore {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            redial_backoff: None,
+            max_redial_backoff: DEFAULT_MAX_BOOT_NODE_REDIAL_BACKOFF,
+        }
+    }
+}
+
+/// Exponential backoff of boot node re-dials
+#[derive(Debug, Clone)]
+struct RedialBackoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl RedialBackoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial.min(max),
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = self.next.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next = self.initial.min(self.max);
+    }
+}
+
+pub type BootNodeScorer = Arc<dyn Fn(&BootNode) -> BootNodeScore + Send + Sync>;
+
+/// Weight of the newest sample in the moving average of round-trip times
+const RTT_EWMA_WEIGHT: f64 = 0.2;
+
+/// Round-trip times to connected peers measured by the ping protocol, smoothed with
+/// an exponentially-weighted moving average. Clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct PeerRtts(Arc<RwLock<HashMap<PeerId, Duration>>>);
+
+impl PeerRtts {
+    pub fn get(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.0.read().expect("lock poisoned").get(peer_id).copied()
+    }
+
+    fn record(&self, peer_id: PeerId, rtt: Duration) {
+        self.0
+            .write()
+            .expect("lock poisoned")
+            .entry(peer_id)
+            .and_modify(|avg| {
+                *avg = avg.mul_f64(1.0 - RTT_EWMA_WEIGHT) + rtt.mul_f64(RTT_EWMA_WEIGHT)
+            })
+            .or_insert(rtt);
+    }
+
+    fn remove(&self, peer_id: &PeerId) {
+        self.0.write().expect("lock poisoned").remove(peer_id);
+    }
+}
+
+/// Dial to an address made to learn the peer ID behind it
+struct AddrProbe {
+    peer_id: Option<PeerId>,
+    /// Keep the connection and add the peer to the DHT after it's identified
+    keep: bool,
+    /// The peer was already in the routing table when the connection was established
+    known: bool,
+}
+
 pub struct BaseBehaviour {
     inner: InnerBehaviour,
     keypair: Keypair,
@@ -103,6 +232,14 @@ pub struct BaseBehaviour {
     registered_nodes: HashSet<PeerId>,
     active_nodes_stream: NodeStream,
     max_pubsub_msg_size: usize,
+    // Boot nodes with re-dialing enabled
+    boot_nodes: HashMap<PeerId, (Multiaddr, RedialBackoff)>,
+    pending_redials: HashSet<PeerId>,
+    redials: FuturesUnordered<BoxFuture<'static, PeerId>>,
+    peer_rtts: PeerRtts,
+    filter_unreachable_addrs: bool,
+    addr_probes: HashMap<ConnectionId, AddrProbe>,
+    pending_actions: VecDeque<TToSwarm<Self>>,
 }
 
 #[allow(dead_code)]
@@ -112,7 +249,8 @@ impl BaseBehaviour {
         contract_client: Box<dyn contract_client::Client>,
         config: BaseConfig,
         boot_nodes: Vec<BootNode>,
-        relay: relay::client::Behaviour,
+        boot_node_scorer: &BootNodeScorer,
+        relay: Option<relay::client::Behaviour>,
         dht_protocol: StreamProtocol,
     ) -> Self {
         let local_peer_id = keypair.public().to_peer_id();
@@ -122,14 +260,15 @@ impl BaseBehaviour {
             identify: identify::Behaviour::new(
                 identify::Config::new(ID_PROTOCOL.to_string(), keypair.public())
                     .with_interval(config.identify_interval)
-                    .with_push_listen_addr_updates(true),
+                    .with_push_listen_addr_updates(config.identify_push_listen_addr_updates)
+                    .with_cache_size(config.identify_cache_size),
             ),
             kademlia: kad::Behaviour::with_config(
                 local_peer_id,
                 MemoryStore::new(local_peer_id),
                 kad_config,
             ),
-            relay,
+            relay: relay.into(),
             dcutr: dcutr::Behaviour::new(local_peer_id),
             ping: ping::Behaviour::new(ping::Config::default()),
             autonat: autonat::Behaviour::new(
@@ -140,10 +279,28 @@ impl BaseBehaviour {
                 },
             ),
             allow: Default::default(),
-            pubsub: PubsubBehaviour::new(keypair.clone(), config.max_pubsub_msg_size).into(),
+            conn_limits: libp2p_connection_limits::Behaviour::new(
+                ConnectionLimits::default()
+                    .with_max_pending_outgoing(config.max_pending_outgoing_dials),
+            ),
+            pubsub: PubsubBehaviour::new(
+                keypair.clone(),
+                config.max_pubsub_msg_size,
+                config.pubsub_peer_score,
+                config.pubsub_signing,
+                config.pubsub_publish_retry,
+                config.pubsub_flood_publish,
+            )
+            .into(),
         };
 
+        let mut boot_node_backoffs = HashMap::new();
         for boot_node in boot_nodes {
+            let score = boot_node_scorer(&boot_node);
+            if let Some(initial) = score.redial_backoff {
+                let backoff = RedialBackoff::new(initial, score.max_redial_backoff);
+                boot_node_backoffs.insert(boot_node.peer_id, (boot_node.address.clone(), backoff));
+            }
             inner.allow.allow_peer(boot_node.peer_id);
             inner.kademlia.add_address(&boot_node.peer_id, boot_node.address.clone());
             inner.autonat.add_server(boot_node.peer_id, Some(boot_node.address));
@@ -158,6 +315,13 @@ impl BaseBehaviour {
             registered_nodes: Default::default(),
             active_nodes_stream: contract_client.network_nodes_stream(config.nodes_update_interval),
             max_pubsub_msg_size: config.max_pubsub_msg_size,
+            boot_nodes: boot_node_backoffs,
+            pending_redials: Default::default(),
+            redials: Default::default(),
+            peer_rtts: Default::default(),
+            filter_unreachable_addrs: config.filter_unreachable_addrs,
+            addr_probes: Default::default(),
+            pending_actions: Default::default(),
         }
     }
 
@@ -175,6 +339,11 @@ impl BaseBehaviour {
         self.inner.pubsub.subscribe(LOGS_COLLECTED_TOPIC, 1);
     }
 
+    /// Topics the node is subscribed to, updated on every new subscription
+    pub fn subscribed_topics(&self) -> SubscribedTopics {
+        self.inner.pubsub.subscribed_topics()
+    }
+
     pub fn sign<T: SignedMessage>(&self, msg: &mut T) {
         msg.sign(&self.keypair)
     }
@@ -184,10 +353,8 @@ impl BaseBehaviour {
         self.inner.pubsub.publish(PING_TOPIC, ping.encode_to_vec());
     }
 
-    pub fn publish_worker_logs(&mut self, mut logs: Vec<QueryExecuted>) {
-        for log in logs.iter_mut() {
-            self.sign(log);
-        }
+    /// Publish worker logs. The logs are expected to be already signed.
+    pub fn publish_worker_logs(&mut self, logs: Vec<QueryExecuted>) {
         for bundle in bundle_messages(logs, self.max_pubsub_msg_size) {
             let msg: WorkerLogsMsg = bundle.into();
             self.inner.pubsub.publish(WORKER_LOGS_TOPIC, msg.encode_to_vec());
@@ -235,11 +402,104 @@ impl BaseBehaviour {
         Ok(false)
     }
 
+    /// Dial `addr` to learn the ID of the peer listening on it. The result is reported
+    /// with `AddrProbed` once identify completes or the connection fails.
+    /// Unless `keep` is set, the connection is closed afterwards and the peer is removed
+    /// from the routing table if it wasn't there before.
+    pub fn probe_addr(&mut self, addr: Multiaddr, keep: bool) -> ConnectionId {
+        log::debug!("Probing address {addr}");
+        let opts = DialOpts::unknown_peer_id().address(addr).build();
+        let connection_id = opts.connection_id();
+        let probe = AddrProbe {
+            peer_id: None,
+            keep,
+            known: false,
+        };
+        self.addr_probes.insert(connection_id, probe);
+        self.pending_actions.push_back(ToSwarm::Dial { opts });
+        connection_id
+    }
+
+    fn fail_addr_probe(&mut self, connection_id: ConnectionId, error: ProbeError) {
+        if self.addr_probes.remove(&connection_id).is_some() {
+            log::debug!("Address probe failed: {error}");
+            let ev = BaseBehaviourEvent::AddrProbed {
+                connection_id,
+                result: Err(error),
+            };
+            self.pending_actions.push_back(ToSwarm::GenerateEvent(ev));
+        }
+    }
+
+    /// Finish probes over connections to `peer_id`. Returns `false` if the peer's addresses
+    /// shouldn't be added to the DHT.
+    fn finish_addr_probes(&mut self, peer_id: PeerId) -> bool {
+        let mut add_to_dht = true;
+        for connection_id in self.addr_probe_connections(peer_id) {
+            let probe = self.addr_probes.remove(&connection_id).expect("probe exists");
+            log::debug!("Address probe identified peer {peer_id}");
+            let ev = BaseBehaviourEvent::AddrProbed {
+                connection_id,
+                result: Ok(peer_id),
+            };
+            self.pending_actions.push_back(ToSwarm::GenerateEvent(ev));
+            if !probe.keep {
+                self.pending_actions.push_back(ToSwarm::CloseConnection {
+                    peer_id,
+                    connection: CloseConnection::One(connection_id),
+                });
+                if !probe.known {
+                    self.inner.kademlia.remove_peer(&peer_id);
+                    add_to_dht = false;
+                }
+            }
+        }
+        add_to_dht
+    }
+
+    fn addr_probe_connections(&self, peer_id: PeerId) -> Vec<ConnectionId> {
+        self.addr_probes
+            .iter()
+            .filter(|(_, probe)| probe.peer_id == Some(peer_id))
+            .map(|(connection_id, _)| *connection_id)
+            .collect()
+    }
+
+    fn in_routing_table(&mut self, peer_id: &PeerId) -> bool {
+        self.inner
+            .kademlia
+            .kbuckets()
+            .any(|bucket| bucket.iter().any(|entry| entry.node.key.preimage() == peer_id))
+    }
+
+    /// Shared view of round-trip times to connected peers, updated by the behaviour
+    pub fn peer_rtts(&self) -> PeerRtts {
+        self.peer_rtts.clone()
+    }
+
+    /// Whether the relay client transport is enabled, i.e. `/p2p-circuit` addresses can be dialed
+    pub fn relay_enabled(&self) -> bool {
+        self.inner.relay.is_enabled()
+    }
+
     pub fn allow_peer(&mut self, peer_id: PeerId) {
         log::info!("Allowing peer {peer_id}");
         self.inner.allow.allow_peer(peer_id);
     }
 
+    fn schedule_boot_node_redial(&mut self, peer_id: PeerId) {
+        if self.pending_redials.contains(&peer_id) {
+            return;
+        }
+        let Some((_, backoff)) = self.boot_nodes.get_mut(&peer_id) else {
+            return;
+        };
+        let delay = backoff.next_delay();
+        self.pending_redials.insert(peer_id);
+        log::debug!("Scheduling re-dial of boot node {peer_id} in {delay:?}");
+        self.redials.push(tokio::time::sleep(delay).map(move |_| peer_id).boxed());
+    }
+
     // TODO: Refactor into a separate behaviour to reuse in bootnode
     fn on_nodes_update(&mut self, result: Result<HashSet<PeerId>, contract_client::ClientError>) {
         let nodes = match result {
@@ -290,6 +550,15 @@ pub enum BaseBehaviourEvent {
         peer_id: PeerId,
         protocols: Vec<StreamProtocol>,
     },
+    /// Public address of the local node has been confirmed by AutoNAT
+    ExternalAddrConfirmed {
+        addr: Multiaddr,
+    },
+    /// Result of a probe started with `probe_addr`
+    AddrProbed {
+        connection_id: ConnectionId,
+        result: Result<PeerId, ProbeError>,
+    },
 }
 
 impl BehaviourWrapper for BaseBehaviour {
@@ -304,11 +573,19 @@ impl BehaviourWrapper for BaseBehaviour {
         match ev {
             FromSwarm::ConnectionEstablished(conn) => self.on_connection_established(conn),
             FromSwarm::ConnectionClosed(conn) => self.on_connection_closed(conn),
-            FromSwarm::DialFailure(DialFailure { peer_id, error, .. }) => {
+            FromSwarm::DialFailure(DialFailure {
+                peer_id,
+                error,
+                connection_id,
+            }) => {
                 log::debug!(
                     "Failed to dial {}: {error:?}",
                     peer_id.map(|id| id.to_base58()).unwrap_or_default()
                 );
+                if let Some(peer_id) = peer_id {
+                    self.schedule_boot_node_redial(peer_id);
+                }
+                self.fail_addr_probe(connection_id, ProbeError::Dial(error.to_string()));
                 None
             }
             _ => None,
@@ -326,6 +603,9 @@ impl BehaviourWrapper for BaseBehaviour {
             InnerBehaviourEvent::Pubsub(ev) => self.on_pubsub_event(ev),
             InnerBehaviourEvent::Ping(ev) => {
                 record_event(&ev);
+                if let Ok(rtt) = ev.result {
+                    self.peer_rtts.record(ev.peer, rtt);
+                }
                 None
             }
             InnerBehaviourEvent::Dcutr(ev) => {
@@ -337,6 +617,9 @@ impl BehaviourWrapper for BaseBehaviour {
     }
 
     fn poll(&mut self, cx: &mut Context<'_>) -> Poll<impl IntoIterator<Item = TToSwarm<Self>>> {
+        if let Some(action) = self.pending_actions.pop_front() {
+            return Poll::Ready(Some(action));
+        }
         loop {
             match self.active_nodes_stream.poll_next_unpin(cx) {
                 Poll::Ready(Some(res)) => {
@@ -363,6 +646,18 @@ impl BehaviourWrapper for BaseBehaviour {
                 _ => unreachable!(), // future::pending() should never complete
             }
 
+            if let Poll::Ready(Some(peer_id)) = self.redials.poll_next_unpin(cx) {
+                self.pending_redials.remove(&peer_id);
+                let (address, _) = self.boot_nodes.get(&peer_id).expect("redials only boot nodes");
+                log::info!("Re-dialing boot node {peer_id} at {address}");
+                return Poll::Ready(Some(ToSwarm::Dial {
+                    opts: DialOpts::peer_id(peer_id)
+                        .addresses(vec![address.clone()])
+                        .condition(PeerCondition::DisconnectedAndNotDialing)
+                        .build(),
+                }));
+            }
+
             return Poll::Pending;
         }
     }
@@ -372,12 +667,21 @@ impl BaseBehaviour {
     fn on_connection_established(&mut self, conn: ConnectionEstablished) -> Option<TToSwarm<Self>> {
         #[cfg(feature = "metrics")]
         ACTIVE_CONNECTIONS.inc();
+        if let Some((_, backoff)) = self.boot_nodes.get_mut(&conn.peer_id) {
+            backoff.reset();
+        }
         let peer_id = match conn.endpoint {
             ConnectedPoint::Dialer { .. } => conn.peer_id,
             _ => return None,
         };
         log::debug!("Established outbound connection to {peer_id}");
         *self.outbound_conns.entry(peer_id).or_default() += 1;
+        if self.addr_probes.contains_key(&conn.connection_id) {
+            let known = self.in_routing_table(&peer_id);
+            let probe = self.addr_probes.get_mut(&conn.connection_id).expect("probe exists");
+            probe.peer_id = Some(peer_id);
+            probe.known = known;
+        }
         if self.probe_timeouts.remove(peer_id).is_some() {
             #[cfg(feature = "metrics")]
             ONGOING_PROBES.dec();
@@ -394,6 +698,11 @@ impl BaseBehaviour {
     fn on_connection_closed(&mut self, conn: ConnectionClosed) -> Option<TToSwarm<Self>> {
         #[cfg(feature = "metrics")]
         ACTIVE_CONNECTIONS.dec();
+        if conn.remaining_established == 0 {
+            self.peer_rtts.remove(&conn.peer_id);
+            self.schedule_boot_node_redial(conn.peer_id);
+        }
+        self.fail_addr_probe(conn.connection_id, ProbeError::ConnectionClosed);
         let peer_id = match conn.endpoint {
             ConnectedPoint::Dialer { .. } => conn.peer_id,
             _ => return None,
@@ -413,12 +722,20 @@ impl BaseBehaviour {
             identify::Event::Received { peer_id, info } => {
                 (peer_id, info.listen_addrs, info.protocols)
             }
+            identify::Event::Error { peer_id, error, .. } => {
+                for connection_id in self.addr_probe_connections(peer_id) {
+                    self.fail_addr_probe(connection_id, ProbeError::Identify(error.to_string()));
+                }
+                return None;
+            }
             _ => return None,
         };
-        let kademlia = &mut self.inner.kademlia;
-        listen_addrs.into_iter().filter(addr_is_reachable).for_each(|addr| {
-            kademlia.add_address(&peer_id, addr);
-        });
+        if self.finish_addr_probes(peer_id) {
+            let kademlia = &mut self.inner.kademlia;
+            dht_addrs(listen_addrs, self.filter_unreachable_addrs).for_each(|addr| {
+                kademlia.add_address(&peer_id, addr);
+            });
+        }
         let ev = BaseBehaviourEvent::PeerProtocols { peer_id, protocols };
         Some(ToSwarm::GenerateEvent(ev))
     }
@@ -465,7 +782,12 @@ impl BaseBehaviour {
             _ => return None,
         };
         match status {
-            NatStatus::Public(addr) => log::info!("Public address confirmed: {addr}"),
+            NatStatus::Public(addr) => {
+                log::info!("Public address confirmed: {addr}");
+                return Some(ToSwarm::GenerateEvent(BaseBehaviourEvent::ExternalAddrConfirmed {
+                    addr,
+                }));
+            }
             NatStatus::Private => log::warn!("Public address check failed."),
             NatStatus::Unknown => {}
         }
@@ -492,13 +814,9 @@ impl BaseBehaviour {
 }
 
 fn decode_ping(peer_id: PeerId, data: Box<[u8]>) -> Option<BaseBehaviourEvent> {
-    let mut ping = Ping::decode(data.as_ref())
-        .map_err(|e| log::warn!("Error decoding ping: {e:?}"))
+    let ping = verify_and_decode::<Ping>(data.as_ref(), &peer_id)
+        .map_err(|e| log::warn!("Invalid ping from {peer_id}: {e}"))
         .ok()?;
-    if !ping.verify_signature(&peer_id) {
-        log::warn!("Invalid ping signature from {peer_id}");
-        return None;
-    }
     Some(BaseBehaviourEvent::Ping { peer_id, ping })
 }
 
@@ -527,7 +845,7 @@ fn decode_logs_collected(peer_id: PeerId, data: Box<[u8]>) -> Option<BaseBehavio
     })
 }
 
-fn bundle_messages<T: prost::Message>(
+pub(crate) fn bundle_messages<T: prost::Message>(
     messages: impl IntoIterator<Item = T>,
     size_limit: usize,
 ) -> impl Iterator<Item = Vec<T>> {
@@ -557,6 +875,13 @@ fn bundle_messages<T: prost::Message>(
     })
 }
 
+/// Addresses reported by a peer which should be added to the DHT
+fn dht_addrs(addrs: Vec<Multiaddr>, filter_unreachable: bool) -> impl Iterator<Item = Multiaddr> {
+    addrs
+        .into_iter()
+        .filter(move |addr| !filter_unreachable || addr_is_reachable(addr))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -570,4 +895,49 @@ mod tests {
         assert_eq!(bundles[0].len(), 2);
         assert_eq!(bundles[1].len(), 1);
     }
+
+    #[test]
+    fn test_redial_backoff() {
+        let secs = Duration::from_secs;
+        let mut backoff = RedialBackoff::new(secs(10), secs(60));
+        // A boot node which keeps failing is re-dialed less and less often
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, [secs(10), secs(20), secs(40), secs(60), secs(60), secs(60)]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), secs(10));
+
+        let mut backoff = RedialBackoff::new(secs(10), secs(5));
+        assert_eq!(backoff.next_delay(), secs(5));
+        let mut backoff = RedialBackoff::new(Duration::MAX, Duration::MAX);
+        assert_eq!(backoff.next_delay(), Duration::MAX);
+        assert_eq!(backoff.next_delay(), Duration::MAX);
+
+        // Re-dialing is opt-in
+        assert_eq!(BootNodeScore::default().redial_backoff, None);
+    }
+
+    #[test]
+    fn test_peer_rtts() {
+        let rtts = PeerRtts::default();
+        let peer_id = PeerId::random();
+        assert_eq!(rtts.get(&peer_id), None);
+
+        rtts.record(peer_id, Duration::from_millis(100));
+        assert_eq!(rtts.get(&peer_id), Some(Duration::from_millis(100)));
+        rtts.clone().record(peer_id, Duration::from_millis(200));
+        assert_eq!(rtts.get(&peer_id), Some(Duration::from_millis(120)));
+
+        rtts.remove(&peer_id);
+        assert_eq!(rtts.get(&peer_id), None);
+    }
+
+    #[test]
+    fn test_dht_addrs() {
+        let loopback: Multiaddr = "/ip4/127.0.0.1/udp/12345/quic-v1".parse().unwrap();
+        let public: Multiaddr = "/ip4/1.2.3.4/udp/12345/quic-v1".parse().unwrap();
+        let addrs = vec![loopback.clone(), public.clone()];
+
+        assert_eq!(dht_addrs(addrs.clone(), true).collect::<Vec<_>>(), vec![public.clone()]);
+        assert_eq!(dht_addrs(addrs, false).collect::<Vec<_>>(), vec![loopback, public]);
+    }
 }
@@ -1,21 +1,30 @@
 use std::{
-    collections::HashMap,
-    time::{SystemTime, UNIX_EPOCH},
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use derivative::Derivative;
 use libp2p::{
     gossipsub,
-    gossipsub::{MessageAcceptance, MessageAuthenticity, Sha256Topic, TopicHash},
+    gossipsub::{
+        MessageAcceptance, PeerScoreParams, PeerScoreThresholds, PublishError, Sha256Topic,
+        TopicHash,
+    },
     identity::Keypair,
     swarm::{NetworkBehaviour, ToSwarm},
 };
+use sha2::{Digest, Sha256};
+use tokio::time::{Instant, Interval};
 
 use crate::{
     behaviour::wrapped::{BehaviourWrapper, TToSwarm},
-    record_event, PeerId,
+    record_event, PeerId, PubsubSigning,
 };
 
+const PUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
 struct TopicState {
     name: &'static str,
     topic: Sha256Topic,
@@ -34,6 +43,26 @@ impl TopicState {
     }
 }
 
+/// Names of the topics the node is subscribed to. Clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct SubscribedTopics(Arc<RwLock<BTreeSet<&'static str>>>);
+
+impl SubscribedTopics {
+    /// Topic names in alphabetical order
+    pub fn get(&self) -> Vec<String> {
+        self.0
+            .read()
+            .expect("lock poisoned")
+            .iter()
+            .map(|&name| name.to_owned())
+            .collect()
+    }
+
+    fn insert(&self, name: &'static str) {
+        self.0.write().expect("lock poisoned").insert(name);
+    }
+}
+
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct PubsubMsg {
@@ -43,25 +72,55 @@ pub struct PubsubMsg {
     pub data: Box<[u8]>,
 }
 
+struct PendingPublish {
+    topic: &'static str,
+    data: Vec<u8>,
+    deadline: Instant,
+}
+
 pub struct PubsubBehaviour {
     inner: gossipsub::Behaviour,
     topics: HashMap<TopicHash, TopicState>,
+    subscribed_topics: SubscribedTopics,
+    signing: PubsubSigning,
+    publish_retry: HashMap<String, Duration>,
+    pending_publishes: Vec<PendingPublish>,
+    publish_retry_timer: Option<Interval>,
 }
 
 impl PubsubBehaviour {
-    pub fn new(keypair: Keypair, max_msg_size: usize) -> Self {
+    pub fn new(
+        keypair: Keypair,
+        max_msg_size: usize,
+        peer_score: Option<(PeerScoreParams, PeerScoreThresholds)>,
+        signing: PubsubSigning,
+        publish_retry: HashMap<String, Duration>,
+        flood_publish: bool,
+    ) -> Self {
         let gossipsub_config = gossipsub::ConfigBuilder::default()
             .validate_messages()
+            .validation_mode(signing.validation_mode())
             .message_id_fn(msg_id)
             .max_transmit_size(max_msg_size)
+            .flood_publish(flood_publish)
             .build()
             .expect("config should be valid");
-        let inner =
-            gossipsub::Behaviour::new(MessageAuthenticity::Signed(keypair), gossipsub_config)
-                .expect("config should be valid");
+        let mut inner = gossipsub::Behaviour::new(signing.authenticity(keypair), gossipsub_config)
+            .expect("config should be valid");
+        if let Some((params, thresholds)) = peer_score {
+            match inner.with_peer_score(params, thresholds) {
+                Ok(()) => log::info!("Gossipsub peer scoring enabled"),
+                Err(e) => log::error!("Invalid gossipsub peer score config: {e}"),
+            }
+        }
         Self {
             inner,
             topics: Default::default(),
+            subscribed_topics: Default::default(),
+            signing,
+            publish_retry,
+            pending_publishes: Default::default(),
+            publish_retry_timer: None,
         }
     }
 
@@ -78,14 +137,64 @@ impl PubsubBehaviour {
             return;
         }
         self.topics.insert(topic_hash, topic);
+        self.subscribed_topics.insert(topic_name);
         log::info!("Topic {topic_name} subscribed");
     }
 
+    pub fn subscribed_topics(&self) -> SubscribedTopics {
+        self.subscribed_topics.clone()
+    }
+
+    /// Publish message to the topic. If publish retry is configured for the topic, and there are
+    /// not enough peers to publish to (e.g. the mesh hasn't formed yet), the message is buffered
+    /// and publishing is retried until the configured timeout elapses.
     pub fn publish(&mut self, topic_name: &'static str, msg: impl Into<Vec<u8>>) {
         log::debug!("Publishing message to topic {topic_name}");
         let topic_hash = Sha256Topic::new(topic_name).hash();
-        if let Err(e) = self.inner.publish(topic_hash, msg) {
-            log::error!("Error publishing message to {topic_name}: {e:?}");
+        let data = msg.into();
+        let Some(timeout) = self.publish_retry.get(topic_name).copied() else {
+            if let Err(e) = self.inner.publish(topic_hash, data) {
+                log::error!("Error publishing message to {topic_name}: {e:?}");
+            }
+            return;
+        };
+        match self.inner.publish(topic_hash, data.clone()) {
+            Ok(_) => {}
+            Err(PublishError::InsufficientPeers) => {
+                log::debug!(
+                    "Not enough peers to publish to {topic_name}. Retrying for {timeout:?}"
+                );
+                self.pending_publishes.push(PendingPublish {
+                    topic: topic_name,
+                    data,
+                    deadline: Instant::now() + timeout,
+                });
+                self.publish_retry_timer.get_or_insert_with(|| {
+                    tokio::time::interval_at(
+                        Instant::now() + PUBLISH_RETRY_INTERVAL,
+                        PUBLISH_RETRY_INTERVAL,
+                    )
+                });
+            }
+            Err(e) => log::error!("Error publishing message to {topic_name}: {e:?}"),
+        }
+    }
+
+    fn retry_pending_publishes(&mut self) {
+        let now = Instant::now();
+        for pending in std::mem::take(&mut self.pending_publishes) {
+            let topic_name = pending.topic;
+            let topic_hash = Sha256Topic::new(topic_name).hash();
+            match self.inner.publish(topic_hash, pending.data.clone()) {
+                Ok(_) => log::debug!("Message to {topic_name} published after retrying"),
+                Err(PublishError::InsufficientPeers) if now < pending.deadline => {
+                    self.pending_publishes.push(pending)
+                }
+                Err(e) => log::error!("Error publishing message to {topic_name}: {e:?}"),
+            }
+        }
+        if self.pending_publishes.is_empty() {
+            self.publish_retry_timer = None;
         }
     }
 
@@ -93,18 +202,29 @@ impl PubsubBehaviour {
     ///   1) Check if message is not anonymous,
     ///   2) Check if topic is known (subscribed),
     ///   3) Enforce message ordering (if configured for topic).
+    ///
+    /// With anonymous signing policy, messages are attributed to the propagation source
+    /// and ordering is not enforced (there are no sequence numbers).
     fn validate_gossipsub_msg(
         &mut self,
         msg: gossipsub::Message,
+        propagation_source: PeerId,
     ) -> Result<PubsubMsg, &'static str> {
-        let peer_id = match msg.source {
-            Some(peer_id) => peer_id,
-            None => return Err("anonymous message"),
-        };
         let topic_state = match self.topics.get_mut(&msg.topic) {
             Some(x) => x,
             None => return Err("message with unknown topic"),
         };
+        let peer_id = match (msg.source, self.signing) {
+            (None, PubsubSigning::Anonymous) => {
+                return Ok(PubsubMsg {
+                    peer_id: propagation_source,
+                    topic: topic_state.name,
+                    data: msg.data.into_boxed_slice(),
+                })
+            }
+            (Some(peer_id), _) => peer_id,
+            (None, _) => return Err("anonymous message"),
+        };
         let last_seq_no = topic_state.sequence_numbers.entry(peer_id).or_default();
         match msg.sequence_number {
             None => return Err("message without sequence number"),
@@ -148,7 +268,7 @@ impl BehaviourWrapper for PubsubBehaviour {
             _ => return None,
         };
 
-        match self.validate_gossipsub_msg(msg) {
+        match self.validate_gossipsub_msg(msg, propagation_source) {
             Ok(msg) => {
                 let _ = self.inner.report_message_validation_result(
                     &message_id,
@@ -168,10 +288,31 @@ impl BehaviourWrapper for PubsubBehaviour {
             }
         }
     }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<impl IntoIterator<Item = TToSwarm<Self>>> {
+        while let Some(timer) = &mut self.publish_retry_timer {
+            if timer.poll_tick(cx).is_pending() {
+                break;
+            }
+            self.retry_pending_publishes();
+        }
+        Poll::<Option<TToSwarm<Self>>>::Pending
+    }
 }
 
-// Default gossipsub msg ID function, copied from libp2p
+// Default gossipsub msg ID function, copied from libp2p.
+// Anonymous messages have neither source nor sequence number, so they're identified by content.
+// The hash has to be the same on every node, regardless of the toolchain it was built with.
 fn msg_id(msg: &gossipsub::Message) -> gossipsub::MessageId {
+    if msg.source.is_none() && msg.sequence_number.is_none() {
+        let topic = msg.topic.as_str().as_bytes();
+        let digest = Sha256::new()
+            .chain_update((topic.len() as u64).to_be_bytes())
+            .chain_update(topic)
+            .chain_update(&msg.data)
+            .finalize();
+        return gossipsub::MessageId::new(&digest);
+    }
     let mut source_string = if let Some(peer_id) = msg.source.as_ref() {
         peer_id.to_base58()
     } else {
@@ -67,6 +67,8 @@ where
     waiting_for_connection: HashMap<PeerId, HashSet<OutboundRequestId>>,
     // Requests that were submitted for the second time, after the peer had been found (new_id -> old_id)
     resubmitted_requests: BTreeMap<OutboundRequestId, OutboundRequestId>,
+    // Requests which have been sent, but whose outcome should be ignored
+    cancelled_requests: HashSet<OutboundRequestId>,
     // Timeouts for peer lookups
     lookup_timeouts: FuturesMap<PeerId, ()>,
     max_buffered: usize,
@@ -96,11 +98,19 @@ where
             original_requests: Default::default(),
             waiting_for_connection: Default::default(),
             resubmitted_requests: Default::default(),
+            cancelled_requests: Default::default(),
             lookup_timeouts: FuturesMap::new(send_timeout, max_buffered),
             max_buffered,
         }
     }
 
+    /// Number of outbound requests currently in flight, excluding the ones waiting
+    /// for the peer to be found
+    pub fn ongoing_requests(&self) -> usize {
+        let waiting: usize = self.waiting_for_connection.values().map(HashSet::len).sum();
+        self.original_requests.len() - waiting + self.resubmitted_requests.len()
+    }
+
     /// Try to send a request. It will be dropped if the outbound buffer is full
     pub fn try_send_request(
         &mut self,
@@ -121,6 +131,28 @@ where
         Ok(req_id)
     }
 
+    /// Stop tracking the request. If it's already been sent, its response is ignored.
+    /// The protocol can't notify the peer, so it may still process the request.
+    pub fn cancel_request(&mut self, req_id: OutboundRequestId) {
+        let was_waiting = self.waiting_for_connection.values_mut().any(|reqs| reqs.remove(&req_id));
+        self.waiting_for_connection.retain(|_, reqs| !reqs.is_empty());
+        self.original_requests.remove(&req_id);
+        let resubmitted_as = self
+            .resubmitted_requests
+            .iter()
+            .find_map(|(new_id, old_id)| (*old_id == req_id).then_some(*new_id));
+        match resubmitted_as {
+            Some(new_id) => {
+                self.resubmitted_requests.remove(&new_id);
+                self.cancelled_requests.insert(new_id);
+            }
+            None if !was_waiting => {
+                self.cancelled_requests.insert(req_id);
+            }
+            None => {}
+        }
+    }
+
     fn on_timeout(&mut self, peer_id: PeerId) -> Vec<TToSwarm<Self>> {
         let buffered = self.waiting_for_connection.remove(&peer_id).unwrap_or_default();
         log::debug!("Lookup for peer {peer_id} timed out, dropping {} requests", buffered.len());
@@ -159,6 +191,9 @@ where
         response: C::Response,
     ) -> Option<TToSwarm<Self>> {
         log::debug!("Request {req_id} successful");
+        if self.cancelled_requests.remove(&req_id) {
+            return None;
+        }
         self.original_requests.remove(&req_id);
         let req_id = self.resubmitted_requests.remove(&req_id).unwrap_or(req_id);
         Some(ToSwarm::GenerateEvent(ClientEvent::Response {
@@ -170,6 +205,9 @@ where
 
     fn on_failure(&mut self, peer_id: PeerId, req_id: OutboundRequestId) -> Option<TToSwarm<Self>> {
         log::debug!("Request {req_id} failed");
+        if self.cancelled_requests.remove(&req_id) {
+            return None;
+        }
         // If request was already resubmitted, remove it and emit timeout event
         if let Some(req_id) = self.resubmitted_requests.remove(&req_id) {
             return Some(ToSwarm::GenerateEvent(ClientEvent::Timeout { peer_id, req_id }));
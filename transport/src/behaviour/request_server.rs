@@ -1,8 +1,10 @@
+use std::collections::HashSet;
+
 use crate::behaviour::wrapped::{BehaviourWrapper, TToSwarm};
 use derivative::Derivative;
 use libp2p::{
     request_response,
-    request_response::{Codec, ProtocolSupport, ResponseChannel},
+    request_response::{Codec, InboundRequestId, ProtocolSupport, ResponseChannel},
     swarm::ToSwarm,
     PeerId,
 };
@@ -22,6 +24,8 @@ where
     C: Codec + Clone + Send + 'static,
 {
     inner: request_response::Behaviour<C>,
+    // Requests which haven't been responded to yet
+    ongoing_requests: HashSet<InboundRequestId>,
 }
 
 impl<C> ServerBehaviour<C>
@@ -34,7 +38,15 @@ where
             vec![(protocol, ProtocolSupport::Inbound)],
             request_response::Config::default(),
         );
-        Self { inner }
+        Self {
+            inner,
+            ongoing_requests: Default::default(),
+        }
+    }
+
+    /// Number of inbound requests waiting for a response
+    pub fn ongoing_requests(&self) -> usize {
+        self.ongoing_requests.len()
     }
 
     pub fn try_send_response(
@@ -66,18 +78,29 @@ where
                 peer,
                 message:
                     request_response::Message::Request {
-                        request, channel, ..
+                        request_id,
+                        request,
+                        channel,
                     },
             } => {
+                self.ongoing_requests.insert(request_id);
                 return Some(ToSwarm::GenerateEvent(Request {
                     peer_id: peer,
                     request,
                     response_channel: channel,
-                }))
+                }));
             }
-            request_response::Event::InboundFailure { peer, error, .. } => {
+            request_response::Event::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.ongoing_requests.remove(&request_id);
                 log::error!("Request from {peer} failed: {error:?}")
             }
+            request_response::Event::ResponseSent { request_id, .. } => {
+                self.ongoing_requests.remove(&request_id);
+            }
             _ => {}
         }
         None
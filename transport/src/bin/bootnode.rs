@@ -6,9 +6,7 @@ use futures::StreamExt;
 use libp2p::{
     allow_block_list,
     allow_block_list::AllowedPeers,
-    autonat,
-    gossipsub::{self, MessageAuthenticity},
-    identify,
+    autonat, gossipsub, identify,
     kad::{self, store::MemoryStore, Mode},
     ping, relay,
     swarm::SwarmEvent,
@@ -20,8 +18,8 @@ use tokio::signal::unix::{signal, SignalKind};
 
 use subsquid_network_transport::{
     protocol::{dht_protocol, ID_PROTOCOL},
-    util::{addr_is_reachable, get_keypair},
-    BootNode, Keypair, QuicConfig, TransportArgs,
+    util::{addr_is_reachable, dns_resolver_config, get_keypair},
+    BootNode, Keypair, PubsubSigning, QuicConfig, TransportArgs,
 };
 
 #[cfg(not(target_env = "msvc"))]
@@ -39,6 +37,15 @@ struct Cli {
 
     #[arg(long, env, value_delimiter = ',', help = "Allowed nodes")]
     allowed_nodes: Vec<PeerId>,
+
+    #[arg(
+        long,
+        env,
+        value_enum,
+        default_value_t = PubsubSigning::Strict,
+        help = "Gossipsub message signing policy (non-strict policies are for test setups only)"
+    )]
+    pubsub_signing: PubsubSigning,
 }
 
 #[derive(NetworkBehaviour)]
@@ -87,8 +94,11 @@ async fn main() -> anyhow::Result<()> {
         ),
         relay: relay::Behaviour::new(local_peer_id, Default::default()),
         gossipsub: gossipsub::Behaviour::new(
-            MessageAuthenticity::Signed(keypair.clone()),
-            Default::default(),
+            cli.pubsub_signing.authenticity(keypair.clone()),
+            gossipsub::ConfigBuilder::default()
+                .validation_mode(cli.pubsub_signing.validation_mode())
+                .build()
+                .expect("config should be valid"),
         )
         .unwrap(),
         ping: ping::Behaviour::new(Default::default()),
@@ -101,10 +111,11 @@ async fn main() -> anyhow::Result<()> {
 
     // Start the swarm
     let quic_config = QuicConfig::from_env();
+    let (dns_config, dns_opts) = dns_resolver_config(&cli.transport.dns_servers)?;
     let mut swarm = SwarmBuilder::with_existing_identity(keypair)
         .with_tokio()
-        .with_quic_config(|config| config.mtu_upper_bound(quic_config.mtu_discovery_max))
-        .with_dns()?
+        .with_quic_config(|config| quic_config.apply(config))
+        .with_dns_config(dns_config, dns_opts)
         .with_behaviour(behaviour)
         .expect("infallible")
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(120)))
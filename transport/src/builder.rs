@@ -1,17 +1,18 @@
-use std::time::Duration;
+use std::{cmp::Reverse, net::IpAddr, sync::Arc};
 
-use futures_core::Stream;
+use contract_client::Network;
 use libp2p::{
+    core::{transport::MemoryTransport, upgrade},
     multiaddr::Protocol,
     noise,
     swarm::{dial_opts::DialOpts, NetworkBehaviour},
-    yamux, StreamProtocol, Swarm, SwarmBuilder,
+    yamux, StreamProtocol, Swarm, SwarmBuilder, Transport,
 };
 
 use crate::{
-    behaviour::base::{BaseBehaviour, BaseConfig},
+    behaviour::base::{BaseBehaviour, BaseConfig, BootNodeScore, BootNodeScorer},
     cli::{BootNode, TransportArgs},
-    util::get_keypair,
+    util::{dns_resolver_config, get_keypair, Receiver},
     Error, Keypair, Multiaddr, PeerId, QuicConfig,
 };
 
@@ -37,18 +38,24 @@ use crate::actors::worker::{
     self, WorkerBehaviour, WorkerConfig, WorkerEvent, WorkerTransportHandle,
 };
 use crate::protocol::dht_protocol;
+#[cfg(feature = "worker")]
+use crate::protocol::QUERY_RESULT_OVERHEAD;
 
 pub struct P2PTransportBuilder {
     keypair: Keypair,
     listen_addrs: Vec<Multiaddr>,
     public_addrs: Vec<Multiaddr>,
     boot_nodes: Vec<BootNode>,
+    boot_node_scorer: BootNodeScorer,
     relay_addrs: Vec<Multiaddr>,
     relay: bool,
     quic_config: QuicConfig,
     base_config: BaseConfig,
     contract_client: Box<dyn contract_client::Client>,
     dht_protocol: StreamProtocol,
+    memory_transport: bool,
+    standalone: bool,
+    dns_servers: Vec<IpAddr>,
 }
 impl P2PTransportBuilder {
     pub async fn from_cli(args: TransportArgs) -> anyhow::Result<Self> {
@@ -61,15 +68,44 @@ impl P2PTransportBuilder {
             listen_addrs,
             public_addrs: args.p2p_public_addrs,
             boot_nodes: args.boot_nodes,
+            boot_node_scorer: Arc::new(|_| BootNodeScore::default()),
             relay_addrs: vec![],
             relay: false,
             quic_config: QuicConfig::from_env(),
             base_config: Default::default(),
             contract_client,
             dht_protocol,
+            memory_transport: false,
+            standalone: false,
+            dns_servers: args.dns_servers,
         })
     }
 
+    /// Create a builder without connecting to RPC or reading CLI arguments,
+    /// e.g. for tests and local simulations.
+    pub fn new(
+        keypair: Keypair,
+        contract_client: Box<dyn contract_client::Client>,
+        network: Network,
+    ) -> Self {
+        Self {
+            keypair,
+            listen_addrs: vec![],
+            public_addrs: vec![],
+            boot_nodes: vec![],
+            boot_node_scorer: Arc::new(|_| BootNodeScore::default()),
+            relay_addrs: vec![],
+            relay: false,
+            quic_config: QuicConfig::from_env(),
+            base_config: Default::default(),
+            contract_client,
+            dht_protocol: dht_protocol(network),
+            memory_transport: false,
+            standalone: false,
+            dns_servers: vec![],
+        }
+    }
+
     pub fn with_listen_addrs<I: IntoIterator<Item = Multiaddr>>(mut self, addrs: I) -> Self {
         self.listen_addrs.extend(addrs);
         self
@@ -85,6 +121,17 @@ impl P2PTransportBuilder {
         self
     }
 
+    /// Set a function scoring boot nodes, which determines the order in which they are dialed
+    /// and whether to re-dial them when disconnected. By default, boot nodes are dialed
+    /// in the order they were provided and are not re-dialed.
+    pub fn with_boot_node_scorer(
+        mut self,
+        scorer: impl Fn(&BootNode) -> BootNodeScore + Send + Sync + 'static,
+    ) -> Self {
+        self.boot_node_scorer = Arc::new(scorer);
+        self
+    }
+
     pub fn with_relay(mut self, relay: bool) -> Self {
         self.relay = relay;
         self
@@ -101,6 +148,31 @@ impl P2PTransportBuilder {
         self
     }
 
+    pub fn with_base_config(mut self, f: impl FnOnce(BaseConfig) -> BaseConfig) -> Self {
+        self.base_config = f(self.base_config);
+        self
+    }
+
+    /// Use in-memory transport instead of QUIC. Swarms in the same process can then
+    /// connect over `/memory/<port>` addresses. Meant for tests and local simulations only.
+    pub fn with_memory_transport(mut self, memory_transport: bool) -> Self {
+        self.memory_transport = memory_transport;
+        self
+    }
+
+    /// Allow building a node without boot nodes. Without them, the node has no way to discover
+    /// peers, so it's only useful if other nodes connect to it (e.g. it's a boot node itself).
+    pub fn with_standalone(mut self, standalone: bool) -> Self {
+        self.standalone = standalone;
+        self
+    }
+
+    /// Resolve `/dns` addresses using the given name servers instead of the system resolver
+    pub fn with_dns_servers<I: IntoIterator<Item = IpAddr>>(mut self, servers: I) -> Self {
+        self.dns_servers.extend(servers);
+        self
+    }
+
     pub fn local_peer_id(&self) -> PeerId {
         self.keypair.public().to_peer_id()
     }
@@ -117,30 +189,70 @@ impl P2PTransportBuilder {
         mut self,
         behaviour: impl FnOnce(BaseBehaviour) -> T,
     ) -> Result<Swarm<T>, Error> {
-        let mut swarm = SwarmBuilder::with_existing_identity(self.keypair)
-            .with_tokio()
-            .with_quic_config(|config| {
-                let mut config = config.mtu_upper_bound(self.quic_config.mtu_discovery_max);
-                config.keep_alive_interval =
-                    Duration::from_millis(self.quic_config.keep_alive_interval_ms as u64);
-                config.max_idle_timeout = self.quic_config.max_idle_timeout_ms;
-                config
-            })
-            .with_dns()?
-            .with_relay_client(noise::Config::new, yamux::Config::default)?
-            .with_behaviour(|keypair, relay| {
-                let base = BaseBehaviour::new(
-                    keypair,
-                    self.contract_client,
-                    self.base_config,
-                    self.boot_nodes.clone(),
-                    relay,
-                    self.dht_protocol,
-                );
-                behaviour(base)
-            })
-            .expect("infallible")
-            .build();
+        if self.boot_nodes.is_empty() && !self.standalone {
+            return Err(Error::NoDiscoveryConfigured);
+        }
+        let dial_concurrency_factor = self.base_config.dial_concurrency_factor;
+        let scorer = &self.boot_node_scorer;
+        self.boot_nodes.sort_by_cached_key(|node| Reverse(scorer(node).priority));
+
+        let new_behaviour = |keypair: &Keypair, relay| {
+            let base = BaseBehaviour::new(
+                keypair,
+                self.contract_client,
+                self.base_config,
+                self.boot_nodes.clone(),
+                &self.boot_node_scorer,
+                relay,
+                self.dht_protocol,
+            );
+            behaviour(base)
+        };
+        // Relay client transport is only added if the node is going to listen via a relay
+        let relay = self.relay;
+        macro_rules! finish {
+            ($builder:expr) => {
+                if relay {
+                    $builder
+                        .with_relay_client(noise::Config::new, yamux::Config::default)?
+                        .with_behaviour(|keypair, relay| new_behaviour(keypair, Some(relay)))
+                        .expect("infallible")
+                        .with_swarm_config(|c| {
+                            c.with_dial_concurrency_factor(dial_concurrency_factor)
+                        })
+                        .build()
+                } else {
+                    $builder
+                        .with_behaviour(|keypair| new_behaviour(keypair, None))
+                        .expect("infallible")
+                        .with_swarm_config(|c| {
+                            c.with_dial_concurrency_factor(dial_concurrency_factor)
+                        })
+                        .build()
+                }
+            };
+        }
+        let mut swarm = if self.memory_transport {
+            let builder = SwarmBuilder::with_existing_identity(self.keypair)
+                .with_tokio()
+                .with_other_transport(|keypair| {
+                    Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                        MemoryTransport::default()
+                            .upgrade(upgrade::Version::V1)
+                            .authenticate(noise::Config::new(keypair)?)
+                            .multiplex(yamux::Config::default()),
+                    )
+                })
+                .map_err(|e| Error::Transport(e.to_string()))?;
+            finish!(builder)
+        } else {
+            let (dns_config, dns_opts) = dns_resolver_config(&self.dns_servers)?;
+            let builder = SwarmBuilder::with_existing_identity(self.keypair)
+                .with_tokio()
+                .with_quic_config(|config| self.quic_config.apply(config))
+                .with_dns_config(dns_config, dns_opts);
+            finish!(builder)
+        };
 
         // If relay node not specified explicitly, use boot nodes
         if self.relay && self.relay_addrs.is_empty() {
@@ -161,10 +273,10 @@ impl P2PTransportBuilder {
             swarm.add_external_address(addr);
         }
 
-        // Connect to boot nodes
-        for BootNode { peer_id, address } in self.boot_nodes {
-            log::info!("Connecting to boot node {peer_id} at {address}");
-            swarm.dial(DialOpts::peer_id(peer_id).addresses(vec![address]).build())?;
+        // Connect to boot nodes, dialing each peer once with all of its addresses
+        for (peer_id, addresses) in group_boot_nodes(&self.boot_nodes) {
+            log::info!("Connecting to boot node {peer_id} at {addresses:?}");
+            swarm.dial(DialOpts::peer_id(peer_id).addresses(addresses).build())?;
         }
 
         // Connect to relay and listen for relayed connections
@@ -182,7 +294,7 @@ impl P2PTransportBuilder {
     pub fn build_gateway(
         self,
         config: GatewayConfig,
-    ) -> Result<(impl Stream<Item = GatewayEvent>, GatewayTransportHandle), Error> {
+    ) -> Result<(Receiver<GatewayEvent>, GatewayTransportHandle), Error> {
         let swarm = self.build_swarm(|base| GatewayBehaviour::new(base, config.clone()))?;
         Ok(gateway::start_transport(swarm, config))
     }
@@ -191,7 +303,7 @@ impl P2PTransportBuilder {
     pub fn build_logs_collector(
         self,
         config: LogsCollectorConfig,
-    ) -> Result<(impl Stream<Item = LogsCollectorEvent>, LogsCollectorTransportHandle), Error> {
+    ) -> Result<(Receiver<LogsCollectorEvent>, LogsCollectorTransportHandle), Error> {
         let swarm = self.build_swarm(|base| LogsCollectorBehaviour::new(base, config.clone()))?;
         Ok(logs_collector::start_transport(swarm, config))
     }
@@ -200,7 +312,7 @@ impl P2PTransportBuilder {
     pub fn build_observer(
         self,
         config: ObserverConfig,
-    ) -> Result<(impl Stream<Item = ObserverEvent>, ObserverTransportHandle), Error> {
+    ) -> Result<(Receiver<ObserverEvent>, ObserverTransportHandle), Error> {
         let swarm =
             self.build_swarm(|base| ObserverBehaviour::new(base, config.logs_collector_id))?;
         Ok(observer::start_transport(swarm, config))
@@ -210,7 +322,7 @@ impl P2PTransportBuilder {
     pub fn build_scheduler(
         self,
         config: SchedulerConfig,
-    ) -> Result<(impl Stream<Item = SchedulerEvent>, SchedulerTransportHandle), Error> {
+    ) -> Result<(Receiver<SchedulerEvent>, SchedulerTransportHandle), Error> {
         let swarm = self.build_swarm(|base| SchedulerBehaviour::new(base, config.clone()))?;
         Ok(scheduler::start_transport(swarm, config))
     }
@@ -219,10 +331,113 @@ impl P2PTransportBuilder {
     pub fn build_worker(
         self,
         config: WorkerConfig,
-    ) -> Result<(impl Stream<Item = WorkerEvent>, WorkerTransportHandle), Error> {
+    ) -> Result<(Receiver<WorkerEvent>, WorkerTransportHandle), Error> {
+        if config.logs_resend_interval.is_zero() {
+            return Err(Error::InvalidConfig("logs_resend_interval must be non-zero"));
+        }
+        if config.max_result_bytes as u64 + QUERY_RESULT_OVERHEAD > config.max_query_result_size {
+            return Err(Error::InvalidConfig(
+                "max_result_bytes with overhead exceeds max_query_result_size",
+            ));
+        }
         let local_peer_id = self.local_peer_id();
+        let contract_client = self.contract_client();
         let swarm =
             self.build_swarm(|base| WorkerBehaviour::new(base, local_peer_id, config.clone()))?;
-        Ok(worker::start_transport(swarm, config))
+        Ok(worker::start_transport(swarm, config, contract_client))
+    }
+}
+
+/// Group boot node addresses by peer ID, preserving the order of first occurrence
+fn group_boot_nodes(boot_nodes: &[BootNode]) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    let mut grouped: Vec<(PeerId, Vec<Multiaddr>)> = Vec::with_capacity(boot_nodes.len());
+    for BootNode { peer_id, address } in boot_nodes {
+        match grouped.iter_mut().find(|(id, _)| id == peer_id) {
+            Some((_, addresses)) => {
+                log::info!("Boot node {peer_id} listed multiple times, merging address {address}");
+                if !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+            }
+            None => grouped.push((*peer_id, vec![address.clone()])),
+        }
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use libp2p::swarm::SwarmEvent;
+
+    use super::*;
+    use crate::behaviour::wrapped::Wrapped;
+
+    fn memory_swarm(listen_addrs: Vec<Multiaddr>) -> Swarm<Wrapped<BaseBehaviour>> {
+        let client = contract_client::MockClient::new(Default::default());
+        P2PTransportBuilder::new(Keypair::generate_ed25519(), Box::new(client), Network::Tethys)
+            .with_memory_transport(true)
+            .with_standalone(true)
+            .with_listen_addrs(listen_addrs)
+            .build_swarm(Wrapped::from)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport() {
+        let listen_addr: Multiaddr = "/memory/6790".parse().unwrap();
+        let mut listener = memory_swarm(vec![listen_addr.clone()]);
+        let mut dialer = memory_swarm(vec![]);
+        let listener_id = *listener.local_peer_id();
+        dialer.dial(listen_addr.with(Protocol::P2p(listener_id))).unwrap();
+
+        let connected = async {
+            loop {
+                tokio::select! {
+                    _ = listener.select_next_some() => {}
+                    ev = dialer.select_next_some() => {
+                        if let SwarmEvent::ConnectionEstablished { peer_id, .. } = ev {
+                            break peer_id;
+                        }
+                    }
+                }
+            }
+        };
+        let peer_id = tokio::time::timeout(Duration::from_secs(10), connected)
+            .await
+            .expect("connection established");
+        assert_eq!(peer_id, listener_id);
+    }
+
+    #[test]
+    fn test_group_boot_nodes() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let addr = |port: u16| -> Multiaddr {
+            format!("/ip4/1.2.3.4/udp/{port}/quic-v1").parse().unwrap()
+        };
+        let boot_nodes = [
+            BootNode {
+                peer_id: a,
+                address: addr(1),
+            },
+            BootNode {
+                peer_id: b,
+                address: addr(2),
+            },
+            BootNode {
+                peer_id: a,
+                address: addr(3),
+            },
+            BootNode {
+                peer_id: a,
+                address: addr(1),
+            },
+        ];
+        assert_eq!(
+            group_boot_nodes(&boot_nodes),
+            vec![(a, vec![addr(1), addr(3)]), (b, vec![addr(2)])]
+        );
     }
 }
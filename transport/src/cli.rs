@@ -2,7 +2,7 @@ use crate::PeerId;
 use clap::Args;
 use contract_client::RpcArgs;
 use libp2p::Multiaddr;
-use std::{path::PathBuf, str::FromStr};
+use std::{net::IpAddr, path::PathBuf, str::FromStr};
 
 #[derive(Args)]
 pub struct TransportArgs {
@@ -37,6 +37,15 @@ pub struct TransportArgs {
     )]
     pub boot_nodes: Vec<BootNode>,
 
+    #[arg(
+        long,
+        env,
+        help = "DNS name servers to use instead of the system resolver",
+        value_delimiter = ',',
+        num_args = 1..,
+    )]
+    pub dns_servers: Vec<IpAddr>,
+
     #[command(flatten)]
     pub rpc: RpcArgs,
 }
@@ -6,6 +6,8 @@ use libp2p::request_response;
 use prost::Message;
 
 pub const ACK_SIZE: u64 = 4;
+/// `WorkerLogsAck` with the largest `last_seq_no`: 1 tag byte and a 10-byte varint
+pub const WORKER_LOGS_ACK_SIZE: u64 = 11;
 
 pub struct ProtoCodec<Req, Res> {
     _req: PhantomData<Req>,
@@ -93,3 +95,25 @@ impl<Req: Message + Default, Res: Message + Default> request_response::Codec
         io.write_all(buf.as_slice()).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::io::Cursor;
+    use libp2p::request_response::Codec;
+    use subsquid_messages::WorkerLogsAck;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_worker_logs_ack_size() {
+        let mut codec = ProtoCodec::<(), WorkerLogsAck>::new(0, WORKER_LOGS_ACK_SIZE);
+        let ack = WorkerLogsAck {
+            last_seq_no: Some(u64::MAX),
+        };
+        let mut buf = Vec::new();
+        codec.write_response(&"", &mut buf, ack.clone()).await.unwrap();
+        assert_eq!(buf.len() as u64, WORKER_LOGS_ACK_SIZE);
+        let decoded = codec.read_response(&"", &mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(decoded, ack);
+    }
+}
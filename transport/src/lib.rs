@@ -14,9 +14,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use libp2p::{noise, swarm::DialError, TransportError};
+use libp2p::{
+    gossipsub::{MessageAuthenticity, ValidationMode},
+    noise,
+    swarm::DialError,
+    TransportError,
+};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 use tokio::sync::mpsc;
 
 pub use libp2p::{
@@ -45,7 +50,8 @@ pub mod util;
 
 #[cfg(feature = "gateway")]
 pub use crate::actors::gateway::{
-    GatewayBehaviour, GatewayConfig, GatewayEvent, GatewayTransportHandle,
+    check_workers_health, consistent_select, BudgetError, GatewayBehaviour, GatewayConfig,
+    GatewayEvent, GatewayTransportHandle, HealthSummary, QueryBudget, SendQueryError, WorkerHealth,
 };
 #[cfg(feature = "logs-collector")]
 pub use crate::actors::logs_collector::{
@@ -53,30 +59,88 @@ pub use crate::actors::logs_collector::{
 };
 #[cfg(feature = "observer")]
 pub use crate::actors::observer::{
-    ObserverBehaviour, ObserverConfig, ObserverEvent, ObserverTransportHandle,
+    aggregated_stream, EpochStats, LatencyHistogram, LatencySummary, ObserverBehaviour,
+    ObserverConfig, ObserverEvent, ObserverTransportHandle, StatsConfig,
 };
 #[cfg(feature = "scheduler")]
 pub use crate::actors::scheduler::{
-    SchedulerBehaviour, SchedulerConfig, SchedulerEvent, SchedulerTransportHandle,
+    assignment_balance, BalanceStats, SchedulerBehaviour, SchedulerConfig, SchedulerEvent,
+    SchedulerTransportHandle,
 };
 #[cfg(feature = "worker")]
 pub use crate::actors::worker::{
-    WorkerBehaviour, WorkerConfig, WorkerEvent, WorkerTransportHandle,
+    LogsTransport, QueryCancellation, ResultTooLarge, ResultWriter, WorkerBehaviour, WorkerConfig,
+    WorkerEvent, WorkerTransportHandle,
 };
 #[cfg(feature = "actors")]
+pub use behaviour::base::{BaseConfig, BootNodeScore, BootNodeScorer};
+#[cfg(feature = "actors")]
 pub use builder::P2PTransportBuilder;
 pub use cli::{BootNode, TransportArgs};
 
+/// QUIC transport tuning. The node listens only on QUIC, so there are no TCP-specific options
+/// (like listen backlog), and UDP socket buffers are managed by the QUIC implementation.
+/// Options left unset keep the library defaults.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuicConfig {
     pub mtu_discovery_max: u16,
     pub keep_alive_interval_ms: u32,
     pub max_idle_timeout_ms: u32,
+    /// Maximum number of concurrent inbound streams per connection
+    pub max_concurrent_streams: Option<u32>,
+    /// Receive window of a single stream (bytes)
+    pub max_stream_data: Option<u32>,
+    /// Receive window of a whole connection (bytes)
+    pub max_connection_data: Option<u32>,
+}
+
+/// Gossipsub message signing policy. Determines both how outgoing messages are authenticated
+/// and how incoming messages are validated, so that the two can't be configured inconsistently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PubsubSigning {
+    /// Sign outgoing messages, reject incoming messages without a valid signature
+    #[default]
+    Strict,
+    /// Sign outgoing messages, verify signatures of incoming messages only if present
+    Permissive,
+    /// Don't sign outgoing messages, reject incoming messages with author information.
+    /// Messages are attributed to the peer which forwarded them. Intended for test setups only.
+    Anonymous,
+}
+
+impl PubsubSigning {
+    pub fn authenticity(&self, keypair: Keypair) -> MessageAuthenticity {
+        match self {
+            Self::Strict | Self::Permissive => MessageAuthenticity::Signed(keypair),
+            Self::Anonymous => MessageAuthenticity::Anonymous,
+        }
+    }
+
+    pub fn validation_mode(&self) -> ValidationMode {
+        match self {
+            Self::Strict => ValidationMode::Strict,
+            Self::Permissive => ValidationMode::Permissive,
+            Self::Anonymous => ValidationMode::Anonymous,
+        }
+    }
+}
+
+/// Number of request-response streams currently open for a protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamCounts {
+    pub inbound: usize,
+    pub outbound: usize,
 }
 
 #[inline(always)]
 fn parse_var<T: FromStr>(var: &str, default: T) -> T {
-    std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+    parse_opt_var(var).unwrap_or(default)
+}
+
+#[inline(always)]
+fn parse_opt_var<T: FromStr>(var: &str) -> Option<T> {
+    std::env::var(var).ok().and_then(|v| v.parse().ok())
 }
 
 impl QuicConfig {
@@ -84,11 +148,33 @@ impl QuicConfig {
         let mtu_discovery_max = parse_var("MTU_DISCOVERY_MAX", 1452);
         let keep_alive_interval_ms = parse_var("KEEP_ALIVE_INTERVAL_MS", 5000);
         let max_idle_timeout_ms = parse_var("MAX_IDLE_TIMEOUT_MS", 60000);
+        let max_concurrent_streams = parse_opt_var("MAX_CONCURRENT_STREAMS");
+        let max_stream_data = parse_opt_var("MAX_STREAM_DATA");
+        let max_connection_data = parse_opt_var("MAX_CONNECTION_DATA");
         Self {
             mtu_discovery_max,
             keep_alive_interval_ms,
             max_idle_timeout_ms,
+            max_concurrent_streams,
+            max_stream_data,
+            max_connection_data,
+        }
+    }
+
+    pub fn apply(&self, config: libp2p::quic::Config) -> libp2p::quic::Config {
+        let mut config = config.mtu_upper_bound(self.mtu_discovery_max);
+        config.keep_alive_interval = Duration::from_millis(self.keep_alive_interval_ms as u64);
+        config.max_idle_timeout = self.max_idle_timeout_ms;
+        if let Some(max_concurrent_streams) = self.max_concurrent_streams {
+            config.max_concurrent_stream_limit = max_concurrent_streams;
+        }
+        if let Some(max_stream_data) = self.max_stream_data {
+            config.max_stream_data = max_stream_data;
+        }
+        if let Some(max_connection_data) = self.max_connection_data {
+            config.max_connection_data = max_connection_data;
         }
+        config
     }
 }
 
@@ -102,6 +188,42 @@ impl<T> From<mpsc::error::TrySendError<T>> for QueueFull {
     }
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum DeliveryError {
+    #[error(transparent)]
+    QueueFull(#[from] QueueFull),
+    #[error("Message not delivered")]
+    NotDelivered,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProbeError {
+    #[error("Queue full")]
+    QueueFull,
+    #[error("Transport stopped")]
+    TransportStopped,
+    #[error("Dialing failed: {0}")]
+    Dial(String),
+    #[error("Identify failed: {0}")]
+    Identify(String),
+    #[error("Connection closed before the peer was identified")]
+    ConnectionClosed,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RelayDialError {
+    #[error("Queue full")]
+    QueueFull,
+    #[error("Transport stopped")]
+    TransportStopped,
+    #[error("Invalid relay address: {0}")]
+    InvalidRelayAddr(String),
+    #[error("Relay client transport not enabled")]
+    RelayNotEnabled,
+    #[error("Dialing failed: {0}")]
+    Dial(String),
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Libp2p transport creation failed: {0}")]
@@ -110,6 +232,10 @@ pub enum Error {
     Listen(#[from] TransportError<std::io::Error>),
     #[error("Dialing failed: {0:?}")]
     Dial(#[from] DialError),
+    #[error("No boot nodes configured, the node won't be able to discover peers")]
+    NoDiscoveryConfigured,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(&'static str),
 }
 
 impl From<noise::Error> for Error {
@@ -9,11 +9,16 @@ pub const ID_PROTOCOL: &str = "/subsquid/1.0.0";
 pub const QUERY_PROTOCOL: &str = "/subsquid/query/1.0.0";
 pub const GATEWAY_LOGS_PROTOCOL: &str = "/subsquid/gateway-logs/1.0.0";
 pub const PONG_PROTOCOL: &str = "/subsquid/pong/1.0.0";
+pub const WORKER_LOGS_PROTOCOL: &str = "/subsquid/worker-logs/1.0.0";
 
 pub const MAX_QUERY_SIZE: u64 = 1024 * 1024;
 pub const MAX_QUERY_RESULT_SIZE: u64 = 100 * 1024 * 1024;
+/// Room reserved in an encoded `QueryResult` for fields other than the result data
+pub const QUERY_RESULT_OVERHEAD: u64 = 1024 * 1024;
 pub const MAX_GATEWAY_LOG_SIZE: u64 = 1024 * 1024;
 pub const MAX_PONG_SIZE: u64 = 1024 * 1024;
+pub const MAX_WORKER_LOGS_SIZE: u64 = 1024 * 1024;
+pub const MAX_PENDING_WORKER_LOGS: usize = 10000;
 pub const MAX_PUBSUB_MSG_SIZE: usize = 65536;
 pub const KEEP_LAST_WORKER_LOGS: u64 = 100;
 
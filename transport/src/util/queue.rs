@@ -15,12 +15,21 @@ use crate::QueueFull;
 #[cfg(feature = "metrics")]
 const QUEUE_NAME: &str = "queue_name";
 
-#[derive(Clone)]
 pub struct Sender<T> {
     inner: mpsc::Sender<T>,
     name: &'static str,
 }
 
+// Derived impl would require `T: Clone`
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            name: self.name,
+        }
+    }
+}
+
 impl<T: Debug> Sender<T> {
     pub fn new(inner: mpsc::Sender<T>, name: &'static str) -> Self {
         Self { inner, name }
@@ -62,6 +71,23 @@ impl<T> Receiver<T> {
             msg
         })
     }
+
+    /// Receive up to `max` messages which are already in the queue, without waiting.
+    /// Useful for processing events in batches.
+    pub fn try_recv_many(&mut self, max: usize) -> Vec<T> {
+        let mut msgs = Vec::new();
+        while msgs.len() < max {
+            match self.inner.try_recv() {
+                Ok(msg) => msgs.push(msg),
+                Err(_) => break,
+            }
+        }
+        #[cfg(feature = "metrics")]
+        QUEUE_SIZE
+            .get_or_create(&vec![(QUEUE_NAME, self.name)])
+            .dec_by(msgs.len() as u32);
+        msgs
+    }
 }
 
 impl<T> Stream for Receiver<T> {
@@ -78,3 +104,19 @@ pub fn new_queue<T: Debug>(size: usize, name: &'static str) -> (Sender<T>, Recei
     let rx = Receiver::new(rx, name);
     (tx, rx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_try_recv_many() {
+        let (tx, mut rx) = new_queue(10, "test");
+        assert_eq!(rx.try_recv_many(5), Vec::<u32>::new());
+        for i in 0..3 {
+            tx.try_send(i).unwrap();
+        }
+        assert_eq!(rx.try_recv_many(2), vec![0, 1]);
+        assert_eq!(rx.try_recv_many(5), vec![2]);
+    }
+}